@@ -0,0 +1,49 @@
+//! Insert cost of a full cache under a flood of unique names
+//!
+//! Run with `cargo bench --bench cache_insert`. The cost per insert should
+//! stay flat as the capacity grows.
+
+use hickory_server::proto::rr::rdata::TXT;
+use hickory_server::proto::rr::{Name, RData, Record};
+use llm_over_dns::DnsCache;
+use std::time::{Duration, Instant};
+
+/// Inserts timed per capacity, after the cache is already full
+const INSERTS: usize = 200_000;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("runtime");
+    let record = Record::from_rdata(
+        Name::from_ascii("example.com.").unwrap(),
+        300,
+        RData::TXT(TXT::new(vec!["answer".to_string()])),
+    );
+
+    for capacity in [1_000, 10_000, 100_000] {
+        runtime.block_on(async {
+            let cache = DnsCache::with_capacity(Duration::from_secs(300), capacity);
+            for i in 0..capacity {
+                cache
+                    .insert(&format!("fill-{i}"), vec![record.clone()])
+                    .await;
+            }
+
+            let keys: Vec<String> = (0..INSERTS).map(|i| format!("flood-{i}")).collect();
+            let start = Instant::now();
+            for key in &keys {
+                cache.get(key).await;
+                cache.insert(key, vec![record.clone()]).await;
+            }
+            let elapsed = start.elapsed();
+
+            println!(
+                "capacity {:>7}: {:>6} ns/insert ({} entries)",
+                capacity,
+                elapsed.as_nanos() / INSERTS as u128,
+                cache.len().await
+            );
+        });
+    }
+}
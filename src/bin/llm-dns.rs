@@ -0,0 +1,190 @@
+//! llm-dns - Command-line client for LLM over DNS
+//!
+//! Asks a prompt and prints the whole answer, however many records, pages or
+//! uploads it takes:
+//!
+//! ```text
+//! llm-dns ask --server 127.0.0.1:53 what is rust
+//! echo "a long prompt" | llm-dns ask --zone llm.example.com
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::Read;
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+use llm_over_dns::client::{DnsClient, PromptStyle};
+use llm_over_dns::TxtFraming;
+
+const USAGE: &str = "\
+Usage: llm-dns ask [OPTIONS] [PROMPT]...
+
+Asks PROMPT (or standard input when no prompt is given) and prints the answer.
+
+Options:
+  --server HOST:PORT   DNS server to ask (default: 127.0.0.1:53)
+  --zone ZONE          Zone the server answers for (default: none)
+  --timeout SECONDS    Wait per query, sized for LLM latency (default: 30)
+  --retries N          Retries after a timeout or SERVFAIL (default: 2)
+  --encoding STYLE     auto, plain, b32 or b64 (default: auto)
+  --framing FRAMING    records, sequenced or packed (default: detected)
+  -h, --help           Show this help";
+
+/// Options of the `ask` command
+struct AskArgs {
+    server: String,
+    zone: Option<String>,
+    timeout: Duration,
+    retries: u32,
+    style: PromptStyle,
+    framing: Option<TxtFraming>,
+    prompt: Vec<String>,
+}
+
+/// Parses the arguments after `ask`
+fn parse_ask_args(args: impl IntoIterator<Item = String>) -> Result<AskArgs> {
+    let mut parsed = AskArgs {
+        server: "127.0.0.1:53".to_string(),
+        zone: None,
+        timeout: llm_over_dns::client::DEFAULT_TIMEOUT,
+        retries: llm_over_dns::client::DEFAULT_RETRIES,
+        style: PromptStyle::Auto,
+        framing: None,
+        prompt: Vec::new(),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .ok_or_else(|| anyhow!("Missing value for {}", flag))
+        };
+
+        match arg.as_str() {
+            "--server" => parsed.server = value(&arg)?,
+            "--zone" => parsed.zone = Some(value(&arg)?),
+            "--timeout" => {
+                let seconds: f64 = value(&arg)?.parse().context("Invalid --timeout value")?;
+                parsed.timeout =
+                    Duration::try_from_secs_f64(seconds).context("Invalid --timeout value")?;
+            }
+            "--retries" => {
+                parsed.retries = value(&arg)?.parse().context("Invalid --retries value")?
+            }
+            "--encoding" => parsed.style = value(&arg)?.parse()?,
+            "--framing" => parsed.framing = Some(value(&arg)?.parse()?),
+            "--" => parsed.prompt.extend(args.by_ref()),
+            flag if flag.starts_with("--") => bail!("Unknown option {}\n\n{}", flag, USAGE),
+            _ => parsed.prompt.push(arg),
+        }
+    }
+
+    Ok(parsed)
+}
+
+async fn ask(args: AskArgs) -> Result<()> {
+    let server = tokio::net::lookup_host(&args.server)
+        .await
+        .with_context(|| format!("Cannot resolve server '{}'", args.server))?
+        .next()
+        .ok_or_else(|| anyhow!("Server '{}' has no address", args.server))?;
+
+    let mut client = DnsClient::new(server)
+        .with_timeout(args.timeout)
+        .with_retries(args.retries)
+        .with_style(args.style);
+    if let Some(zone) = &args.zone {
+        client = client.with_zone(zone)?;
+    }
+    if let Some(framing) = args.framing {
+        client = client.with_framing(framing);
+    }
+
+    let prompt = if args.prompt.is_empty() {
+        let mut prompt = String::new();
+        std::io::stdin()
+            .read_to_string(&mut prompt)
+            .context("Failed to read prompt from standard input")?;
+        prompt
+    } else {
+        args.prompt.join(" ")
+    };
+
+    let answer = client.ask(&prompt).await?;
+    println!("{}", answer);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("ask") => {
+            let args: Vec<String> = args.collect();
+            if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            ask(parse_ask_args(args)?).await
+        }
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => bail!("Unknown command '{}'\n\n{}", other, USAGE),
+        None => bail!("Missing command\n\n{}", USAGE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_ask_args() {
+        let parsed = parse_ask_args(args(&[
+            "--server",
+            "10.0.0.1:5353",
+            "--zone",
+            "llm.example.com",
+            "--timeout",
+            "45",
+            "--encoding",
+            "b64",
+            "what",
+            "is",
+            "rust",
+        ]))
+        .unwrap();
+
+        assert_eq!(parsed.server, "10.0.0.1:5353");
+        assert_eq!(parsed.zone.as_deref(), Some("llm.example.com"));
+        assert_eq!(parsed.timeout, Duration::from_secs(45));
+        assert_eq!(parsed.retries, 2);
+        assert!(matches!(parsed.style, PromptStyle::Encoded(_)));
+        assert_eq!(parsed.prompt, args(&["what", "is", "rust"]));
+    }
+
+    #[test]
+    fn test_parse_ask_args_errors() {
+        assert!(parse_ask_args(args(&["--zone"])).is_err());
+        assert!(parse_ask_args(args(&["--retries", "many"])).is_err());
+        assert!(parse_ask_args(args(&["--verbose"])).is_err());
+    }
+
+    #[test]
+    fn test_double_dash_ends_options() {
+        let parsed = parse_ask_args(args(&["--", "--zone", "is", "a", "flag"])).unwrap();
+        assert_eq!(parsed.prompt, args(&["--zone", "is", "a", "flag"]));
+        assert!(parsed.zone.is_none());
+    }
+}
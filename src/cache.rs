@@ -1,21 +1,428 @@
+//! In-memory response cache with serve-stale and prefetch
+//!
+//! Entries are fresh for the cache TTL, or less when a [`TtlPolicy`] rule
+//! says so, and are served with what is left of it as their record TTL, so
+//! resolvers never keep an answer longer than the cache does. With a stale
+//! window set, an expired entry is kept for that much longer, so an answer
+//! can still be served when the LLM is down or too slow (RFC 8767). Stale
+//! answers go out with [`STALE_TTL`], keeping resolvers from holding on to
+//! them.
+//!
+//! Each entry counts its hits. With prefetch enabled, an entry hit often
+//! enough in the last tenth of its TTL is refreshed before it expires, so hot
+//! prompts never miss.
+//!
+//! Answers are keyed on a [`CacheKey`]: the prompt with trivial variations
+//! folded together, under a [`Fingerprint`] of the configuration that
+//! answered it, so changing the model or system prompt stops serving old
+//! answers.
+//! [`SimilarPrompts`] can find the answer to a prompt spelled a little
+//! differently, for queries that miss their own key.
+//!
+//! Failures that asking again will not fix (see [`FailureKind`]) can be
+//! cached too, under the same keys, so a prompt the provider always rejects
+//! costs one LLM call per negative TTL rather than one per query. Transient
+//! failures such as timeouts and rate limits are never cached.
+//!
+//! A bounded cache decides what to keep by recent popularity rather than by
+//! expiry (see [`DnsCache`]), so a flood of one-off prompts cannot push out
+//! the answers everyone keeps asking for.
+
+use hickory_server::proto::op::ResponseCode;
 use hickory_server::proto::rr::Record;
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use hickory_server::proto::serialize::binary::BinEncodable;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::llm_client::LlmError;
+use lru::LruList;
+use sketch::FrequencySketch;
+
+pub use backend::CacheBackend;
+pub use key::{key_parts, normalize_prompt, CacheKey, Fingerprint};
+pub use policy::TtlPolicy;
+pub use resp::RespCache;
+pub use similar::{SimilarPrompt, SimilarPrompts};
+
+mod backend;
+mod key;
+pub(crate) mod lru;
+mod policy;
+pub(crate) mod resp;
+mod similar;
+mod sketch;
+
+/// TTL of records served stale, as recommended by RFC 8767
+pub const STALE_TTL: u32 = 30;
+
+/// An LLM failure that recurs for the same prompt, worth caching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, thiserror::Error)]
+pub enum FailureKind {
+    /// The provider rejected the request (400)
+    #[error("LLM rejected the request")]
+    BadRequest,
+    /// The provider's moderation flagged the prompt (403)
+    #[error("Prompt flagged by moderation")]
+    ContentFiltered,
+    /// No model in the fallback chain is available (404)
+    #[error("No model is available")]
+    ModelNotFound,
+}
+
+impl FailureKind {
+    /// Classifies an error from the LLM call
+    ///
+    /// Returns `None` for transient failures, which are not cached. A
+    /// `FailureKind` itself, as returned for a cached failure, classifies as
+    /// itself.
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        if let Some(kind) = error.downcast_ref::<Self>() {
+            return Some(*kind);
+        }
+        match error.downcast_ref::<LlmError>()? {
+            LlmError::BadRequest(_) => Some(Self::BadRequest),
+            LlmError::ContentFiltered(_) => Some(Self::ContentFiltered),
+            LlmError::ModelNotFound => Some(Self::ModelNotFound),
+            _ => None,
+        }
+    }
+
+    /// The response code a query failing this way is answered with
+    ///
+    /// A moderation flag is a policy refusal; the others are the server's
+    /// failure to answer.
+    pub fn response_code(self) -> ResponseCode {
+        match self {
+            Self::ContentFiltered => ResponseCode::Refused,
+            Self::BadRequest | Self::ModelNotFound => ResponseCode::ServFail,
+        }
+    }
+}
+
+/// How long each kind of failure is cached. A zero TTL leaves that kind
+/// uncached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NegativeTtls {
+    /// TTL for [`FailureKind::BadRequest`]
+    pub bad_request: Duration,
+    /// TTL for [`FailureKind::ContentFiltered`]
+    pub content_filtered: Duration,
+    /// TTL for [`FailureKind::ModelNotFound`]
+    pub model_not_found: Duration,
+}
+
+impl NegativeTtls {
+    fn ttl(&self, kind: FailureKind) -> Duration {
+        match kind {
+            FailureKind::BadRequest => self.bad_request,
+            FailureKind::ContentFiltered => self.content_filtered,
+            FailureKind::ModelNotFound => self.model_not_found,
+        }
+    }
+}
+
+/// Shards a bounded cache is split into at most
+const MAX_SHARDS: usize = 16;
+
+/// Entries a shard should hold before the cache is split further
+const MIN_SHARD_ENTRIES: usize = 256;
+
+/// Hits of a restored entry replayed into the frequency sketch, where
+/// counters saturate anyway
+const MAX_SEEDED_HITS: u8 = 15;
 
 /// Thread-safe in-memory cache for DNS records with TTL support.
+///
+/// Keys are spread over independently locked shards. A bounded shard keeps a
+/// small admission window (1% of its entries) in front of its main space,
+/// both in LRU order (W-TinyLFU): every new answer enters the window, and an
+/// entry pushed out of it only replaces the least recently used main entry if
+/// it has been asked for more often recently, or that entry has expired. A
+/// flood of names asked once each churns through the window without
+/// evicting popular answers, and every operation is O(1).
+///
+/// With a byte budget (see [`DnsCache::with_max_bytes`]) entries are also
+/// weighed by size, and evicted the same way until the shard fits again.
 #[derive(Debug)]
 pub struct DnsCache {
-    entries: RwLock<HashMap<String, CacheEntry>>,
+    shards: Box<[Mutex<Shard>]>,
+    hasher: RandomState,
     ttl: Duration,
-    /// Hard ceiling on retained entries. 0 means unbounded.
-    max_entries: usize,
+    /// How long expired entries are kept to be served stale.
+    stale_window: Duration,
+    /// Hits that make an entry worth prefetching. 0 disables prefetch.
+    prefetch_hits: u64,
+    negative_ttls: NegativeTtls,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
+struct FailureEntry {
+    kind: FailureKind,
+    expires_at: Instant,
+}
+
+/// A cache entry as copied out for a snapshot, keyed on its lowercased key
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExportedEntry {
+    pub key: String,
+    pub records: Vec<Record>,
+    pub expires_at: SystemTime,
+    pub hits: u64,
+}
+
+/// A retained answer as listed for administration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Stored key, lowercased
+    pub key: String,
+    pub hits: u64,
+    /// Lifetime left; zero once the entry is only kept to be served stale
+    pub ttl: Duration,
+    /// Bytes charged against the budget
+    pub bytes: usize,
+}
+
+#[derive(Debug)]
 struct CacheEntry {
     records: Vec<Record>,
     expires_at: Instant,
+    /// Lifetime the entry was cached with
+    ttl: Duration,
+    /// Hash of the key, for the frequency sketch
+    hash: u64,
+    /// Bytes charged against the budget (see [`entry_size`])
+    size: usize,
+    hits: u64,
+    /// Set once a refresh has been handed out, so it is handed out once.
+    prefetching: bool,
+}
+
+/// Size of an entry as charged against the byte budget: its key plus its
+/// records in wire format
+fn entry_size(key: &str, records: &[Record]) -> usize {
+    key.len()
+        + records
+            .iter()
+            .map(|record| record.to_bytes().map_or(0, |bytes| bytes.len()))
+            .sum::<usize>()
+}
+
+impl CacheEntry {
+    fn is_fresh(&self, now: Instant) -> bool {
+        now < self.expires_at
+    }
+
+    fn is_retained(&self, now: Instant, stale_window: Duration) -> bool {
+        now < self.expires_at + stale_window
+    }
+}
+
+/// One independently locked part of the cache
+#[derive(Debug)]
+struct Shard {
+    /// Where new answers land, always admitted
+    window: LruList<CacheEntry>,
+    /// Answers that won admission from the window
+    main: LruList<CacheEntry>,
+    /// `None` when the cache is unbounded
+    sketch: Option<FrequencySketch>,
+    window_capacity: usize,
+    main_capacity: usize,
+    /// Size of the entries in the window and main space
+    bytes: usize,
+    /// Byte budget. 0 means unlimited.
+    max_bytes: usize,
+    failures: LruList<FailureEntry>,
+}
+
+impl Shard {
+    /// A shard of `capacity` entries, or unbounded for `None`
+    fn new(capacity: Option<usize>) -> Self {
+        let window_capacity = capacity.map_or(0, |c| (c / 100).max(1));
+        Self {
+            window: LruList::default(),
+            main: LruList::default(),
+            sketch: capacity.map(FrequencySketch::new),
+            window_capacity,
+            main_capacity: capacity.map_or(0, |c| c.saturating_sub(window_capacity)),
+            bytes: 0,
+            max_bytes: 0,
+            failures: LruList::default(),
+        }
+    }
+
+    fn is_bounded(&self) -> bool {
+        self.sketch.is_some()
+    }
+
+    fn capacity(&self) -> usize {
+        self.window_capacity + self.main_capacity
+    }
+
+    fn len(&self) -> usize {
+        self.window.len() + self.main.len()
+    }
+
+    fn record_access(&mut self, hash: u64) {
+        if let Some(sketch) = &mut self.sketch {
+            sketch.increment(hash);
+        }
+    }
+
+    fn peek(&self, key: &str) -> Option<&CacheEntry> {
+        self.window.peek(key).or_else(|| self.main.peek(key))
+    }
+
+    fn peek_mut(&mut self, key: &str) -> Option<&mut CacheEntry> {
+        match self.window.peek_mut(key) {
+            Some(entry) => Some(entry),
+            None => self.main.peek_mut(key),
+        }
+    }
+
+    /// Looks up an entry and marks it recently used
+    fn touch(&mut self, key: &str) -> Option<&mut CacheEntry> {
+        match self.window.get_mut(key) {
+            Some(entry) => Some(entry),
+            None => self.main.get_mut(key),
+        }
+    }
+
+    fn insert(&mut self, key: String, entry: CacheEntry, now: Instant, stale_window: Duration) {
+        if self.max_bytes > 0 && entry.size > self.max_bytes {
+            // Could never fit; drop the older answer rather than serve it
+            if let Some(old) = self.window.remove(&key).or_else(|| self.main.remove(&key)) {
+                self.bytes -= old.size;
+            }
+            return;
+        }
+
+        self.bytes += entry.size;
+        if let Some(existing) = self.touch(&key) {
+            let old = std::mem::replace(existing, entry);
+            self.bytes -= old.size;
+        } else if !self.is_bounded() {
+            self.main.insert(key, entry);
+        } else {
+            self.window.insert(key, entry);
+            if self.window.len() > self.window_capacity {
+                if let Some((key, candidate)) = self.window.pop_lru() {
+                    self.admit(key, candidate, now, stale_window);
+                }
+            }
+        }
+
+        self.shrink(now);
+    }
+
+    /// Moves an entry pushed out of the window into the main space if it is
+    /// worth more than what it would replace
+    fn admit(&mut self, key: String, candidate: CacheEntry, now: Instant, stale_window: Duration) {
+        if !candidate.is_retained(now, stale_window) {
+            self.bytes -= candidate.size;
+            return;
+        }
+        if self.main.len() < self.main_capacity {
+            self.main.insert(key, candidate);
+            return;
+        }
+
+        let admit = self
+            .main
+            .peek_lru()
+            .is_some_and(|(_, victim)| self.prefers(&candidate, victim, now));
+        if admit {
+            if let Some((_, victim)) = self.main.pop_lru() {
+                self.bytes -= victim.size;
+            }
+            self.main.insert(key, candidate);
+        } else {
+            self.bytes -= candidate.size;
+        }
+    }
+
+    /// Whether `candidate` is worth keeping over `victim`: expired answers
+    /// make way first, then the less asked for
+    fn prefers(&self, candidate: &CacheEntry, victim: &CacheEntry, now: Instant) -> bool {
+        !victim.is_fresh(now)
+            || self.sketch.as_ref().is_some_and(|sketch| {
+                sketch.frequency(candidate.hash) > sketch.frequency(victim.hash)
+            })
+    }
+
+    /// Evicts until the entries fit the byte budget, weighing the oldest
+    /// window entry against the main victim as admission does. The newest
+    /// entry is always admitted, so it goes last.
+    fn shrink(&mut self, now: Instant) {
+        while self.max_bytes > 0 && self.bytes > self.max_bytes {
+            let from_main = match (self.window.peek_lru(), self.main.peek_lru()) {
+                (Some((_, candidate)), Some((_, victim))) if self.window.len() > 1 => {
+                    self.prefers(candidate, victim, now)
+                }
+                (_, Some(_)) => true,
+                (_, None) => false,
+            };
+            let evicted = if from_main {
+                self.main.pop_lru()
+            } else {
+                self.window.pop_lru()
+            };
+            match evicted {
+                Some((_, entry)) => self.bytes -= entry.size,
+                None => break,
+            }
+        }
+    }
+
+    fn insert_failure(&mut self, key: String, failure: FailureEntry) {
+        self.failures.insert(key, failure);
+        if self.is_bounded() && self.failures.len() > self.capacity() {
+            self.failures.pop_lru();
+        }
+    }
+
+    fn retain(&mut self, now: Instant, stale_window: Duration) {
+        let mut freed = 0;
+        let mut keep = |_: &String, e: &CacheEntry| {
+            let retained = e.is_retained(now, stale_window);
+            if !retained {
+                freed += e.size;
+            }
+            retained
+        };
+        self.window.retain(&mut keep);
+        self.main.retain(&mut keep);
+        self.bytes -= freed;
+        self.failures.retain(|_, f| now < f.expires_at);
+    }
+
+    /// Removes the answers and failures whose key `matches`, and returns how
+    /// many answers went
+    fn purge(&mut self, matches: &mut impl FnMut(&str) -> bool) -> usize {
+        let (mut freed, mut purged) = (0, 0);
+        let mut keep = |key: &String, e: &CacheEntry| {
+            let doomed = matches(key);
+            if doomed {
+                freed += e.size;
+                purged += 1;
+            }
+            !doomed
+        };
+        self.window.retain(&mut keep);
+        self.main.retain(&mut keep);
+        self.bytes -= freed;
+        self.failures.retain(|key, _| !matches(key));
+        purged
+    }
+
+    fn clear(&mut self) {
+        self.window.clear();
+        self.main.clear();
+        self.failures.clear();
+        self.bytes = 0;
+    }
 }
 
 impl DnsCache {
@@ -30,66 +437,356 @@ impl DnsCache {
     /// inserts an entry that survives a full TTL, so an unbounded cache lets a
     /// stream of unique names grow memory without limit.
     pub fn with_capacity(ttl: Duration, max_entries: usize) -> Self {
+        let shards = match max_entries {
+            0 => (0..MAX_SHARDS)
+                .map(|_| Mutex::new(Shard::new(None)))
+                .collect(),
+            max => {
+                let count = (max / MIN_SHARD_ENTRIES).clamp(1, MAX_SHARDS);
+                (0..count)
+                    .map(|_| Mutex::new(Shard::new(Some(max / count))))
+                    .collect()
+            }
+        };
+
         Self {
-            entries: RwLock::new(HashMap::new()),
+            shards,
+            hasher: RandomState::new(),
             ttl,
-            max_entries,
+            stale_window: Duration::ZERO,
+            prefetch_hits: 0,
+            negative_ttls: NegativeTtls::default(),
         }
     }
 
+    /// Caps the size of cached records at `max_bytes`, counting each entry's
+    /// key and records in wire format.
+    ///
+    /// The budget is split evenly across shards, and an answer larger than a
+    /// shard's share is not cached. A `max_bytes` of 0 leaves size unbounded.
+    pub fn with_max_bytes(self, max_bytes: usize) -> Self {
+        let per_shard = max_bytes.div_ceil(self.shards.len());
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().max_bytes = per_shard;
+        }
+        self
+    }
+
+    /// Keeps expired entries for `window` so [`DnsCache::get_stale`] can
+    /// serve them when a fresh answer cannot be had.
+    ///
+    /// Stale entries still count against the capacity, and are the first to
+    /// be evicted.
+    pub fn with_serve_stale(mut self, window: Duration) -> Self {
+        self.stale_window = window;
+        self
+    }
+
+    /// Marks entries with at least `min_hits` hits for refresh in the last
+    /// tenth of their TTL (see [`DnsCache::claim_prefetch`]).
+    ///
+    /// A `min_hits` of 0 disables prefetch.
+    pub fn with_prefetch(mut self, min_hits: u64) -> Self {
+        self.prefetch_hits = min_hits;
+        self
+    }
+
+    /// Caches failures for the given TTLs (see [`DnsCache::insert_failure`]).
+    ///
+    /// Without this no failure is cached.
+    pub fn with_negative_ttls(mut self, ttls: NegativeTtls) -> Self {
+        self.negative_ttls = ttls;
+        self
+    }
+
+    /// Lifetime of a new entry, unless [`DnsCache::insert_with_ttl`] shortens
+    /// it.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Lowercases a key and hashes it, for picking a shard and counting
+    /// accesses
+    fn locate(&self, key: &str) -> (String, u64) {
+        let key_lower = key.to_lowercase();
+        let hash = self.hasher.hash_one(&key_lower);
+        (key_lower, hash)
+    }
+
+    fn shard(&self, hash: u64) -> MutexGuard<'_, Shard> {
+        // The sketch indexes with the low bits; pick the shard with the high ones
+        let index = (hash >> 48) as usize % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+
     /// Retrieves cached DNS records for a given key if they exist and are not expired.
+    ///
+    /// The records' TTL is lowered to the whole seconds the entry has left.
+    /// Every lookup, hit or miss, counts towards the key's admission
+    /// frequency.
     pub async fn get(&self, key: &str) -> Option<Vec<Record>> {
-        let key_lower = key.to_lowercase();
-        let entries = self.entries.read().await;
-        if let Some(entry) = entries.get(&key_lower) {
-            if Instant::now() < entry.expires_at {
-                return Some(entry.records.clone());
-            }
+        let (key_lower, hash) = self.locate(key);
+        let mut shard = self.shard(hash);
+        shard.record_access(hash);
+
+        let entry = shard.touch(&key_lower)?;
+        let now = Instant::now();
+        if !entry.is_fresh(now) {
+            return None;
         }
-        None
+        entry.hits += 1;
+
+        let left = u32::try_from((entry.expires_at - now).as_secs()).unwrap_or(u32::MAX);
+        let mut records = entry.records.clone();
+        for record in &mut records {
+            record.ttl = record.ttl.min(left);
+        }
+        Some(records)
+    }
+
+    /// Retrieves cached DNS records even if they expired, as long as they are
+    /// still inside the stale window.
+    ///
+    /// Records are returned as stored; callers serving them stale should
+    /// lower their TTL to [`STALE_TTL`].
+    pub async fn get_stale(&self, key: &str) -> Option<Vec<Record>> {
+        let (key_lower, hash) = self.locate(key);
+        let shard = self.shard(hash);
+        shard
+            .peek(&key_lower)
+            .filter(|entry| entry.is_retained(Instant::now(), self.stale_window))
+            .map(|entry| entry.records.clone())
+    }
+
+    /// Returns true, once per entry, when a fresh entry is hot and close
+    /// enough to expiry to be worth refreshing now.
+    ///
+    /// The caller that gets true is expected to fetch the answer again and
+    /// [`DnsCache::insert`] it, which starts a new entry.
+    pub async fn claim_prefetch(&self, key: &str) -> bool {
+        if self.prefetch_hits == 0 {
+            return false;
+        }
+
+        let (key_lower, hash) = self.locate(key);
+        let mut shard = self.shard(hash);
+        let Some(entry) = shard.peek_mut(&key_lower) else {
+            return false;
+        };
+
+        let now = Instant::now();
+        let claimed = entry.is_fresh(now)
+            && entry.expires_at - now <= entry.ttl / 10
+            && entry.hits >= self.prefetch_hits
+            && !entry.prefetching;
+        entry.prefetching |= claimed;
+        claimed
+    }
+
+    /// Number of hits on a retained entry since it was inserted.
+    pub async fn hits(&self, key: &str) -> u64 {
+        let (key_lower, hash) = self.locate(key);
+        let shard = self.shard(hash);
+        shard.peek(&key_lower).map_or(0, |entry| entry.hits)
     }
 
     /// Caches the given records for the specified key.
+    ///
+    /// In a full cache a new key may push a less popular one out, or be
+    /// turned away itself once it leaves the admission window.
     pub async fn insert(&self, key: &str, records: Vec<Record>) {
-        if self.ttl.is_zero() {
+        self.insert_with_ttl(key, records, self.ttl).await
+    }
+
+    /// Caches the given records for `ttl`, or the cache TTL if that is
+    /// shorter.
+    ///
+    /// A `ttl` of 0 caches nothing.
+    pub async fn insert_with_ttl(&self, key: &str, records: Vec<Record>, ttl: Duration) {
+        let ttl = ttl.min(self.ttl);
+        if ttl.is_zero() {
             return;
         }
-        let key_lower = key.to_lowercase();
-        let expires_at = Instant::now() + self.ttl;
+        let (key_lower, hash) = self.locate(key);
         let entry = CacheEntry {
+            size: entry_size(&key_lower, &records),
             records,
-            expires_at,
+            expires_at: Instant::now() + ttl,
+            ttl,
+            hash,
+            hits: 0,
+            prefetching: false,
         };
-        let mut entries = self.entries.write().await;
-
-        // Make room before growing past the ceiling. The periodic cleanup only
-        // runs every 30s, which is far slower than an attacker can insert.
-        if self.max_entries > 0
-            && entries.len() >= self.max_entries
-            && !entries.contains_key(&key_lower)
-        {
-            let now = Instant::now();
-            entries.retain(|_, e| now < e.expires_at);
-
-            // Still full of live entries: evict whichever expires soonest so the
-            // cache keeps turning over instead of freezing on its first 10k keys.
-            if entries.len() >= self.max_entries {
-                if let Some(soonest) = entries
+        self.shard(hash)
+            .insert(key_lower, entry, Instant::now(), self.stale_window);
+    }
+
+    /// Copies out every retained entry, with its expiry on the wall clock so
+    /// it survives a restart.
+    pub(crate) async fn export(&self) -> Vec<ExportedEntry> {
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        let mut exported = Vec::new();
+
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            exported.extend(
+                shard
+                    .window
                     .iter()
-                    .min_by_key(|(_, e)| e.expires_at)
-                    .map(|(k, _)| k.clone())
-                {
-                    entries.remove(&soonest);
-                }
-            }
+                    .chain(shard.main.iter())
+                    .filter(|(_, e)| e.is_retained(now, self.stale_window))
+                    .map(|(key, e)| ExportedEntry {
+                        key: key.to_string(),
+                        records: e.records.clone(),
+                        expires_at: match e.expires_at.checked_duration_since(now) {
+                            Some(left) => wall + left,
+                            None => wall - now.duration_since(e.expires_at),
+                        },
+                        hits: e.hits,
+                    }),
+            );
+        }
+        exported
+    }
+
+    /// Puts back an exported entry, unless it has outlived its stale window
+    /// or the key already holds a newer answer.
+    ///
+    /// An entry never outlives the current TTL, whatever TTL it was cached
+    /// with, and its hits count towards its admission frequency. Returns true
+    /// if the entry was restored.
+    pub(crate) async fn restore(&self, exported: ExportedEntry) -> bool {
+        if self.ttl.is_zero() {
+            return false;
+        }
+        let now = Instant::now();
+        let expires_at = match exported.expires_at.duration_since(SystemTime::now()) {
+            Ok(left) => now + left.min(self.ttl),
+            Err(e) => match now.checked_sub(e.duration()) {
+                Some(expired) => expired,
+                None => return false,
+            },
+        };
+
+        let (key_lower, hash) = self.locate(&exported.key);
+        let entry = CacheEntry {
+            size: entry_size(&key_lower, &exported.records),
+            records: exported.records,
+            expires_at,
+            ttl: self.ttl,
+            hash,
+            hits: exported.hits,
+            prefetching: false,
+        };
+        let mut shard = self.shard(hash);
+        if !entry.is_retained(now, self.stale_window) || shard.peek(&key_lower).is_some() {
+            return false;
+        }
+
+        for _ in 0..exported.hits.min(u64::from(MAX_SEEDED_HITS)) {
+            shard.record_access(hash);
         }
+        shard.insert(key_lower, entry, now, self.stale_window);
+        true
+    }
 
-        entries.insert(key_lower, entry);
+    /// Remembers that the answer for `key` failed with `kind`, for that kind's
+    /// negative TTL.
+    pub async fn insert_failure(&self, key: &str, kind: FailureKind) {
+        let ttl = self.negative_ttls.ttl(kind);
+        if ttl.is_zero() {
+            return;
+        }
+        let (key_lower, hash) = self.locate(key);
+        self.shard(hash).insert_failure(
+            key_lower,
+            FailureEntry {
+                kind,
+                expires_at: Instant::now() + ttl,
+            },
+        );
+    }
+
+    /// Returns how the answer for `key` failed, if that failure is still
+    /// cached.
+    pub async fn get_failure(&self, key: &str) -> Option<FailureKind> {
+        let (key_lower, hash) = self.locate(key);
+        let shard = self.shard(hash);
+        shard
+            .failures
+            .peek(&key_lower)
+            .filter(|f| Instant::now() < f.expires_at)
+            .map(|f| f.kind)
     }
 
     /// Number of entries currently retained, including any not yet cleaned up.
     pub async fn len(&self) -> usize {
-        self.entries.read().await.len()
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    /// Bytes charged against the budget by the retained entries, whether or
+    /// not a budget is set.
+    pub async fn bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().bytes)
+            .sum()
+    }
+
+    /// Removes the answer stored under `key`, and any failure cached under
+    /// it. Returns true if there was an answer.
+    pub async fn remove(&self, key: &str) -> bool {
+        let (key_lower, hash) = self.locate(key);
+        self.shard(hash).purge(&mut |k| k == key_lower) > 0
+    }
+
+    /// Removes every answer, and every failure, whose stored key `matches`,
+    /// and returns how many answers went.
+    ///
+    /// Keys are passed lowercased; [`key_parts`] reads the page and prompt
+    /// out of them.
+    pub async fn purge(&self, mut matches: impl FnMut(&str) -> bool) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().purge(&mut matches))
+            .sum()
+    }
+
+    /// Removes every page of the answer cached under `key`.
+    pub async fn purge_answer(&self, key: &CacheKey) -> usize {
+        let key = key.as_str().to_lowercase();
+        self.purge(|stored| key::split_page(stored).1 == key).await
+    }
+
+    /// The `limit` most hit retained answers, most hit first.
+    pub async fn top(&self, limit: usize) -> Vec<EntryInfo> {
+        let now = Instant::now();
+        let mut entries: Vec<EntryInfo> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard
+                    .window
+                    .iter()
+                    .chain(shard.main.iter())
+                    .filter(|(_, e)| e.is_retained(now, self.stale_window))
+                    .map(|(key, e)| EntryInfo {
+                        key: key.to_string(),
+                        hits: e.hits,
+                        ttl: e.expires_at.saturating_duration_since(now),
+                        bytes: e.size,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        entries.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.key.cmp(&b.key)));
+        entries.truncate(limit);
+        entries
     }
 
     /// Returns true when no entries are retained.
@@ -97,17 +794,20 @@ impl DnsCache {
         self.len().await == 0
     }
 
-    /// Clears all entries from the cache.
+    /// Clears all entries from the cache, failures included.
     pub async fn clear(&self) {
-        let mut entries = self.entries.write().await;
-        entries.clear();
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().clear();
+        }
     }
 
-    /// Removes expired entries from the cache.
+    /// Removes entries that are past their stale window, or simply expired
+    /// when serve-stale is off.
     pub async fn cleanup(&self) {
-        let mut entries = self.entries.write().await;
         let now = Instant::now();
-        entries.retain(|_, entry| now < entry.expires_at);
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().retain(now, self.stale_window);
+        }
     }
 }
 
@@ -168,6 +868,47 @@ mod tests {
         assert!(cache.get("example.com").await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_hits_carry_the_lifetime_left() {
+        let cache = DnsCache::new(Duration::from_secs(600));
+        cache
+            .insert("example.com", vec![create_test_record("example.com.", "a")])
+            .await;
+        // The records' own TTL is never raised
+        assert_eq!(cache.get("example.com").await.unwrap()[0].ttl, 300);
+
+        let cache = DnsCache::new(Duration::from_secs(60));
+        cache
+            .insert("example.com", vec![create_test_record("example.com.", "a")])
+            .await;
+        let ttl = cache.get("example.com").await.unwrap()[0].ttl;
+        assert!((58..60).contains(&ttl), "ttl {ttl}");
+    }
+
+    #[tokio::test]
+    async fn test_insert_with_ttl_only_shortens() {
+        let cache = DnsCache::new(Duration::from_secs(60));
+        let record = create_test_record("example.com.", "hello");
+
+        cache
+            .insert_with_ttl("short", vec![record.clone()], Duration::from_millis(50))
+            .await;
+        cache
+            .insert_with_ttl("long", vec![record.clone()], Duration::from_secs(3600))
+            .await;
+        cache
+            .insert_with_ttl("never", vec![record], Duration::ZERO)
+            .await;
+
+        assert!(cache.get("short").await.is_some());
+        assert!(cache.get("long").await.unwrap()[0].ttl < 60);
+        assert!(cache.get("never").await.is_none());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(cache.get("short").await.is_none());
+        assert!(cache.get("long").await.is_some());
+    }
+
     #[tokio::test]
     async fn test_cache_zero_ttl() {
         let cache = DnsCache::new(Duration::from_secs(0));
@@ -177,6 +918,67 @@ mod tests {
         assert!(cache.get("example.com").await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_purge_by_answer_and_key() {
+        let cache = DnsCache::new(Duration::from_secs(300)).with_negative_ttls(NegativeTtls {
+            bad_request: Duration::from_secs(60),
+            ..NegativeTtls::default()
+        });
+        let fingerprint = Fingerprint::default();
+        let key = |prompt| CacheKey::prompt(prompt, fingerprint);
+        let record = create_test_record("example.com.", "hello");
+        for prompt in ["what is rust", "what is go", "price of gold today"] {
+            cache
+                .insert(&key(prompt).page(1), vec![record.clone()])
+                .await;
+        }
+        cache
+            .insert(&key("what is rust").page(2), vec![record])
+            .await;
+        cache
+            .insert_failure(key("what is go").as_str(), FailureKind::BadRequest)
+            .await;
+
+        assert_eq!(cache.purge_answer(&key("What is Rust?")).await, 2);
+        let prefix = normalize_prompt("What-is");
+        assert_eq!(
+            cache
+                .purge(|stored| key_parts(stored).1.starts_with(&prefix))
+                .await,
+            1
+        );
+        assert_eq!(cache.get_failure(key("what is go").as_str()).await, None);
+        assert_eq!(
+            cache
+                .purge(|stored| key_parts(stored).1.ends_with("today"))
+                .await,
+            1
+        );
+        assert!(cache.is_empty().await);
+        assert_eq!(cache.bytes().await, 0);
+        assert!(!cache.remove("what is rust").await);
+    }
+
+    #[tokio::test]
+    async fn test_top_lists_most_hit_answers() {
+        let cache = DnsCache::new(Duration::from_secs(300));
+        for (key, hits) in [("cold", 0), ("hot", 3), ("warm", 1)] {
+            cache
+                .insert(key, vec![create_test_record("example.com.", key)])
+                .await;
+            for _ in 0..hits {
+                cache.get(key).await;
+            }
+        }
+
+        let top = cache.top(2).await;
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].key.as_str(), top[0].hits), ("hot", 3));
+        assert_eq!((top[1].key.as_str(), top[1].hits), ("warm", 1));
+        assert!(top[0].ttl <= Duration::from_secs(300) && top[0].ttl > Duration::ZERO);
+        assert!(top[0].bytes > 0);
+    }
+
     #[tokio::test]
     async fn test_cache_clear() {
         let cache = DnsCache::new(Duration::from_secs(10));
@@ -280,4 +1082,332 @@ mod tests {
         }
         assert_eq!(cache.len().await, 100);
     }
+
+    #[tokio::test]
+    async fn test_one_off_keys_do_not_evict_popular_ones() {
+        let cache = DnsCache::with_capacity(Duration::from_secs(300), 100);
+        cache
+            .insert("popular", vec![create_test_record("example.com.", "hot")])
+            .await;
+        for _ in 0..5 {
+            cache.get("popular").await;
+        }
+
+        // Each one-off name misses once, then is cached, while the popular
+        // name keeps being asked for
+        for i in 0..2000 {
+            if i % 50 == 0 {
+                assert!(cache.get("popular").await.is_some(), "evicted at {i}");
+            }
+            let key = format!("one-off-{i}.example.com");
+            cache.get(&key).await;
+            cache
+                .insert(&key, vec![create_test_record("example.com.", "x")])
+                .await;
+        }
+
+        assert!(cache.len().await <= 100);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_cache_is_sharded() {
+        let cache = DnsCache::with_capacity(Duration::from_secs(300), 10_000);
+        assert_eq!(cache.shards.len(), MAX_SHARDS);
+
+        for i in 0..20_000 {
+            cache
+                .insert(
+                    &format!("k{i}"),
+                    vec![create_test_record("example.com.", "x")],
+                )
+                .await;
+        }
+        assert!(cache.len().await <= 10_000);
+        assert_eq!(DnsCache::with_capacity(Duration::ZERO, 8).shards.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_are_tracked() {
+        let cache = DnsCache::new(Duration::from_millis(20));
+        let record = create_test_record("example.com.", "hello");
+        let size = entry_size("example.com", std::slice::from_ref(&record));
+        assert!(size > "example.com".len());
+
+        cache.insert("Example.com", vec![record.clone()]).await;
+        assert_eq!(cache.bytes().await, size);
+
+        // Replacing an answer charges the new one instead of both
+        cache
+            .insert("example.com", vec![record.clone(), record.clone()])
+            .await;
+        assert_eq!(cache.bytes().await, 2 * size - "example.com".len());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cache.cleanup().await;
+        assert_eq!(cache.bytes().await, 0);
+
+        cache.insert("example.com", vec![record]).await;
+        cache.clear().await;
+        assert_eq!(cache.bytes().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_byte_budget_is_enforced() {
+        let record = create_test_record("example.com.", &"x".repeat(200));
+        let size = entry_size("k00", std::slice::from_ref(&record));
+        let cache =
+            DnsCache::with_capacity(Duration::from_secs(300), 100).with_max_bytes(10 * size);
+
+        for i in 0..50 {
+            cache
+                .insert(&format!("k{i:02}"), vec![record.clone()])
+                .await;
+            assert!(cache.bytes().await <= 10 * size);
+        }
+        assert!(cache.len().await <= 10);
+        assert!(cache.get("k49").await.is_some(), "newest answer evicted");
+
+        // An answer larger than the whole budget is not cached at all, and
+        // does not leave the older answer behind
+        let huge = vec![record.clone(); 11];
+        cache.insert("k49", huge).await;
+        assert!(cache.get("k49").await.is_none());
+        assert!(cache.bytes().await <= 10 * size);
+    }
+
+    #[tokio::test]
+    async fn test_stale_entries_are_kept_for_the_window() {
+        let cache =
+            DnsCache::new(Duration::from_millis(20)).with_serve_stale(Duration::from_secs(60));
+        cache
+            .insert(
+                "example.com",
+                vec![create_test_record("example.com.", "old")],
+            )
+            .await;
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cache.cleanup().await;
+
+        assert!(cache.get("example.com").await.is_none());
+        assert!(cache.get_stale("EXAMPLE.com").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stale_entries_are_dropped_after_the_window() {
+        let cache =
+            DnsCache::new(Duration::from_millis(10)).with_serve_stale(Duration::from_millis(10));
+        cache
+            .insert(
+                "example.com",
+                vec![create_test_record("example.com.", "old")],
+            )
+            .await;
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(cache.get_stale("example.com").await.is_none());
+
+        cache.cleanup().await;
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_no_stale_answers_without_a_window() {
+        let cache = DnsCache::new(Duration::from_millis(10));
+        cache
+            .insert(
+                "example.com",
+                vec![create_test_record("example.com.", "old")],
+            )
+            .await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(cache.get_stale("example.com").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stale_entries_are_evicted_before_fresh_ones() {
+        let cache = DnsCache::with_capacity(Duration::from_millis(30), 2)
+            .with_serve_stale(Duration::from_secs(60));
+        cache
+            .insert("stale", vec![create_test_record("example.com.", "1")])
+            .await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+
+        cache
+            .insert("fresh", vec![create_test_record("example.com.", "2")])
+            .await;
+        cache
+            .insert("newer", vec![create_test_record("example.com.", "3")])
+            .await;
+
+        assert!(cache.get_stale("stale").await.is_none());
+        assert!(cache.get("fresh").await.is_some());
+        assert!(cache.get("newer").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_hits_are_counted_per_entry() {
+        let cache = DnsCache::new(Duration::from_secs(10));
+        cache
+            .insert("a", vec![create_test_record("example.com.", "1")])
+            .await;
+        cache
+            .insert("b", vec![create_test_record("example.com.", "2")])
+            .await;
+
+        for _ in 0..3 {
+            cache.get("a").await;
+        }
+        cache.get("B").await;
+
+        assert_eq!(cache.hits("a").await, 3);
+        assert_eq!(cache.hits("b").await, 1);
+        assert_eq!(cache.hits("missing").await, 0);
+
+        // A new answer starts counting again
+        cache
+            .insert("a", vec![create_test_record("example.com.", "3")])
+            .await;
+        assert_eq!(cache.hits("a").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_hot_entries_are_claimed_once_near_expiry() {
+        let cache = DnsCache::new(Duration::from_millis(300)).with_prefetch(2);
+        cache
+            .insert("hot", vec![create_test_record("example.com.", "1")])
+            .await;
+        cache
+            .insert("cold", vec![create_test_record("example.com.", "2")])
+            .await;
+        cache.get("hot").await;
+        cache.get("hot").await;
+        cache.get("cold").await;
+
+        // Hot, but not yet near expiry
+        assert!(!cache.claim_prefetch("hot").await);
+
+        tokio::time::sleep(Duration::from_millis(275)).await;
+        assert!(cache.claim_prefetch("hot").await);
+        assert!(!cache.claim_prefetch("hot").await, "claimed twice");
+        assert!(!cache.claim_prefetch("cold").await);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_disabled_by_default() {
+        let cache = DnsCache::new(Duration::from_millis(10));
+        cache
+            .insert("hot", vec![create_test_record("example.com.", "1")])
+            .await;
+        for _ in 0..10 {
+            cache.get("hot").await;
+        }
+
+        tokio::time::sleep(Duration::from_millis(9)).await;
+        assert!(!cache.claim_prefetch("hot").await);
+    }
+
+    fn negative_ttls() -> NegativeTtls {
+        NegativeTtls {
+            bad_request: Duration::from_secs(60),
+            content_filtered: Duration::from_millis(20),
+            model_not_found: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_cached_per_kind() {
+        let cache = DnsCache::new(Duration::from_secs(300)).with_negative_ttls(negative_ttls());
+
+        cache.insert_failure("bad", FailureKind::BadRequest).await;
+        cache
+            .insert_failure("flagged", FailureKind::ContentFiltered)
+            .await;
+        cache
+            .insert_failure("gone", FailureKind::ModelNotFound)
+            .await;
+
+        assert_eq!(
+            cache.get_failure("BAD").await,
+            Some(FailureKind::BadRequest)
+        );
+        assert_eq!(
+            cache.get_failure("flagged").await,
+            Some(FailureKind::ContentFiltered)
+        );
+        // A zero TTL leaves the kind uncached
+        assert_eq!(cache.get_failure("gone").await, None);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cache.cleanup().await;
+        assert_eq!(cache.get_failure("flagged").await, None);
+        assert_eq!(
+            cache.get_failure("bad").await,
+            Some(FailureKind::BadRequest)
+        );
+
+        // Failures are kept apart from answers
+        assert!(cache.get("bad").await.is_none());
+        cache.clear().await;
+        assert_eq!(cache.get_failure("bad").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_not_cached_by_default() {
+        let cache = DnsCache::new(Duration::from_secs(300));
+        cache.insert_failure("bad", FailureKind::BadRequest).await;
+        assert_eq!(cache.get_failure("bad").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_bounded_by_capacity() {
+        let cache = DnsCache::with_capacity(Duration::from_secs(300), 4)
+            .with_negative_ttls(negative_ttls());
+        for i in 0..50 {
+            cache
+                .insert_failure(&format!("bad-{i}"), FailureKind::BadRequest)
+                .await;
+        }
+        let failures: usize = cache
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().failures.len())
+            .sum();
+        assert!(failures <= 4);
+        assert!(cache.get_failure("bad-49").await.is_some());
+    }
+
+    #[test]
+    fn test_failure_kinds_exclude_transient_errors() {
+        let kind = |e: LlmError| FailureKind::of(&e.into());
+
+        assert_eq!(
+            kind(LlmError::BadRequest("too long".to_string())),
+            Some(FailureKind::BadRequest)
+        );
+        assert_eq!(
+            kind(LlmError::ContentFiltered("flagged".to_string())),
+            Some(FailureKind::ContentFiltered)
+        );
+        assert_eq!(
+            kind(LlmError::ModelNotFound),
+            Some(FailureKind::ModelNotFound)
+        );
+        assert_eq!(kind(LlmError::RateLimited), None);
+        assert_eq!(kind(LlmError::ServerError), None);
+        assert_eq!(kind(LlmError::Unauthorized), None);
+        assert_eq!(
+            FailureKind::of(&anyhow::anyhow!("operation timed out")),
+            None
+        );
+        assert_eq!(
+            FailureKind::of(&FailureKind::ContentFiltered.into()),
+            Some(FailureKind::ContentFiltered)
+        );
+        assert_eq!(
+            FailureKind::ContentFiltered.response_code(),
+            ResponseCode::Refused
+        );
+    }
 }
@@ -0,0 +1,60 @@
+//! Where answers are cached: this process's memory, or a store shared with
+//! other replicas
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_server::proto::rr::Record;
+use std::time::Duration;
+
+use super::{CacheKey, DnsCache};
+
+/// Storage for cached answers, keyed like [`DnsCache`]
+///
+/// [`DnsCache`] keeps answers in memory, with serve-stale, prefetch and
+/// negative caching on top. A shared backend such as
+/// [`RespCache`](super::RespCache) sits behind it, so replicas answer each
+/// other's questions from the cache; it only needs to store and expire
+/// answers.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// Returns the records cached under `key`, if they are still fresh,
+    /// with their TTL lowered to the whole seconds they have left.
+    async fn get(&self, key: &str) -> Option<Vec<Record>>;
+
+    /// Caches `records` under `key` for `ttl`. A `ttl` of 0 caches nothing.
+    async fn insert(&self, key: &str, records: Vec<Record>, ttl: Duration);
+
+    /// Drops every page of the answer cached under `key` (see
+    /// [`CacheKey::page`]), and returns how many went.
+    ///
+    /// Unlike lookups and inserts, a purge waits for a busy backend rather
+    /// than giving up, and is tried even while the backend is failing.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if pages of the answer may be left behind.
+    async fn purge_answer(&self, key: &CacheKey) -> Result<usize>;
+
+    /// Drops expired answers, for backends that do not expire them
+    /// themselves.
+    async fn cleanup(&self);
+}
+
+#[async_trait]
+impl CacheBackend for DnsCache {
+    async fn get(&self, key: &str) -> Option<Vec<Record>> {
+        DnsCache::get(self, key).await
+    }
+
+    async fn insert(&self, key: &str, records: Vec<Record>, ttl: Duration) {
+        DnsCache::insert_with_ttl(self, key, records, ttl).await
+    }
+
+    async fn purge_answer(&self, key: &CacheKey) -> Result<usize> {
+        Ok(DnsCache::purge_answer(self, key).await)
+    }
+
+    async fn cleanup(&self) {
+        DnsCache::cleanup(self).await
+    }
+}
@@ -0,0 +1,245 @@
+//! Cache keys: the prompt as asked, under the configuration that answered it
+
+use std::fmt;
+
+/// Characters dropped from the ends of words, so `rust?` and `rust` match
+const TRIMMED: &[char] = &[
+    '?', '!', '.', ',', ';', ':', '"', '\'', '(', ')', '[', ']', '{', '}', '¿', '¡',
+];
+
+/// Stable 64-bit hash of everything besides the prompt that shapes an answer
+///
+/// Computed with FNV-1a rather than the standard hasher, whose output may
+/// change between Rust releases, so keys in a snapshot still match after an
+/// upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    /// Hashes `parts` in order; each part is length-prefixed so their
+    /// boundaries count
+    pub fn of<I, S>(parts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut hash = Fnv::default();
+        for part in parts {
+            let part = part.as_ref().as_bytes();
+            hash.write(&(part.len() as u64).to_le_bytes());
+            hash.write(part);
+        }
+        Self(hash.0)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// FNV-1a, 64-bit
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// Marks a capitalized character in an exact key (see [`CacheKey::exact`])
+const CAPITAL: char = '^';
+
+/// Folds trivial variations of a prompt together
+///
+/// Lowercases, reads hyphens, underscores and dots as spaces (they stand for
+/// spaces in query names), drops punctuation from the ends of words and
+/// collapses whitespace. Meant for prompts spelled in plain labels, which
+/// resolvers mangle. `What  is Rust?`, `what-is-rust.` and `what is
+/// rust` all normalize to `what is rust`, while `c++` and `c#` stay apart.
+pub fn normalize_prompt(prompt: &str) -> String {
+    prompt
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || matches!(c, '-' | '_' | '.'))
+        .map(|word| word.trim_matches(TRIMMED))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Key of a cached answer
+///
+/// The configuration fingerprint comes first, so changing the model chain,
+/// system prompt or sampling parameters misses every older answer, and a
+/// page key (`p<n> <key>`) cannot collide with any prompt. It is followed by
+/// `:` for a normalized prompt and `=` for an exact one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    /// Key of the answer to `prompt`, normalized with [`normalize_prompt`]
+    pub fn prompt(prompt: &str, fingerprint: Fingerprint) -> Self {
+        Self(format!("{}:{}", fingerprint, normalize_prompt(prompt)))
+    }
+
+    /// Key of the answer to `prompt` as spelled, with only its whitespace
+    /// collapsed
+    ///
+    /// For prompts carried encoded or uploaded, where case and punctuation
+    /// are deliberate: `print(X.y)` and `print x y` get separate answers.
+    /// Caches fold the case of their keys, so each character that has a
+    /// lowercase form is kept as [`CAPITAL`] followed by that form, and
+    /// [`CAPITAL`] itself is doubled.
+    pub fn exact(prompt: &str, fingerprint: Fingerprint) -> Self {
+        let mut key = format!("{}=", fingerprint);
+        for (index, word) in prompt.split_whitespace().enumerate() {
+            if index > 0 {
+                key.push(' ');
+            }
+            for c in word.chars() {
+                if c == CAPITAL || c.to_lowercase().ne([c]) {
+                    key.push(CAPITAL);
+                }
+                key.extend(c.to_lowercase());
+            }
+        }
+        Self(key)
+    }
+
+    /// Key of the answer a stored key (see [`CacheKey::page`]) pages
+    pub(crate) fn of_stored(stored: &str) -> Self {
+        Self(split_page(stored).1.to_string())
+    }
+
+    /// Returns true for a key made by [`CacheKey::exact`]
+    pub fn is_exact(&self) -> bool {
+        self.0
+            .find([':', '='])
+            .is_some_and(|at| self.0[at..].starts_with('='))
+    }
+
+    /// Key of page `page` of the answer; page 1 keeps the plain key
+    pub fn page(&self, page: usize) -> String {
+        match page {
+            1 => self.0.clone(),
+            page => format!("p{} {}", page, self.0),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Splits a stored key (see [`CacheKey::page`]) into its page number and the
+/// prompt it was made from, as keyed
+pub fn key_parts(stored: &str) -> (usize, &str) {
+    let (page, key) = split_page(stored);
+    let prompt = key.split_once([':', '=']).map_or(key, |(_, prompt)| prompt);
+    (page, prompt)
+}
+
+/// Splits a stored key into its page number and the [`CacheKey`] it pages
+pub(crate) fn split_page(stored: &str) -> (usize, &str) {
+    stored
+        .split_once(' ')
+        .and_then(|(label, key)| Some((label.strip_prefix('p')?.parse().ok()?, key)))
+        .unwrap_or((1, stored))
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trivial_variations_share_a_key() {
+        let fingerprint = Fingerprint::of(["model", "system prompt"]);
+        let key = |prompt| CacheKey::prompt(prompt, fingerprint);
+
+        assert_eq!(key("what is rust"), key("what-is-rust."));
+        assert_eq!(key("what is rust"), key("What  is Rust?"));
+        assert_eq!(key("what is rust"), key(" \"what_is_rust\"!"));
+        assert_ne!(key("what is rust"), key("what is rusty"));
+        assert_ne!(key("c++ vs c#"), key("c vs c"));
+        assert_eq!(normalize_prompt("Ça va ?"), "ça va");
+    }
+
+    #[test]
+    fn test_exact_keys_keep_case_and_punctuation() {
+        let fingerprint = Fingerprint::default();
+        let key = |prompt| CacheKey::exact(prompt, fingerprint);
+
+        assert_eq!(key("print(X.y)"), key(" print(X.y)\n"));
+        assert_ne!(key("print(X.y)"), key("print x y"));
+        assert_ne!(key("print(X.y)"), key("print(x.y)"));
+        assert_ne!(key("a_b"), key("a-b"));
+        assert_ne!(key("a.b"), key("A B"));
+        assert_ne!(key("^a"), key("A"));
+        assert_ne!(
+            key("print(x.y)"),
+            CacheKey::prompt("print(x.y)", fingerprint)
+        );
+
+        // Still apart once a cache folds their case
+        let folded = |prompt| key(prompt).page(1).to_lowercase();
+        assert_ne!(folded("Print"), folded("print"));
+        assert_eq!(key_parts(&key("Print x").page(2)), (2, "^print x"));
+    }
+
+    #[test]
+    fn test_configuration_changes_the_key() {
+        let key = |parts: &[&str]| CacheKey::prompt("what is rust", Fingerprint::of(parts));
+
+        assert_eq!(key(&["a", "b"]), key(&["a", "b"]));
+        assert_ne!(key(&["a", "b"]), key(&["b", "a"]));
+        assert_ne!(key(&["ab", ""]), key(&["a", "b"]));
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable() {
+        // Snapshot keys depend on this value across builds
+        assert_eq!(Fingerprint::of([""; 0]).to_string(), "cbf29ce484222325");
+        assert_eq!(
+            Fingerprint::of(["model"]),
+            Fingerprint::of(vec!["model".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_page_keys_do_not_collide_with_prompts() {
+        let fingerprint = Fingerprint::default();
+        let key = CacheKey::prompt("what is rust", fingerprint);
+
+        assert_eq!(key.page(1), key.as_str());
+        assert_ne!(
+            key.page(2),
+            CacheKey::prompt("p2 what is rust", fingerprint).page(1)
+        );
+    }
+
+    #[test]
+    fn test_key_parts() {
+        let fingerprint = Fingerprint::default();
+        let key = CacheKey::prompt("What is Rust?", fingerprint);
+
+        assert_eq!(key_parts(&key.page(1)), (1, "what is rust"));
+        assert_eq!(key_parts(&key.page(3)), (3, "what is rust"));
+        assert_eq!(key_parts("legacy key"), (1, "legacy key"));
+    }
+}
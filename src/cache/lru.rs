@@ -0,0 +1,253 @@
+//! Doubly linked LRU list over a slab, with a key index for O(1) access
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Marks the end of the list
+const NIL: usize = usize::MAX;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    prev: usize,
+    next: usize,
+}
+
+/// Keys in recency order; every operation except [`LruList::retain`] and
+/// [`LruList::iter`] is O(1)
+#[derive(Debug)]
+pub(crate) struct LruList<V, K = String> {
+    index: HashMap<K, usize>,
+    slots: Vec<Option<Node<K, V>>>,
+    free: Vec<usize>,
+    /// Most recently used
+    head: usize,
+    /// Least recently used
+    tail: usize,
+}
+
+impl<V, K> Default for LruList<V, K> {
+    fn default() -> Self {
+        Self {
+            index: HashMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+}
+
+impl<V, K: Hash + Eq + Clone> LruList<V, K> {
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Looks up a value without touching its recency
+    pub fn peek<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.index.get(key).map(|&slot| &self.node(slot).value)
+    }
+
+    /// Looks up a value for update without touching its recency
+    pub fn peek_mut<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let slot = *self.index.get(key)?;
+        Some(&mut self.node_mut(slot).value)
+    }
+
+    /// Looks up a value and marks it most recently used
+    pub fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let slot = *self.index.get(key)?;
+        self.unlink(slot);
+        self.link_front(slot);
+        Some(&mut self.node_mut(slot).value)
+    }
+
+    /// Inserts or replaces a value as the most recently used
+    pub fn insert(&mut self, key: K, value: V) {
+        if let Some(existing) = self.get_mut(&key) {
+            *existing = value;
+            return;
+        }
+
+        let node = Node {
+            key: key.clone(),
+            value,
+            prev: NIL,
+            next: NIL,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(node);
+                slot
+            }
+            None => {
+                self.slots.push(Some(node));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(key, slot);
+        self.link_front(slot);
+    }
+
+    /// The least recently used entry
+    pub fn peek_lru(&self) -> Option<(&K, &V)> {
+        (self.tail != NIL).then(|| {
+            let node = self.node(self.tail);
+            (&node.key, &node.value)
+        })
+    }
+
+    /// Removes and returns the least recently used entry
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        (self.tail != NIL).then(|| self.take(self.tail))
+    }
+
+    /// Removes and returns the value for `key`
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let slot = *self.index.get(key)?;
+        Some(self.take(slot).1)
+    }
+
+    /// Keeps only the entries `keep` accepts
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let doomed: Vec<usize> = self
+            .index
+            .iter()
+            .filter(|&(key, &slot)| !keep(key, &self.node(slot).value))
+            .map(|(_, &slot)| slot)
+            .collect();
+        for slot in doomed {
+            self.take(slot);
+        }
+    }
+
+    /// Every entry, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|node| (&node.key, &node.value))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn node(&self, slot: usize) -> &Node<K, V> {
+        self.slots[slot].as_ref().expect("indexed slot is occupied")
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut Node<K, V> {
+        self.slots[slot].as_mut().expect("indexed slot is occupied")
+    }
+
+    fn take(&mut self, slot: usize) -> (K, V) {
+        self.unlink(slot);
+        let node = self.slots[slot].take().expect("indexed slot is occupied");
+        self.index.remove(&node.key);
+        self.free.push(slot);
+        (node.key, node.value)
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = {
+            let node = self.node(slot);
+            (node.prev, node.next)
+        };
+        match prev {
+            NIL => self.head = next,
+            prev => self.node_mut(prev).next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.node_mut(next).prev = prev,
+        }
+    }
+
+    fn link_front(&mut self, slot: usize) {
+        let head = self.head;
+        {
+            let node = self.node_mut(slot);
+            node.prev = NIL;
+            node.next = head;
+        }
+        match head {
+            NIL => self.tail = slot,
+            head => self.node_mut(head).prev = slot,
+        }
+        self.head = slot;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys_lru_first(list: &mut LruList<u32>) -> Vec<String> {
+        let mut keys = Vec::new();
+        while let Some((key, _)) = list.pop_lru() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn test_recency_order() {
+        let mut list = LruList::default();
+        list.insert("a".to_string(), 1);
+        list.insert("b".to_string(), 2);
+        list.insert("c".to_string(), 3);
+
+        // Reading through get_mut and replacing both count as a use
+        list.get_mut("a");
+        list.insert("b".to_string(), 20);
+        // Peeking does not
+        assert_eq!(list.peek("c"), Some(&3));
+
+        assert_eq!(list.peek_lru(), Some((&"c".to_string(), &3)));
+        assert_eq!(list.len(), 3);
+        assert_eq!(keys_lru_first(&mut list), ["c", "a", "b"]);
+        assert_eq!(list.len(), 0);
+    }
+
+    #[test]
+    fn test_slots_are_reused() {
+        let mut list = LruList::default();
+        for i in 0..100 {
+            list.insert(format!("k{i}"), i);
+            if list.len() > 4 {
+                list.pop_lru();
+            }
+        }
+        assert_eq!(list.len(), 4);
+        assert!(list.slots.len() <= 5);
+        assert_eq!(keys_lru_first(&mut list), ["k96", "k97", "k98", "k99"]);
+    }
+
+    #[test]
+    fn test_retain_keeps_order() {
+        let mut list = LruList::default();
+        for i in 0..6 {
+            list.insert(format!("k{i}"), i);
+        }
+        list.retain(|_, v| v % 2 == 0);
+
+        assert_eq!(list.peek("k1"), None);
+        assert_eq!(list.iter().count(), 3);
+        assert_eq!(keys_lru_first(&mut list), ["k0", "k2", "k4"]);
+    }
+}
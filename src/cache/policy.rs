@@ -0,0 +1,114 @@
+//! Per-prompt cache lifetimes
+//!
+//! Some questions go stale long before the cache TTL: the weather, a price,
+//! anything about "today". A [`TtlPolicy`] matches prompts against regular
+//! expressions and gives matching answers a shorter lifetime, or none at all,
+//! which also keeps resolvers from caching them.
+
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::time::Duration;
+
+/// One `<regex>=<seconds>` rule
+#[derive(Debug, Clone)]
+struct TtlRule {
+    pattern: Regex,
+    ttl: Duration,
+}
+
+/// Rules shortening the lifetime of answers to matching prompts
+///
+/// Rules are tried in order and the first match wins. Patterns are matched
+/// case-insensitively anywhere in the prompt, so anchor them (`^define `) to
+/// match a whole prompt.
+#[derive(Debug, Clone, Default)]
+pub struct TtlPolicy {
+    rules: Vec<TtlRule>,
+}
+
+impl TtlPolicy {
+    /// Parses `;`-separated `<regex>=<seconds>` rules, such as
+    /// `today|weather|price=0;^news=60`.
+    ///
+    /// The seconds follow the last `=`, so patterns may contain `=` but not
+    /// `;`. Empty rules are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if a rule has no `=<seconds>`, or its pattern is not a
+    /// valid regular expression.
+    pub fn parse(rules: &str) -> Result<Self> {
+        let rules = rules
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                let (pattern, seconds) = rule
+                    .rsplit_once('=')
+                    .with_context(|| format!("TTL rule '{}' has no '=<seconds>'", rule))?;
+                let seconds: u64 = seconds
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid seconds in TTL rule '{}'", rule))?;
+                let pattern = RegexBuilder::new(pattern.trim())
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| format!("Invalid pattern in TTL rule '{}'", rule))?;
+                Ok(TtlRule {
+                    pattern,
+                    ttl: Duration::from_secs(seconds),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Lifetime the first matching rule gives answers to `prompt`, if any
+    /// matches
+    pub fn ttl_for(&self, prompt: &str) -> Option<Duration> {
+        self.rules
+            .iter()
+            .find(|rule| rule.pattern.is_match(prompt))
+            .map(|rule| rule.ttl)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let policy = TtlPolicy::parse("today|weather|price=0; ^define =3600;weather=60").unwrap();
+
+        assert_eq!(
+            policy.ttl_for("What is the Weather in Hanoi"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            policy.ttl_for("define ownership"),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(policy.ttl_for("please define ownership"), None);
+        assert_eq!(policy.ttl_for("what is rust"), None);
+    }
+
+    #[test]
+    fn test_rules_are_validated() {
+        assert!(TtlPolicy::parse("").unwrap().is_empty());
+        assert!(TtlPolicy::parse(" ; ").unwrap().is_empty());
+        assert_eq!(
+            TtlPolicy::parse("a=b=5").unwrap().ttl_for("a=b"),
+            Some(Duration::from_secs(5))
+        );
+
+        assert!(TtlPolicy::parse("weather").is_err());
+        assert!(TtlPolicy::parse("weather=soon").is_err());
+        assert!(TtlPolicy::parse("(weather=0").is_err());
+    }
+}
@@ -0,0 +1,370 @@
+//! Cached prompts found by similarity, for questions asked another way
+//!
+//! Normalization (see [`normalize_prompt`](super::normalize_prompt)) folds
+//! case, spacing and punctuation, but `whats rust` and `what is rust` still
+//! miss each other. [`SimilarPrompts`] indexes the prompts of cached answers
+//! by their character trigrams and finds the most similar one by Jaccard
+//! similarity of those sets, so a question close enough to one already
+//! answered is served that answer. Everything is local; no embedding API is
+//! called.
+//!
+//! Trigrams measure spelling, not meaning: they catch typos, contractions and
+//! a word added or dropped, but `what is trust` is as close to `what is rust`
+//! as `whats rust` is. Keep the threshold high.
+//!
+//! Prompts keyed as spelled (see [`CacheKey::exact`]) are neither indexed
+//! nor looked up: in code, one character apart is another question.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+use super::key::key_parts;
+use super::CacheKey;
+
+/// Most indexed prompts compared with one query
+const MAX_CANDIDATES: usize = 1024;
+
+/// Index of cached prompts by character trigrams
+#[derive(Debug)]
+pub struct SimilarPrompts {
+    threshold: f64,
+    /// Prompts kept. 0 means unbounded.
+    capacity: usize,
+    index: Mutex<Index>,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    prompts: HashMap<u32, Indexed>,
+    /// Id of each indexed key
+    ids: HashMap<String, u32>,
+    /// Ids of the prompts containing each trigram
+    postings: HashMap<u64, HashSet<u32>>,
+    /// Ids in the order they were indexed, oldest first
+    order: VecDeque<u32>,
+    next_id: u32,
+}
+
+#[derive(Debug)]
+struct Indexed {
+    key: CacheKey,
+    trigrams: Vec<u64>,
+}
+
+/// A cached prompt found by [`SimilarPrompts::find`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimilarPrompt {
+    /// Key of the similar prompt's answer
+    pub key: CacheKey,
+    /// Jaccard similarity of the two prompts' trigrams, in `(0, 1]`
+    pub similarity: f64,
+}
+
+impl SimilarPrompts {
+    /// Creates an index matching prompts at least `threshold` similar,
+    /// holding the `capacity` most recently indexed prompts (0 for no
+    /// limit).
+    pub fn new(threshold: f64, capacity: usize) -> Self {
+        Self {
+            threshold,
+            capacity,
+            index: Mutex::new(Index::default()),
+        }
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Indexes the prompt `key` was made from, if it is not already.
+    pub fn insert(&self, key: &CacheKey) {
+        // Prompts keyed as spelled are only matched exactly
+        if key.is_exact() {
+            return;
+        }
+        let mut index = self.index.lock().unwrap();
+        if index.ids.contains_key(key.as_str()) {
+            return;
+        }
+        let trigrams = trigrams(key_parts(key.as_str()).1);
+        if trigrams.is_empty() {
+            return;
+        }
+
+        let id = index.next_id;
+        index.next_id = index.next_id.wrapping_add(1);
+        for &trigram in &trigrams {
+            index.postings.entry(trigram).or_default().insert(id);
+        }
+        index.ids.insert(key.as_str().to_string(), id);
+        index.prompts.insert(
+            id,
+            Indexed {
+                key: key.clone(),
+                trigrams,
+            },
+        );
+        index.order.push_back(id);
+
+        while self.capacity > 0 && index.prompts.len() > self.capacity {
+            let Some(oldest) = index.order.pop_front() else {
+                break;
+            };
+            index.remove_id(oldest);
+        }
+        // Removed ids linger in `order` until they reach the front
+        if index.order.len() > 2 * index.prompts.len() + 64 {
+            let Index { order, prompts, .. } = &mut *index;
+            order.retain(|id| prompts.contains_key(id));
+        }
+    }
+
+    /// Forgets the prompt `key` was made from, once its answer is gone.
+    pub fn remove(&self, key: &CacheKey) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(id) = index.ids.get(key.as_str()).copied() {
+            index.remove_id(id);
+        }
+    }
+
+    /// Forgets every prompt whose normalized text `matches`, returning the
+    /// keys of their answers.
+    pub fn remove_matching(&self, mut matches: impl FnMut(&str) -> bool) -> Vec<CacheKey> {
+        let mut index = self.index.lock().unwrap();
+        let doomed: Vec<u32> = index
+            .prompts
+            .iter()
+            .filter(|(_, indexed)| matches(key_parts(indexed.key.as_str()).1))
+            .map(|(&id, _)| id)
+            .collect();
+        doomed
+            .into_iter()
+            .filter_map(|id| index.remove_id(id))
+            .collect()
+    }
+
+    /// The indexed prompt most similar to the one `key` was made from, if
+    /// any reaches the threshold. `key` itself is never returned, nor a key
+    /// under another configuration fingerprint.
+    pub fn find(&self, key: &CacheKey) -> Option<SimilarPrompt> {
+        if key.is_exact() {
+            return None;
+        }
+        let fingerprint = fingerprint_of(key);
+        let wanted = trigrams(key_parts(key.as_str()).1);
+        if wanted.is_empty() {
+            return None;
+        }
+
+        // A prompt at least `threshold` similar holds that share of the wanted
+        // trigrams, so it holds one of any `probes` of them: only the
+        // postings of the rarest are read, never those of `the` or ` wh`
+        let needed = (self.threshold * wanted.len() as f64 - 1e-9).ceil() as usize;
+        let probes = wanted.len() + 1 - needed.clamp(1, wanted.len());
+
+        let index = self.index.lock().unwrap();
+        let mut postings: Vec<Option<&HashSet<u32>>> =
+            wanted.iter().map(|t| index.postings.get(t)).collect();
+        postings.sort_by_key(|ids| ids.map_or(0, HashSet::len));
+        let candidates: HashSet<u32> = postings
+            .into_iter()
+            .take(probes)
+            .flatten()
+            .flatten()
+            .copied()
+            .take(MAX_CANDIDATES)
+            .collect();
+
+        candidates
+            .into_iter()
+            .filter_map(|id| {
+                let indexed = index.prompts.get(&id)?;
+                let shared = shared_count(&wanted, &indexed.trigrams);
+                let union = wanted.len() + indexed.trigrams.len() - shared;
+                Some((indexed, shared as f64 / union as f64))
+            })
+            .filter(|(indexed, similarity)| {
+                *similarity >= self.threshold
+                    && indexed.key != *key
+                    && fingerprint_of(&indexed.key) == fingerprint
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(indexed, similarity)| SimilarPrompt {
+                key: indexed.key.clone(),
+                similarity,
+            })
+    }
+
+    /// Number of indexed prompts
+    pub fn len(&self) -> usize {
+        self.index.lock().unwrap().prompts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Index {
+    /// Forgets `id`, returning the key it indexed
+    fn remove_id(&mut self, id: u32) -> Option<CacheKey> {
+        let indexed = self.prompts.remove(&id)?;
+        self.ids.remove(indexed.key.as_str());
+        for trigram in &indexed.trigrams {
+            if let Some(ids) = self.postings.get_mut(trigram) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(trigram);
+                }
+            }
+        }
+        // `order` drops the id when it reaches the front
+        if self.order.front() == Some(&id) {
+            self.order.pop_front();
+        }
+        Some(indexed.key)
+    }
+}
+
+/// The fingerprint part of `key`
+fn fingerprint_of(key: &CacheKey) -> &str {
+    key.as_str()
+        .split_once([':', '='])
+        .map_or("", |(fingerprint, _)| fingerprint)
+}
+
+/// Number of values in both sorted slices
+fn shared_count(a: &[u64], b: &[u64]) -> usize {
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    let mut shared = 0;
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        match x.cmp(y) {
+            std::cmp::Ordering::Less => {
+                a.next();
+            }
+            std::cmp::Ordering::Greater => {
+                b.next();
+            }
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                a.next();
+                b.next();
+            }
+        }
+    }
+    shared
+}
+
+/// The distinct trigrams of each word padded with a space on either side,
+/// three characters packed into one value
+fn trigrams(prompt: &str) -> Vec<u64> {
+    let mut trigrams: Vec<u64> = prompt
+        .split_whitespace()
+        .flat_map(|word| {
+            let chars: Vec<u64> = std::iter::once(' ')
+                .chain(word.chars())
+                .chain(std::iter::once(' '))
+                .map(u64::from)
+                .collect();
+            chars
+                .windows(3)
+                .map(|w| w[0] << 42 | w[1] << 21 | w[2])
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Fingerprint;
+
+    fn key(prompt: &str) -> CacheKey {
+        CacheKey::prompt(prompt, Fingerprint::default())
+    }
+
+    #[test]
+    fn test_finds_rewordings_above_the_threshold() {
+        let similar = SimilarPrompts::new(0.7, 0);
+        similar.insert(&key("what is rust"));
+        similar.insert(&key("how do i learn rust"));
+        similar.insert(&key("capital of france"));
+
+        let found = similar.find(&key("how can i learn rust")).unwrap();
+        assert_eq!(found.key, key("how do i learn rust"));
+        assert!(found.similarity >= 0.7 && found.similarity < 1.0);
+        assert_eq!(
+            similar.find(&key("What is Rust lang?")).unwrap().key,
+            key("what is rust")
+        );
+
+        assert_eq!(similar.find(&key("capital of spain")), None);
+        assert_eq!(similar.find(&key("what is rust")), None);
+
+        let other = Fingerprint::of(["another model"]);
+        assert_eq!(
+            similar.find(&CacheKey::prompt("What is Rust lang?", other)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_exact_prompts_are_not_matched_by_similarity() {
+        let similar = SimilarPrompts::new(0.5, 0);
+        let exact = |prompt| CacheKey::exact(prompt, Fingerprint::default());
+        similar.insert(&exact("print(x.y)"));
+        similar.insert(&key("print x y"));
+
+        assert_eq!(similar.len(), 1);
+        assert_eq!(similar.find(&exact("print(x.z)")), None);
+        assert_eq!(
+            similar.find(&key("print x z")).unwrap().key,
+            key("print x y")
+        );
+    }
+
+    #[test]
+    fn test_index_is_bounded_and_forgets_removed_prompts() {
+        let similar = SimilarPrompts::new(0.5, 2);
+        similar.insert(&key("what is rust"));
+        similar.insert(&key("what is rust lang"));
+        similar.insert(&key("what is go"));
+        assert_eq!(similar.len(), 2);
+        assert_eq!(
+            similar.find(&key("what is rust language")).unwrap().key,
+            key("what is rust lang")
+        );
+
+        similar.remove(&key("what is rust lang"));
+        assert_eq!(
+            similar.remove_matching(|prompt| prompt.starts_with("what")),
+            vec![key("what is go")]
+        );
+        assert!(similar.is_empty());
+        assert_eq!(similar.find(&key("what is rust lang")), None);
+
+        // Ids removed out of order leave nothing behind once evicted
+        similar.insert(&key("one two"));
+        similar.insert(&key("three four"));
+        similar.insert(&key("five six"));
+        assert_eq!(similar.len(), 2);
+    }
+
+    #[test]
+    fn test_common_trigrams_do_not_hide_a_match() {
+        let similar = SimilarPrompts::new(0.7, 0);
+        for i in 0..5000 {
+            similar.insert(&key(&format!("what is the number {}", i)));
+        }
+        similar.insert(&key("what is the rust language"));
+
+        assert_eq!(
+            similar.find(&key("what is the rust lang")).unwrap().key,
+            key("what is the rust language")
+        );
+        assert_eq!(similar.find(&key("what is the weather")), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Count-min sketch of recent access frequency, for TinyLFU admission
+//!
+//! Four rows of saturating 4-bit counters (stored a byte each) estimate how
+//! often a key was seen. Once the sketch has counted ten times as many
+//! accesses as the cache holds entries, every counter is halved, so the
+//! estimate tracks recent popularity rather than all-time totals.
+
+/// Rows of the sketch; each key bumps one counter per row
+const DEPTH: usize = 4;
+
+/// Counters saturate here, as 4-bit counters would
+const MAX_COUNT: u8 = 15;
+
+#[derive(Debug)]
+pub(super) struct FrequencySketch {
+    counters: Vec<u8>,
+    /// Counters per row minus one; rows are a power of two wide
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    /// Creates a sketch sized for a cache of `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        let width = capacity.max(16).next_power_of_two();
+        Self {
+            counters: vec![0; DEPTH * width],
+            mask: width - 1,
+            additions: 0,
+            sample_size: 10 * capacity.max(16),
+        }
+    }
+
+    /// Counts one access to the key with this hash
+    pub fn increment(&mut self, hash: u64) {
+        for row in 0..DEPTH {
+            let slot = self.slot(hash, row);
+            if self.counters[slot] < MAX_COUNT {
+                self.counters[slot] += 1;
+            }
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.age();
+        }
+    }
+
+    /// Estimated recent accesses to the key with this hash
+    pub fn frequency(&self, hash: u64) -> u8 {
+        (0..DEPTH)
+            .map(|row| self.counters[self.slot(hash, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Halves every counter
+    fn age(&mut self) {
+        for counter in &mut self.counters {
+            *counter /= 2;
+        }
+        self.additions /= 2;
+    }
+
+    /// Double hashing: each row offsets the low half of the hash by a
+    /// multiple of the high half.
+    fn slot(&self, hash: u64, row: usize) -> usize {
+        let (low, high) = (hash as u32, (hash >> 32) as u32 | 1);
+        let column = low.wrapping_add(high.wrapping_mul(row as u32)) as usize & self.mask;
+        row * (self.mask + 1) + column
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn test_frequency_counts_and_saturates() {
+        let hasher = RandomState::new();
+        let mut sketch = FrequencySketch::new(1024);
+        let (hot, cold) = (hasher.hash_one("hot"), hasher.hash_one("cold"));
+
+        for _ in 0..5 {
+            sketch.increment(hot);
+        }
+        sketch.increment(cold);
+        assert_eq!(sketch.frequency(hot), 5);
+        assert_eq!(sketch.frequency(cold), 1);
+        assert_eq!(sketch.frequency(hasher.hash_one("unseen")), 0);
+
+        for _ in 0..100 {
+            sketch.increment(hot);
+        }
+        assert_eq!(sketch.frequency(hot), MAX_COUNT);
+    }
+
+    #[test]
+    fn test_counts_age() {
+        let hasher = RandomState::new();
+        let mut sketch = FrequencySketch::new(16);
+        let hot = hasher.hash_one("hot");
+
+        // The sample is ten accesses per entry; the last one halves the counts
+        for _ in 0..159 {
+            sketch.increment(hot);
+        }
+        assert_eq!(sketch.frequency(hot), MAX_COUNT);
+        sketch.increment(hot);
+        assert_eq!(sketch.frequency(hot), MAX_COUNT / 2);
+    }
+}
@@ -1,3 +1,6 @@
+use anyhow::anyhow;
+use std::str::FromStr;
+
 /// DNS TXT record response chunker
 ///
 /// Handles chunking of text responses into DNS TXT record format.
@@ -10,6 +13,8 @@ pub struct Chunker {
     max_chunk_size: usize,
     /// Maximum total response size in bytes (default: 4096 for DNS UDP)
     max_total_size: usize,
+    /// How chunks are laid out in TXT records (default: one record per chunk)
+    framing: TxtFraming,
 }
 
 impl Default for Chunker {
@@ -24,6 +29,69 @@ impl Default for Chunker {
 /// lower bound for [`Chunker::with_sizes`].
 pub const MAX_UTF8_CHAR_LEN: usize = 4;
 
+/// How the chunks of an answer are laid out in TXT records
+///
+/// DNS does not order the records of an RRset, and resolvers shuffle them, so
+/// one unframed record per chunk can come back scrambled. The other framings
+/// let a client put the answer back together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TxtFraming {
+    /// One TXT record per chunk, as is
+    #[default]
+    Records,
+    /// One TXT record per chunk, each prefixed `<index>/<total>|` (`01/05|`)
+    Sequenced,
+    /// A single TXT record holding every chunk as a character-string; the
+    /// strings of one record keep their order
+    Packed,
+}
+
+impl FromStr for TxtFraming {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "records" => Ok(Self::Records),
+            "sequenced" => Ok(Self::Sequenced),
+            "packed" => Ok(Self::Packed),
+            other => Err(anyhow!(
+                "Unknown TXT framing '{}' (expected records, sequenced or packed)",
+                other
+            )),
+        }
+    }
+}
+
+/// Why TXT strings could not be put back together into an answer
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ReassemblyError {
+    /// A string lacks a valid `<index>/<total>|` prefix
+    #[error("Chunk is not framed as <index>/<total>|: {0:?}")]
+    Unframed(String),
+    /// Two chunks claim different totals
+    #[error("Chunks disagree on the total: {0} and {1}")]
+    TotalMismatch(usize, usize),
+    /// The same index arrived twice
+    #[error("Chunk {0} appears more than once")]
+    Duplicate(usize),
+    /// A chunk claims a total larger than the strings received could fill
+    #[error("Chunk total {total} is more than the {received} strings received")]
+    TotalTooLarge {
+        /// Total the chunk claims
+        total: usize,
+        /// Strings received, page markers included
+        received: usize,
+    },
+    /// An index between 1 and the total never arrived
+    #[error("Chunk {index} of {total} is missing")]
+    Missing {
+        /// Missing index, counting from 1
+        index: usize,
+        /// Chunks the answer has
+        total: usize,
+    },
+}
+
 impl Chunker {
     /// Create a new chunker with default settings
     ///
@@ -34,6 +102,7 @@ impl Chunker {
         Self {
             max_chunk_size: 250,
             max_total_size: 4096,
+            framing: TxtFraming::Records,
         }
     }
 
@@ -47,9 +116,24 @@ impl Chunker {
         Self {
             max_chunk_size: max_chunk_size.max(MAX_UTF8_CHAR_LEN),
             max_total_size,
+            framing: TxtFraming::Records,
         }
     }
 
+    /// Set how chunks are laid out in TXT records
+    ///
+    /// [`TxtFraming::Sequenced`] prefixes are counted against the chunk size,
+    /// so framed chunks still fit a TXT character-string.
+    pub fn with_framing(mut self, framing: TxtFraming) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// The configured TXT framing
+    pub fn framing(&self) -> TxtFraming {
+        self.framing
+    }
+
     /// Chunk text into DNS TXT record compatible strings
     ///
     /// # Arguments
@@ -109,6 +193,184 @@ impl Chunker {
         chunks
     }
 
+    /// Split text into pages of at most max_total_size bytes, each chunked
+    ///
+    /// Unlike [`Chunker::chunk_text`], nothing is truncated: later pages carry
+    /// whatever the first could not. Chunks are framed as configured, so with
+    /// the default framing the first page is exactly what `chunk_text` returns.
+    ///
+    /// # Arguments
+    /// * `text` - The text to paginate
+    ///
+    /// # Returns
+    /// One vector of chunks per page. Empty input returns no pages.
+    pub fn paginate(&self, text: &str) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut remaining = text;
+
+        while !remaining.is_empty() {
+            let split_point = Self::find_char_boundary(remaining, self.max_total_size);
+
+            // A page budget narrower than the next character cannot make
+            // progress; stop where `chunk_text` would have truncated.
+            if split_point == 0 {
+                break;
+            }
+
+            let (page, rest) = remaining.split_at(split_point);
+            pages.push(self.frame_page(page));
+            remaining = rest;
+        }
+
+        pages
+    }
+
+    /// Group a page's chunks into TXT records according to the framing
+    ///
+    /// Each inner vector holds the character-strings of one TXT record.
+    pub fn txt_records(&self, chunks: Vec<String>) -> Vec<Vec<String>> {
+        match self.framing {
+            TxtFraming::Packed if chunks.is_empty() => Vec::new(),
+            TxtFraming::Packed => vec![chunks],
+            TxtFraming::Records | TxtFraming::Sequenced => {
+                chunks.into_iter().map(|chunk| vec![chunk]).collect()
+            }
+        }
+    }
+
+    /// Text of the record that closes each page of a multi-page answer
+    pub fn page_marker(page: usize, total: usize) -> String {
+        format!("page {}/{}", page, total)
+    }
+
+    /// Read a `page <n>/<total>` marker, returning the page and the total
+    pub fn parse_page_marker(text: &str) -> Option<(usize, usize)> {
+        let (page, total) = text.strip_prefix("page ")?.split_once('/')?;
+        Some((page.parse().ok()?, total.parse().ok()?))
+    }
+
+    /// Guess the framing of received TXT strings
+    ///
+    /// Strings that all carry sequence prefixes are
+    /// [`TxtFraming::Sequenced`]; anything else is read in the order received,
+    /// which is also how a packed record is read.
+    pub fn detect_framing<S: AsRef<str>>(strings: &[S]) -> TxtFraming {
+        let mut chunks = strings
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|s| !is_page_marker(s))
+            .peekable();
+
+        if chunks.peek().is_some() && chunks.all(|chunk| parse_frame(chunk).is_some()) {
+            TxtFraming::Sequenced
+        } else {
+            TxtFraming::Records
+        }
+    }
+
+    /// Reassemble an answer from the TXT strings of one response
+    ///
+    /// Takes every character-string in the order received, across records.
+    /// Page markers are skipped. With [`TxtFraming::Sequenced`] the chunks are
+    /// put back in order, and each index from 1 to the total must appear
+    /// exactly once; the other framings keep the order received.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use llm_over_dns::chunker::{Chunker, TxtFraming};
+    ///
+    /// let chunker = Chunker::new().with_framing(TxtFraming::Sequenced);
+    /// let received = ["02/02|world", "01/02|hello ", "page 1/1"];
+    /// assert_eq!(chunker.reassemble(&received).unwrap(), "hello world");
+    ///
+    /// assert!(chunker.reassemble(&["02/02|world"]).is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ReassemblyError`] if a sequenced chunk is unframed, missing,
+    /// duplicated, or disagrees with the others on the total, or if the
+    /// total is more than the strings received.
+    pub fn reassemble<S: AsRef<str>>(&self, strings: &[S]) -> Result<String, ReassemblyError> {
+        let chunks = strings
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|s| !is_page_marker(s));
+
+        if self.framing != TxtFraming::Sequenced {
+            return Ok(chunks.collect());
+        }
+
+        let mut total = None;
+        let mut slots: Vec<Option<&str>> = Vec::new();
+        for chunk in chunks {
+            let (index, count, text) =
+                parse_frame(chunk).ok_or_else(|| ReassemblyError::Unframed(chunk.to_string()))?;
+
+            match total {
+                // The total comes from the wire, so it sizes nothing unchecked
+                None if count > strings.len() => {
+                    return Err(ReassemblyError::TotalTooLarge {
+                        total: count,
+                        received: strings.len(),
+                    });
+                }
+                None => {
+                    total = Some(count);
+                    slots = vec![None; count];
+                }
+                Some(total) if total != count => {
+                    return Err(ReassemblyError::TotalMismatch(total, count));
+                }
+                Some(_) => {}
+            }
+
+            let slot = &mut slots[index - 1];
+            if slot.is_some() {
+                return Err(ReassemblyError::Duplicate(index));
+            }
+            *slot = Some(text);
+        }
+
+        let total = slots.len();
+        slots
+            .into_iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                slot.ok_or(ReassemblyError::Missing {
+                    index: i + 1,
+                    total,
+                })
+            })
+            .collect()
+    }
+
+    /// Chunk one page, adding sequence prefixes when framing calls for them
+    fn frame_page(&self, page: &str) -> Vec<String> {
+        if self.framing != TxtFraming::Sequenced {
+            return self.chunk_text(page);
+        }
+
+        // The prefix is paid for out of each chunk, and its width depends on
+        // how many chunks there are, so widen until the count fits.
+        let mut width = 2;
+        loop {
+            let budget = self.max_chunk_size.saturating_sub(2 * width + 2);
+            let chunks = Self::with_sizes(budget, self.max_total_size).chunk_text(page);
+            let total = chunks.len();
+
+            if total.to_string().len() <= width {
+                return chunks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, chunk)| format!("{:0w$}/{:0w$}|{}", i + 1, total, chunk, w = width))
+                    .collect();
+            }
+            width = total.to_string().len();
+        }
+    }
+
     /// Find a valid UTF-8 character boundary at or before the given byte position
     ///
     /// This ensures we don't split multi-byte UTF-8 characters.
@@ -137,6 +399,19 @@ impl Chunker {
     }
 }
 
+/// Parses a `<index>/<total>|<text>` chunk, with 1 <= index <= total.
+fn parse_frame(chunk: &str) -> Option<(usize, usize, &str)> {
+    let (frame, text) = chunk.split_once('|')?;
+    let (index, total) = frame.split_once('/')?;
+    let index: usize = index.parse().ok()?;
+    let total: usize = total.parse().ok()?;
+    (1..=total).contains(&index).then_some((index, total, text))
+}
+
+fn is_page_marker(text: &str) -> bool {
+    Chunker::parse_page_marker(text).is_some()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -414,4 +689,157 @@ mod tests {
         assert_eq!(chunks[0], "🎉");
         assert_eq!(chunks[1], "🌟");
     }
+
+    #[test]
+    fn test_paginate_keeps_whole_text() {
+        let chunker = Chunker::with_sizes(10, 25);
+        let text = "0123456789".repeat(7);
+        let pages = chunker.paginate(&text);
+
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0], chunker.chunk_text(&text));
+        assert_eq!(pages[1], vec!["5678901234", "5678901234", "56789"]);
+        assert_eq!(pages.concat().join(""), text);
+    }
+
+    #[test]
+    fn test_paginate_short_and_empty_text() {
+        let chunker = Chunker::new();
+
+        assert_eq!(chunker.paginate("hello"), vec![vec!["hello".to_string()]]);
+        assert!(chunker.paginate("").is_empty());
+    }
+
+    #[test]
+    fn test_paginate_respects_utf8_boundaries() {
+        let chunker = Chunker::with_sizes(4, 5);
+        // 3-byte characters: a page of 5 bytes only fits one
+        let text = "日本語";
+        let pages = chunker.paginate(text);
+
+        assert_eq!(pages.len(), 3);
+        assert!(pages.iter().all(|page| page.concat().len() == 3));
+        assert_eq!(pages.concat().join(""), text);
+    }
+
+    #[test]
+    fn test_txt_framing_from_str() {
+        assert_eq!(
+            "records".parse::<TxtFraming>().unwrap(),
+            TxtFraming::Records
+        );
+        assert_eq!(
+            " Sequenced ".parse::<TxtFraming>().unwrap(),
+            TxtFraming::Sequenced
+        );
+        assert_eq!("PACKED".parse::<TxtFraming>().unwrap(), TxtFraming::Packed);
+        assert!("json".parse::<TxtFraming>().is_err());
+    }
+
+    #[test]
+    fn test_sequenced_chunks_fit_txt_strings() {
+        let chunker = Chunker::new().with_framing(TxtFraming::Sequenced);
+        let text = "a".repeat(1000);
+        let pages = chunker.paginate(&text);
+
+        let chunks = &pages[0];
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks[0].starts_with("01/05|"));
+        assert!(chunks[4].starts_with("05/05|"));
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 250));
+        assert_eq!(chunker.reassemble(chunks).unwrap(), text);
+    }
+
+    #[test]
+    fn test_sequenced_prefix_widens_past_99_chunks() {
+        let chunker = Chunker::with_sizes(20, 10_000).with_framing(TxtFraming::Sequenced);
+        let text = "b".repeat(1500);
+        let chunks = &chunker.paginate(&text)[0];
+
+        assert!(chunks.len() > 99);
+        assert!(chunks[0].starts_with("001/"));
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 20));
+        assert_eq!(chunker.reassemble(chunks).unwrap(), text);
+    }
+
+    #[test]
+    fn test_reassemble_restores_shuffled_order() {
+        let chunker = Chunker::with_sizes(10, 4096).with_framing(TxtFraming::Sequenced);
+        let text = "The quick brown fox jumps over the lazy dog";
+        let mut chunks = chunker.paginate(text).remove(0);
+        chunks.reverse();
+        chunks.push(Chunker::page_marker(1, 2));
+
+        assert_eq!(chunker.reassemble(&chunks).unwrap(), text);
+    }
+
+    #[test]
+    fn test_reassemble_validates_completeness() {
+        let chunker = Chunker::new().with_framing(TxtFraming::Sequenced);
+
+        assert_eq!(
+            chunker.reassemble(&["1/3|a", "3/3|c", "page 1/2"]),
+            Err(ReassemblyError::Missing { index: 2, total: 3 })
+        );
+        assert_eq!(
+            chunker.reassemble(&["1/2|a", "1/2|a"]),
+            Err(ReassemblyError::Duplicate(1))
+        );
+        assert_eq!(
+            chunker.reassemble(&["1/2|a", "2/3|b"]),
+            Err(ReassemblyError::TotalMismatch(2, 3))
+        );
+        assert_eq!(
+            chunker.reassemble(&["hello"]),
+            Err(ReassemblyError::Unframed("hello".to_string()))
+        );
+        assert_eq!(
+            chunker.reassemble(&["0/2|a"]),
+            Err(ReassemblyError::Unframed("0/2|a".to_string()))
+        );
+        assert_eq!(
+            chunker.reassemble(&["1/3|a", "3/3|c"]),
+            Err(ReassemblyError::TotalTooLarge {
+                total: 3,
+                received: 2
+            })
+        );
+        assert_eq!(
+            chunker.reassemble(&["1/99999999999999|x"]),
+            Err(ReassemblyError::TotalTooLarge {
+                total: 99999999999999,
+                received: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_detect_framing() {
+        assert_eq!(
+            Chunker::detect_framing(&["2/2|b", "1/2|a", "page 1/1"]),
+            TxtFraming::Sequenced
+        );
+        assert_eq!(
+            Chunker::detect_framing(&["2/2|b", "plain"]),
+            TxtFraming::Records
+        );
+        assert_eq!(Chunker::detect_framing::<&str>(&[]), TxtFraming::Records);
+        assert_eq!(Chunker::parse_page_marker("page 2/5"), Some((2, 5)));
+        assert_eq!(Chunker::parse_page_marker("page two"), None);
+    }
+
+    #[test]
+    fn test_packed_framing_uses_one_record() {
+        let chunker = Chunker::with_sizes(10, 4096).with_framing(TxtFraming::Packed);
+        let text = "a".repeat(25);
+        let chunks = chunker.paginate(&text).remove(0);
+        let records = chunker.txt_records(chunks);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].len(), 3);
+        assert_eq!(chunker.reassemble(&records[0]).unwrap(), text);
+
+        let records = Chunker::new().txt_records(vec!["a".into(), "b".into()]);
+        assert_eq!(records, vec![vec!["a".to_string()], vec!["b".to_string()]]);
+    }
 }
@@ -0,0 +1,627 @@
+//! DNS client for asking the server a prompt
+//!
+//! The client is the other half of [`DnsHandler`] and [`Chunker`]: it builds
+//! query names with [`DnsHandler::encode_prompt`] and puts answers back
+//! together with [`Chunker::reassemble`], so both ends share one definition of
+//! the wire format.
+//!
+//! Asking a prompt takes one or more exchanges:
+//!
+//! 1. The prompt becomes a query name. Prompts that survive as plain labels
+//!    are sent that way, others are base32 encoded, and prompts too long for
+//!    one name are sent as an upload session (see [`crate::upload`]).
+//! 2. The TXT query goes out over UDP. A truncated answer (TC set) is fetched
+//!    again over TCP.
+//! 3. Answers spread over several pages are followed with `p<n>` queries and
+//!    joined.
+//!
+//! LLM calls take seconds, so each exchange waits far longer than a resolver
+//! would, and timeouts, network errors and SERVFAIL are retried.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use llm_over_dns::client::DnsClient;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = DnsClient::new("127.0.0.1:53".parse()?).with_zone("llm.example.com")?;
+//! let answer = client.ask("what is rust").await?;
+//! println!("{}", answer);
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use hickory_server::proto::op::{Edns, Message, MessageType, Query, ResponseCode};
+use hickory_server::proto::rr::{Name, RData, RecordType};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::chunker::{Chunker, TxtFraming};
+use crate::dns_handler::{DnsHandler, PromptEncoding, PromptError};
+use crate::ticket::new_ticket_id;
+use crate::upload::UploadQuery;
+
+/// Default time to wait for each exchange, long enough for an LLM call
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default number of times a failed exchange is retried
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// UDP payload size advertised with EDNS, the same budget the server caps at
+const EDNS_PAYLOAD: u16 = 1232;
+
+/// Largest upload fragment tried before shrinking to fit the name
+const MAX_FRAGMENT_BYTES: usize = 160;
+
+/// How the prompt is written into the query name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PromptStyle {
+    /// Plain labels when the prompt reads back unchanged, base32 otherwise,
+    /// and an upload session when the name would be too long
+    #[default]
+    Auto,
+    /// One label per word
+    Plain,
+    /// Encoded labels behind a marker
+    Encoded(PromptEncoding),
+}
+
+impl FromStr for PromptStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "plain" => Ok(Self::Plain),
+            "b32" | "base32" => Ok(Self::Encoded(PromptEncoding::Base32)),
+            "b64" | "base64url" => Ok(Self::Encoded(PromptEncoding::Base64Url)),
+            other => Err(anyhow!(
+                "Unknown prompt encoding '{}' (expected auto, plain, b32 or b64)",
+                other
+            )),
+        }
+    }
+}
+
+/// The server answered with an error response code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Server answered {response_code}")]
+pub struct QueryFailed {
+    /// Response code of the answer
+    pub response_code: ResponseCode,
+}
+
+/// The queries that ask one prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptQueries {
+    /// A single query name carries the prompt
+    Name(Name),
+    /// Fragments to upload, then the commit name that asks the prompt
+    Upload {
+        /// Fragment names, in sequence order
+        fragments: Vec<Name>,
+        /// The `go.<session>.up` name
+        commit: Name,
+    },
+}
+
+/// Client that asks prompts over DNS
+#[derive(Debug, Clone)]
+pub struct DnsClient {
+    server: SocketAddr,
+    zone: Option<Name>,
+    timeout: Duration,
+    retries: u32,
+    style: PromptStyle,
+    framing: Option<TxtFraming>,
+    handler: DnsHandler,
+}
+
+impl DnsClient {
+    /// Creates a client for the server at `server`
+    ///
+    /// Defaults: no zone, [`DEFAULT_TIMEOUT`] per exchange,
+    /// [`DEFAULT_RETRIES`] retries, [`PromptStyle::Auto`], and TXT framing
+    /// detected from each answer.
+    pub fn new(server: SocketAddr) -> Self {
+        Self {
+            server,
+            zone: None,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            style: PromptStyle::Auto,
+            framing: None,
+            handler: DnsHandler::new(),
+        }
+    }
+
+    /// Sets the zone the server answers for, appended to every query name
+    ///
+    /// # Errors
+    ///
+    /// Returns error if `zone` is not a valid domain name.
+    pub fn with_zone(mut self, zone: &str) -> Result<Self> {
+        let mut zone = Name::from_utf8(zone.trim()).context("Invalid DNS zone")?;
+        zone.set_fqdn(true);
+        self.zone = Some(zone);
+        Ok(self)
+    }
+
+    /// Sets how long to wait for each exchange
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times a failed exchange is retried
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how the prompt is written into the query name
+    pub fn with_style(mut self, style: PromptStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Reads answers with the given framing instead of detecting it
+    pub fn with_framing(mut self, framing: TxtFraming) -> Self {
+        self.framing = Some(framing);
+        self
+    }
+
+    /// Asks a prompt and returns the whole answer
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the prompt cannot be written as a query, the server
+    /// does not answer within the retries, answers with an error code
+    /// ([`QueryFailed`]), or sends chunks that do not reassemble.
+    pub async fn ask(&self, prompt: &str) -> Result<String> {
+        let name = match self.prompt_queries(prompt)? {
+            PromptQueries::Name(name) => name,
+            PromptQueries::Upload { fragments, commit } => {
+                debug!("Uploading prompt in {} fragments", fragments.len());
+                for (seq, fragment) in fragments.iter().enumerate() {
+                    self.query_txt(fragment)
+                        .await
+                        .with_context(|| format!("Failed to upload fragment {}", seq))?;
+                }
+                commit
+            }
+        };
+
+        self.fetch_answer(&name).await
+    }
+
+    /// Plans the queries that ask `prompt`
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the prompt is empty, or does not fit a query name in
+    /// the chosen style.
+    pub fn prompt_queries(&self, prompt: &str) -> Result<PromptQueries> {
+        let zone = self.zone.as_ref();
+        let prompt = prompt.trim();
+        if prompt.is_empty() {
+            return Err(PromptError::Empty.into());
+        }
+
+        let name = match self.style {
+            PromptStyle::Plain => self.handler.encode_prompt(prompt, zone, None)?,
+            PromptStyle::Encoded(encoding) => {
+                self.handler.encode_prompt(prompt, zone, Some(encoding))?
+            }
+            PromptStyle::Auto => {
+                let plain = self
+                    .handler
+                    .encode_prompt(prompt, zone, None)
+                    .ok()
+                    .filter(|name| self.reads_back(name, prompt));
+                let encoded = || {
+                    self.handler
+                        .encode_prompt(prompt, zone, Some(PromptEncoding::Base32))
+                        .ok()
+                };
+                match plain.or_else(encoded) {
+                    Some(name) => name,
+                    None => return self.upload_queries(prompt),
+                }
+            }
+        };
+
+        Ok(PromptQueries::Name(name))
+    }
+
+    /// Whether the server reads a plain-label name back as exactly `prompt`
+    ///
+    /// Hyphens may be turned into spaces, `_` labels are service names, and
+    /// some first or last labels mean an encoding, a page or an upload, so
+    /// prompts that collide with any of these are encoded instead.
+    fn reads_back(&self, name: &Name, prompt: &str) -> bool {
+        let zone = self.zone.as_ref();
+        let words: Vec<&str> = prompt.split_whitespace().collect();
+
+        !prompt.contains('-')
+            && !words.iter().any(|word| word.starts_with('_'))
+            && self.handler.prompt_encoding(name, zone).is_none()
+            && self.handler.page_request(name, zone).is_none()
+            && UploadQuery::parse(name, zone).is_none()
+            && self
+                .handler
+                .decode_prompt(name, zone, false)
+                .is_ok_and(|decoded| decoded == words.join(" "))
+    }
+
+    /// Splits a prompt into upload fragments sized to fit the zone
+    fn upload_queries(&self, prompt: &str) -> Result<PromptQueries> {
+        let zone = self.zone.as_ref();
+        // The answer is keyed on the prompt, but a guessed id would still
+        // let another client ask it or overwrite the upload
+        let session = new_ticket_id();
+
+        let fits = |size: usize| {
+            UploadQuery::Fragment {
+                session: session.clone(),
+                seq: u16::MAX,
+                data: vec![0; size],
+            }
+            .to_name(zone)
+            .is_ok()
+        };
+        let size = (1..=MAX_FRAGMENT_BYTES)
+            .rev()
+            .find(|&size| fits(size))
+            .context("DNS zone leaves no room for upload fragments")?;
+
+        let fragments = prompt
+            .as_bytes()
+            .chunks(size)
+            .enumerate()
+            .map(|(seq, data)| {
+                let seq = u16::try_from(seq).context("Prompt is too long to upload")?;
+                UploadQuery::Fragment {
+                    session: session.clone(),
+                    seq,
+                    data: data.to_vec(),
+                }
+                .to_name(zone)
+            })
+            .collect::<Result<_>>()?;
+        let commit = UploadQuery::Commit { session }.to_name(zone)?;
+
+        Ok(PromptQueries::Upload { fragments, commit })
+    }
+
+    /// Fetches every page of the answer to `name` and joins them
+    ///
+    /// Each page must carry the marker of its place in the same answer, so
+    /// pages of two different answers are never joined.
+    async fn fetch_answer(&self, name: &Name) -> Result<String> {
+        let strings = self.query_txt(name).await?;
+        let mut answer = self.reassemble(&strings)?;
+
+        let total = match page_marker(&strings) {
+            None => 1,
+            Some((1, total)) => total,
+            Some((page, total)) => bail!("First page is marked page {}/{}", page, total),
+        };
+        for page in 2..=total {
+            let page_name = name.prepend_label(format!("p{}", page))?;
+            let strings = self
+                .query_txt(&page_name)
+                .await
+                .with_context(|| format!("Failed to fetch page {} of {}", page, total))?;
+            match page_marker(&strings) {
+                Some(marker) if marker == (page, total) => {}
+                Some((n, m)) => bail!("Page {} of {} is marked page {}/{}", page, total, n, m),
+                None => bail!("Page {} of {} has no page marker", page, total),
+            }
+            answer.push_str(&self.reassemble(&strings)?);
+        }
+
+        Ok(answer)
+    }
+
+    fn reassemble(&self, strings: &[String]) -> Result<String> {
+        let framing = self
+            .framing
+            .unwrap_or_else(|| Chunker::detect_framing(strings));
+        Ok(Chunker::new().with_framing(framing).reassemble(strings)?)
+    }
+
+    /// Sends a TXT query, retrying, and returns the answer's strings in order
+    async fn query_txt(&self, name: &Name) -> Result<Vec<String>> {
+        let response = self.exchange(name).await?;
+        if response.metadata.response_code != ResponseCode::NoError {
+            return Err(QueryFailed {
+                response_code: response.metadata.response_code,
+            }
+            .into());
+        }
+
+        Ok(response
+            .answers
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::TXT(txt) => Some(txt.txt_data.iter()),
+                _ => None,
+            })
+            .flatten()
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect())
+    }
+
+    /// Runs one query to completion, retrying timeouts, I/O errors and
+    /// SERVFAIL
+    async fn exchange(&self, name: &Name) -> Result<Message> {
+        let mut last_error = None;
+
+        for attempt in 0..=self.retries {
+            let query = txt_query(name);
+            match timeout(self.timeout, self.exchange_once(&query)).await {
+                Ok(Ok(response)) if response.metadata.response_code != ResponseCode::ServFail => {
+                    return Ok(response);
+                }
+                Ok(Ok(_)) => {
+                    last_error = Some(anyhow!(QueryFailed {
+                        response_code: ResponseCode::ServFail,
+                    }));
+                }
+                Ok(Err(e)) => last_error = Some(e),
+                Err(_) => {
+                    last_error = Some(anyhow!("Timed out after {:?}", self.timeout));
+                }
+            }
+            debug!(
+                "Attempt {} for {} failed: {:?}",
+                attempt + 1,
+                name,
+                last_error
+            );
+        }
+
+        let error = last_error.unwrap_or_else(|| anyhow!("No attempts made"));
+        Err(error.context(format!(
+            "No answer for {} from {} after {} attempts",
+            name,
+            self.server,
+            self.retries + 1
+        )))
+    }
+
+    /// Sends a query over UDP and falls back to TCP when the answer is
+    /// truncated
+    async fn exchange_once(&self, query: &Message) -> Result<Message> {
+        let response = self.exchange_udp(query).await?;
+        if !response.metadata.truncation {
+            return Ok(response);
+        }
+
+        debug!("Answer truncated over UDP, retrying over TCP");
+        self.exchange_tcp(query).await
+    }
+
+    async fn exchange_udp(&self, query: &Message) -> Result<Message> {
+        let local: SocketAddr = if self.server.is_ipv4() {
+            "0.0.0.0:0".parse()?
+        } else {
+            "[::]:0".parse()?
+        };
+        let socket = UdpSocket::bind(local)
+            .await
+            .context("Failed to bind UDP socket")?;
+        socket.connect(self.server).await?;
+        socket.send(&query.to_vec()?).await?;
+
+        let mut buffer = vec![0u8; usize::from(EDNS_PAYLOAD)];
+        loop {
+            let n = socket.recv(&mut buffer).await?;
+            // Stray or spoofed datagrams do not carry our id; keep waiting.
+            match Message::from_vec(&buffer[..n]) {
+                Ok(response) if is_response_to(&response, query) => return Ok(response),
+                _ => debug!("Ignoring unrelated datagram from {}", self.server),
+            }
+        }
+    }
+
+    async fn exchange_tcp(&self, query: &Message) -> Result<Message> {
+        let mut stream = TcpStream::connect(self.server)
+            .await
+            .context("Failed to connect over TCP")?;
+
+        let bytes = query.to_vec()?;
+        let length = u16::try_from(bytes.len()).context("Query too large for TCP")?;
+        let mut frame = length.to_be_bytes().to_vec();
+        frame.extend_from_slice(&bytes);
+        stream.write_all(&frame).await?;
+
+        let length = stream.read_u16().await? as usize;
+        let mut buffer = vec![0u8; length];
+        stream.read_exact(&mut buffer).await?;
+
+        let response = Message::from_vec(&buffer)?;
+        if !is_response_to(&response, query) {
+            return Err(anyhow!("TCP answer does not match the query"));
+        }
+        Ok(response)
+    }
+}
+
+/// The `page <n>/<total>` marker among a page's strings
+fn page_marker(strings: &[String]) -> Option<(usize, usize)> {
+    strings.iter().find_map(|s| Chunker::parse_page_marker(s))
+}
+
+/// Builds a TXT query with a fresh id, advertising the EDNS payload size
+fn txt_query(name: &Name) -> Message {
+    let mut query = Message::query();
+    query.metadata.recursion_desired = true;
+    query.add_query(Query::query(name.clone(), RecordType::TXT));
+
+    let mut edns = Edns::new();
+    edns.set_max_payload(EDNS_PAYLOAD);
+    query.set_edns(edns);
+    query
+}
+
+fn is_response_to(response: &Message, query: &Message) -> bool {
+    response.metadata.message_type == MessageType::Response
+        && response.metadata.id == query.metadata.id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> DnsClient {
+        DnsClient::new("127.0.0.1:53".parse().unwrap())
+            .with_zone("llm.example.com")
+            .unwrap()
+    }
+
+    fn single_name(queries: PromptQueries) -> String {
+        match queries {
+            PromptQueries::Name(name) => name.to_ascii(),
+            other => panic!("expected a single name, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_prompt_style_from_str() {
+        assert_eq!("auto".parse::<PromptStyle>().unwrap(), PromptStyle::Auto);
+        assert_eq!("PLAIN".parse::<PromptStyle>().unwrap(), PromptStyle::Plain);
+        assert_eq!(
+            "b64".parse::<PromptStyle>().unwrap(),
+            PromptStyle::Encoded(PromptEncoding::Base64Url)
+        );
+        assert!("rot13".parse::<PromptStyle>().is_err());
+    }
+
+    #[test]
+    fn test_auto_style_prefers_plain_labels() {
+        let queries = client().prompt_queries("  what is   rust ").unwrap();
+        assert_eq!(single_name(queries), "what.is.rust.llm.example.com.");
+    }
+
+    #[test]
+    fn test_auto_style_encodes_prompts_that_would_not_read_back() {
+        for prompt in [
+            "is e-mail dead",
+            "b32 is a marker",
+            "p2 is a page",
+            "why _now",
+        ] {
+            let name = single_name(client().prompt_queries(prompt).unwrap());
+            assert!(name.starts_with("b32."), "{} -> {}", prompt, name);
+        }
+    }
+
+    #[test]
+    fn test_auto_style_uploads_long_prompts() {
+        let client = client();
+        let prompt = "word ".repeat(100);
+
+        match client.prompt_queries(&prompt).unwrap() {
+            PromptQueries::Upload { fragments, commit } => {
+                let zone = client.zone.as_ref();
+                let mut uploaded = Vec::new();
+                for (seq, fragment) in fragments.iter().enumerate() {
+                    match UploadQuery::parse(fragment, zone) {
+                        Some(Ok(UploadQuery::Fragment { seq: got, data, .. })) => {
+                            assert_eq!(usize::from(got), seq);
+                            uploaded.extend(data);
+                        }
+                        other => panic!("expected a fragment, got {:?}", other),
+                    }
+                }
+                assert_eq!(uploaded, prompt.trim().as_bytes());
+                assert!(matches!(
+                    UploadQuery::parse(&commit, zone),
+                    Some(Ok(UploadQuery::Commit { .. }))
+                ));
+            }
+            other => panic!("expected an upload, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_explicit_styles() {
+        let plain = client().with_style(PromptStyle::Plain);
+        assert_eq!(
+            single_name(plain.prompt_queries("is e-mail dead").unwrap()),
+            "is.e-mail.dead.llm.example.com."
+        );
+        assert!(plain.prompt_queries(&"word ".repeat(100)).is_err());
+
+        let b64 = client().with_style(PromptStyle::Encoded(PromptEncoding::Base64Url));
+        assert!(single_name(b64.prompt_queries("hi").unwrap()).starts_with("b64."));
+
+        assert!(client().prompt_queries("   ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_silent_server_times_out_after_retries() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = DnsClient::new(silent.local_addr().unwrap())
+            .with_timeout(Duration::from_millis(50))
+            .with_retries(1);
+
+        let error = client.ask("hello").await.unwrap_err();
+        assert!(error.to_string().contains("after 2 attempts"), "{}", error);
+
+        let mut buffer = [0u8; 512];
+        for _ in 0..2 {
+            silent.recv(&mut buffer).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pages_of_another_answer_are_not_joined() {
+        use hickory_server::proto::op::OpCode;
+        use hickory_server::proto::rr::rdata::TXT;
+        use hickory_server::proto::rr::Record;
+
+        // Page 2 comes from an answer with three pages
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            while let Ok((n, peer)) = server.recv_from(&mut buffer).await {
+                let query = Message::from_vec(&buffer[..n]).unwrap();
+                let name = query.queries[0].name().clone();
+                let strings = match name.iter().next() {
+                    Some(b"p2") => ["world", "page 2/3"],
+                    _ => ["hello ", "page 1/2"],
+                };
+                let mut response =
+                    Message::new(query.metadata.id, MessageType::Response, OpCode::Query);
+                response.add_queries(query.queries.clone());
+                response.add_answer(Record::from_rdata(
+                    name,
+                    0,
+                    RData::TXT(TXT::new(strings.map(str::to_string).to_vec())),
+                ));
+                server
+                    .send_to(&response.to_vec().unwrap(), peer)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let client = DnsClient::new(address).with_timeout(Duration::from_secs(5));
+        let error = client.ask("hello").await.unwrap_err();
+        assert!(
+            error.to_string().contains("Page 2 of 2 is marked page 2/3"),
+            "{}",
+            error
+        );
+    }
+}
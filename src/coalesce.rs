@@ -0,0 +1,244 @@
+//! Request coalescing for identical in-flight work
+//!
+//! Several clients, and every resolver retry, tend to ask the same question
+//! within seconds of each other. Without coordination each cache miss pays for
+//! its own LLM call. [`SingleFlight`] lets the first caller for a key do the
+//! work while later callers for the same key wait and receive a clone of its
+//! result, failures included.
+//!
+//! If the caller doing the work is cancelled before it finishes, one of the
+//! waiters takes over, so an abandoned call never strands the others.
+//!
+//! # Examples
+//!
+//! ```
+//! use llm_over_dns::coalesce::SingleFlight;
+//!
+//! # async fn example() {
+//! let flights = SingleFlight::new();
+//!
+//! let (a, b) = tokio::join!(
+//!     flights.run("what is rust", || async { "a language".to_string() }),
+//!     flights.run("what is rust", || async { "never called".to_string() }),
+//! );
+//! assert_eq!(a, b);
+//!
+//! let stats = flights.stats();
+//! assert_eq!(stats.calls, 1);
+//! assert_eq!(stats.coalesced, 1);
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// Counters of work done and work saved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlightStats {
+    /// Calls actually made
+    pub calls: u64,
+    /// Callers served by another caller's call instead of their own
+    pub coalesced: u64,
+}
+
+/// Deduplicates concurrent calls that share a key
+#[derive(Debug)]
+pub struct SingleFlight<T> {
+    flights: Mutex<HashMap<String, watch::Receiver<Option<T>>>>,
+    calls: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SingleFlight<T> {
+    /// Creates an empty set of flights
+    pub fn new() -> Self {
+        Self {
+            flights: Mutex::new(HashMap::new()),
+            calls: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+
+    /// Counters since the flights were created
+    pub fn stats(&self) -> FlightStats {
+        FlightStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Number of keys with a call in progress
+    pub fn in_flight(&self) -> usize {
+        self.flights.lock().unwrap().len()
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    /// Runs `call` for `key`, unless a call for `key` is already running, in
+    /// which case waits for that call and returns a clone of its result
+    pub async fn run<F, Fut>(&self, key: &str, call: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let mut call = Some(call);
+
+        loop {
+            let joined = {
+                let mut flights = self.flights.lock().unwrap();
+                match flights.get(key) {
+                    Some(receiver) => Err(receiver.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        flights.insert(key.to_string(), receiver);
+                        Ok(sender)
+                    }
+                }
+            };
+
+            match joined {
+                Ok(sender) => {
+                    // Leaves the flight even if this future is dropped mid-call
+                    let _landing = Landing {
+                        flights: &self.flights,
+                        key,
+                    };
+                    self.calls.fetch_add(1, Ordering::Relaxed);
+
+                    let call = call.take().expect("a caller leads at most one flight");
+                    let value = call().await;
+                    sender.send_replace(Some(value.clone()));
+                    return value;
+                }
+                Err(mut receiver) => {
+                    if let Ok(value) = receiver.wait_for(Option::is_some).await {
+                        self.coalesced.fetch_add(1, Ordering::Relaxed);
+                        return value.clone().expect("waited for a value");
+                    }
+                    // The leader was dropped without an answer; take over
+                }
+            }
+        }
+    }
+}
+
+/// Removes a flight from the table when its leader finishes or is dropped
+struct Landing<'a, T> {
+    flights: &'a Mutex<HashMap<String, watch::Receiver<Option<T>>>>,
+    key: &'a str,
+}
+
+impl<T> Drop for Landing<'_, T> {
+    fn drop(&mut self) {
+        self.flights.lock().unwrap().remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_result() {
+        let flights = Arc::new(SingleFlight::new());
+        let made = Arc::new(AtomicU64::new(0));
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let flights = flights.clone();
+                let made = made.clone();
+                tokio::spawn(async move {
+                    flights
+                        .run("key", || async move {
+                            made.fetch_add(1, Ordering::Relaxed);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok::<_, String>(42)
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), Ok(42));
+        }
+        assert_eq!(made.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            flights.stats(),
+            FlightStats {
+                calls: 1,
+                coalesced: 9
+            }
+        );
+        assert_eq!(flights.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_shared() {
+        let flights = SingleFlight::new();
+
+        let (a, b) = tokio::join!(
+            flights.run("key", || async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Err::<u32, _>("upstream down".to_string())
+            }),
+            flights.run("key", || async { Ok(1) }),
+        );
+
+        assert_eq!(a, Err("upstream down".to_string()));
+        assert_eq!(b, a);
+    }
+
+    #[tokio::test]
+    async fn test_different_keys_do_not_coalesce() {
+        let flights = SingleFlight::new();
+
+        let (a, b) = tokio::join!(
+            flights.run("a", || async { 1 }),
+            flights.run("b", || async { 2 })
+        );
+
+        assert_eq!((a, b), (1, 2));
+        assert_eq!(flights.stats().calls, 2);
+    }
+
+    #[tokio::test]
+    async fn test_waiter_takes_over_from_a_cancelled_leader() {
+        let flights = Arc::new(SingleFlight::new());
+
+        let leader = {
+            let flights = flights.clone();
+            tokio::spawn(async move {
+                flights
+                    .run("key", || async {
+                        tokio::time::sleep(Duration::from_secs(60)).await;
+                        "leader"
+                    })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let waiter = {
+            let flights = flights.clone();
+            tokio::spawn(async move { flights.run("key", || async { "waiter" }).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        leader.abort();
+
+        assert_eq!(waiter.await.unwrap(), "waiter");
+        assert_eq!(flights.stats().calls, 2);
+        assert_eq!(flights.in_flight(), 0);
+    }
+}
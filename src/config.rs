@@ -13,8 +13,106 @@
 //! - `MAX_CONCURRENT_LLM_REQUESTS` (optional): Global ceiling on in-flight LLM
 //!   calls, defaults to 32. Set to 0 to disable. Queries arriving over the limit
 //!   are shed with SERVFAIL rather than queued.
+//! - `RATE_LIMIT_IPV4_PREFIX` (optional): Prefix length IPv4 clients are
+//!   rate limited by, defaults to 32 (one bucket per address). 24 makes each
+//!   /24 one client.
+//! - `RATE_LIMIT_IPV6_PREFIX` (optional): Prefix length IPv6 clients are
+//!   rate limited by, defaults to 64, since hosts are handed a whole /64.
+//! - `RATE_LIMIT_MAX_CLIENTS` (optional): Ceiling on rate limit buckets
+//!   tracked at once, defaults to 100000. Set to 0 for no limit. A full
+//!   limiter forgets refilled buckets first, then the fullest, but never a
+//!   drained one: new clients are refused until a bucket refills.
 //! - `CACHE_MAX_ENTRIES` (optional): Ceiling on cached responses, defaults to
 //!   10000. Set to 0 for an unbounded cache.
+//! - `CACHE_MAX_BYTES` (optional): Ceiling on the size of cached responses,
+//!   counting each response's records in wire format, defaults to 64 MiB. Set
+//!   to 0 to bound the entry count only.
+//! - `CACHE_TTL_RULES` (optional): `;`-separated `<regex>=<seconds>` rules
+//!   giving answers to matching prompts a shorter lifetime, in the cache and
+//!   in their record TTL, e.g. `today|weather|price=0` to cache none of them.
+//!   The pages of a multi-page answer are still held for at least 30 seconds,
+//!   so a client can read them all from one LLM call. Patterns are
+//!   case-insensitive and the first match wins. Unset by default.
+//! - `CACHE_SIMILARITY_THRESHOLD` (optional): Serves a cached answer to a
+//!   prompt whose character trigrams are at least this similar to a cached
+//!   prompt's, as a Jaccard similarity above 0 and at most 1, so near
+//!   spellings of a question share its answer. Unset by default, which serves
+//!   exact matches only; 0.8 or above is advised, since similar spelling does
+//!   not mean similar meaning. Requires a `CACHE_MAX_ENTRIES` above 0.
+//! - `CACHE_STALE_SEC` (optional): Seconds an expired response is kept to be
+//!   served stale, with a 30 second TTL, when the LLM fails or misses the
+//!   response deadline. Defaults to 0, which disables serve-stale.
+//! - `CACHE_PREFETCH_HITS` (optional): Hits that make a cached response worth
+//!   refreshing in the last tenth of its TTL, before it expires. Defaults to
+//!   0, which disables prefetch.
+//! - `NEGATIVE_TTL_BAD_REQUEST_SEC`, `NEGATIVE_TTL_FILTERED_SEC` and
+//!   `NEGATIVE_TTL_MODEL_SEC` (optional): Seconds an LLM failure that will
+//!   recur is cached, so the prompt is not sent upstream again on every query:
+//!   a rejected request (400, default 60), a moderation flag (403, default
+//!   300) and no available model (404 from every model, default 30). Set to 0
+//!   to not cache that kind. Timeouts, rate limits and server errors are never
+//!   cached.
+//! - `CACHE_SNAPSHOT_PATH` (optional): File the response cache is saved to
+//!   and loaded from on startup, so a restart keeps popular answers. Unset by
+//!   default, which keeps the cache in memory only.
+//! - `CACHE_SNAPSHOT_INTERVAL_SEC` (optional): Seconds between snapshots,
+//!   defaults to 60. A final snapshot is taken on shutdown.
+//! - `CACHE_SNAPSHOT_MAX_BYTES` (optional): Ceiling on the snapshot file size,
+//!   defaults to 16777216 (16 MiB). The most hit answers are kept.
+//! - `CACHE_REDIS_URL` (optional): Redis-protocol store shared by replicas, as
+//!   `redis://[[username]:password@]host[:port][/db]`, so one replica's answer
+//!   serves them all. Unset by default. While the store is unreachable the
+//!   cache is memory only.
+//! - `TCP_IDLE_TIMEOUT_SEC` (optional): Seconds a DNS-over-TCP connection may sit
+//!   without sending a query before it is closed, defaults to 10.
+//! - `TCP_MAX_CONNECTIONS` (optional): Ceiling on simultaneously open TCP
+//!   connections, defaults to 256. Set to 0 to disable. Connections over the
+//!   limit are closed immediately.
+//! - `TLS_CERT_PATH` / `TLS_KEY_PATH` (optional): PEM certificate chain and
+//!   private key. Setting both enables the DNS-over-TLS listener. The files are
+//!   watched and reloaded when they change, so renewals need no restart.
+//! - `DOT_PORT` (optional): DNS-over-TLS port, defaults to 853.
+//! - `DNS_ZONE` (optional): Zone this server is delegated, e.g. `llm.example.com`.
+//!   When set, the zone suffix is stripped from query names before prompting,
+//!   SOA and NS are answered at the apex, negative answers carry the SOA, and
+//!   names outside the zone are REFUSED. Unset by default, which treats the
+//!   whole query name as the prompt.
+//! - `DNS_ZONE_NS` (optional): Comma-separated nameservers published at the
+//!   apex, defaults to `ns1.<zone>`. Should match the parent delegation.
+//! - `DNS_ZONE_HOSTMASTER` (optional): SOA contact mailbox, defaults to
+//!   `hostmaster.<zone>`. `admin@example.com` form is accepted.
+//! - `PROMPT_HYPHENS_AS_SPACES` (optional): Read hyphens in query labels as
+//!   spaces, so `what-is-rust` asks "what is rust". Defaults to `false`.
+//! - `UPLOAD_MAX_SESSIONS` (optional): Ceiling on open multi-query upload
+//!   sessions, defaults to 256. Set to 0 to disable uploads.
+//! - `UPLOAD_MAX_BYTES` (optional): Largest prompt a session may assemble,
+//!   defaults to 16384.
+//! - `UPLOAD_TTL_SEC` (optional): Seconds an upload session lives after its
+//!   last fragment, defaults to 120.
+//! - `TICKET_MAX` (optional): Ceiling on outstanding tickets for the
+//!   asynchronous `ask.<prompt>` / `<ticket>.result` flow, defaults to 0,
+//!   which disables tickets (a leading "ask" is then part of the prompt).
+//! - `TICKET_TTL_SEC` (optional): Seconds a ticket lives after it is issued
+//!   or answered, defaults to 300.
+//! - `RESPONSE_DEADLINE_MS` (optional): How long a DNS query waits for its
+//!   answer before the server replies anyway and finishes the LLM call in the
+//!   background, so the resolver's retry is answered from the cache. Defaults
+//!   to 0, which waits for the LLM.
+//! - `DEADLINE_REPLY` (optional): Reply to a query that misses the deadline:
+//!   `servfail` (the default, which resolvers retry) or `thinking` (a TXT
+//!   record with a TTL of 0).
+//! - `TXT_FRAMING` (optional): How answer chunks are laid out in TXT records:
+//!   `records` (one record per chunk, the default), `sequenced` (each chunk
+//!   prefixed `01/05|` so shuffled records can be reordered) or `packed` (one
+//!   record holding every chunk as an ordered character-string).
+//! - `HTTP_PORT` (optional): Port for the HTTP API: DNS-over-HTTPS (RFC 8484)
+//!   at `/dns-query`, JSON DNS at `/resolve` and `POST /ask`. Unset by
+//!   default, which disables it. Served as HTTPS when a
+//!   TLS certificate is configured, plain HTTP otherwise (for use behind a
+//!   TLS-terminating proxy).
+//! - `ADMIN_TOKEN` (optional): Bearer token for the cache administration
+//!   endpoints under `/admin/cache` on the HTTP API (inspect, purge, export,
+//!   import). Unset by default, which leaves them out.
 //!
 //! # Examples
 //!
@@ -34,6 +132,9 @@
 use anyhow::{Context, Result};
 use std::env;
 
+use crate::chunker::TxtFraming;
+use crate::server::DeadlineReply;
+
 /// Configuration for the LLM over DNS server.
 ///
 /// Contains all necessary configuration for starting the DNS server
@@ -84,6 +185,12 @@ pub struct Config {
     pub rate_limit_rps: f64,
     /// Rate limit burst requests per IP (default: 10.0)
     pub rate_limit_burst: f64,
+    /// Prefix length IPv4 clients are rate limited by (default: 32)
+    pub rate_limit_ipv4_prefix: u8,
+    /// Prefix length IPv6 clients are rate limited by (default: 64)
+    pub rate_limit_ipv6_prefix: u8,
+    /// Rate limit buckets tracked at once (default: 100000, set to 0 to disable)
+    pub rate_limit_max_clients: usize,
     /// Maximum LLM API calls in flight at once (default: 32, set to 0 to disable)
     ///
     /// Per-IP rate limiting cannot bound this: UDP source addresses are
@@ -92,6 +199,73 @@ pub struct Config {
     pub max_concurrent_llm_requests: usize,
     /// Maximum cached responses retained (default: 10000, set to 0 to disable)
     pub cache_max_entries: usize,
+    /// Maximum bytes of cached responses (default: 64 MiB, set to 0 to disable)
+    pub cache_max_bytes: usize,
+    /// Seconds expired responses are kept to be served stale (default: 0, which disables it)
+    pub cache_stale_seconds: u64,
+    /// Hits that make a response worth prefetching (default: 0, which disables it)
+    pub cache_prefetch_hits: u64,
+    /// Seconds a rejected request (400) is cached (default: 60, set to 0 to disable)
+    pub negative_ttl_bad_request_seconds: u64,
+    /// Seconds a moderation flag (403) is cached (default: 300, set to 0 to disable)
+    pub negative_ttl_filtered_seconds: u64,
+    /// Seconds a missing model (404) is cached (default: 30, set to 0 to disable)
+    pub negative_ttl_model_seconds: u64,
+    /// Cache snapshot file (default: unset, which keeps the cache in memory only)
+    pub cache_snapshot_path: Option<String>,
+    /// Seconds between cache snapshots (default: 60)
+    pub cache_snapshot_interval_seconds: u64,
+    /// Maximum cache snapshot size in bytes (default: 16 MiB)
+    pub cache_snapshot_max_bytes: u64,
+    /// Shared cache store (default: unset, which keeps answers to this process)
+    pub cache_redis_url: Option<String>,
+    /// Lifetime rules for time-sensitive prompts (default: unset)
+    pub cache_ttl_rules: Option<String>,
+    /// Similarity at which a cached prompt answers another (default: unset,
+    /// which serves exact matches only)
+    pub cache_similarity_threshold: Option<f64>,
+    /// Idle timeout for DNS-over-TCP connections in seconds (default: 10)
+    pub tcp_idle_timeout_seconds: u64,
+    /// Maximum open DNS-over-TCP connections (default: 256, set to 0 to disable)
+    ///
+    /// Each connection holds a socket and a task for as long as the client keeps
+    /// it open, so without a ceiling a client can exhaust file descriptors.
+    pub tcp_max_connections: usize,
+    /// Path to the PEM certificate chain for TLS listeners (default: unset)
+    pub tls_cert_path: Option<String>,
+    /// Path to the PEM private key for TLS listeners (default: unset)
+    pub tls_key_path: Option<String>,
+    /// DNS-over-TLS listening port, used when a certificate is configured (default: 853)
+    pub dot_port: u16,
+    /// HTTP API (DNS-over-HTTPS, JSON) listening port (default: unset, which disables it)
+    pub http_port: Option<u16>,
+    /// Bearer token for `/admin` on the HTTP API (default: unset, which disables it)
+    pub admin_token: Option<String>,
+    /// Delegated zone origin (default: unset, the whole query name is the prompt)
+    pub dns_zone: Option<String>,
+    /// Nameservers published at the zone apex (default: empty, meaning `ns1.<zone>`)
+    pub dns_zone_nameservers: Vec<String>,
+    /// SOA contact mailbox (default: unset, meaning `hostmaster.<zone>`)
+    pub dns_zone_hostmaster: Option<String>,
+    /// Read hyphens in query labels as spaces (default: false)
+    pub hyphens_as_spaces: bool,
+    /// Maximum open upload sessions (default: 256, set to 0 to disable uploads)
+    pub upload_max_sessions: usize,
+    /// Maximum assembled prompt size per upload session in bytes (default: 16384)
+    pub upload_max_bytes: usize,
+    /// Upload session lifetime after its last fragment in seconds (default: 120)
+    pub upload_ttl_seconds: u64,
+    /// Maximum outstanding tickets (default: 0, which disables tickets)
+    pub ticket_max: usize,
+    /// Ticket lifetime after it is issued or answered in seconds (default: 300)
+    pub ticket_ttl_seconds: u64,
+    /// Time a query waits for the LLM before replying early in milliseconds
+    /// (default: 0, which waits for the LLM)
+    pub response_deadline_ms: u64,
+    /// Reply to a query that misses the response deadline (default: SERVFAIL)
+    pub deadline_reply: DeadlineReply,
+    /// Layout of answer chunks in TXT records (default: one record per chunk)
+    pub txt_framing: TxtFraming,
 }
 
 impl Config {
@@ -243,6 +417,29 @@ impl Config {
             .parse()
             .unwrap_or(10.0);
 
+        // A longer prefix than the address would silently mean "per address"
+        let rate_limit_ipv4_prefix = env::var("RATE_LIMIT_IPV4_PREFIX")
+            .unwrap_or_else(|_| "32".to_string())
+            .parse()
+            .unwrap_or(32);
+        if rate_limit_ipv4_prefix > 32 {
+            return Err(anyhow::anyhow!("RATE_LIMIT_IPV4_PREFIX must be at most 32"));
+        }
+        let rate_limit_ipv6_prefix = env::var("RATE_LIMIT_IPV6_PREFIX")
+            .unwrap_or_else(|_| "64".to_string())
+            .parse()
+            .unwrap_or(64);
+        if rate_limit_ipv6_prefix > 128 {
+            return Err(anyhow::anyhow!(
+                "RATE_LIMIT_IPV6_PREFIX must be at most 128"
+            ));
+        }
+
+        let rate_limit_max_clients = env::var("RATE_LIMIT_MAX_CLIENTS")
+            .unwrap_or_else(|_| "100000".to_string())
+            .parse()
+            .unwrap_or(100_000);
+
         let max_concurrent_llm_requests = env::var("MAX_CONCURRENT_LLM_REQUESTS")
             .unwrap_or_else(|_| "32".to_string())
             .parse()
@@ -253,6 +450,176 @@ impl Config {
             .parse()
             .unwrap_or(10000);
 
+        let cache_max_bytes = env::var("CACHE_MAX_BYTES")
+            .unwrap_or_else(|_| (64 << 20).to_string())
+            .parse()
+            .unwrap_or(64 << 20);
+
+        let cache_stale_seconds = env::var("CACHE_STALE_SEC")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+
+        let cache_prefetch_hits = env::var("CACHE_PREFETCH_HITS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+
+        let negative_ttl_bad_request_seconds = env::var("NEGATIVE_TTL_BAD_REQUEST_SEC")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60);
+
+        let negative_ttl_filtered_seconds = env::var("NEGATIVE_TTL_FILTERED_SEC")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+
+        let negative_ttl_model_seconds = env::var("NEGATIVE_TTL_MODEL_SEC")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .unwrap_or(30);
+
+        let cache_snapshot_path = env::var("CACHE_SNAPSHOT_PATH")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        let cache_snapshot_interval_seconds = env::var("CACHE_SNAPSHOT_INTERVAL_SEC")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60);
+
+        let cache_snapshot_max_bytes = env::var("CACHE_SNAPSHOT_MAX_BYTES")
+            .unwrap_or_else(|_| "16777216".to_string())
+            .parse()
+            .unwrap_or(16 << 20);
+
+        let cache_redis_url = env::var("CACHE_REDIS_URL")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        let cache_ttl_rules = env::var("CACHE_TTL_RULES")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        // A threshold of 0 would answer every prompt with some cached answer
+        let cache_similarity_threshold = match env::var("CACHE_SIMILARITY_THRESHOLD") {
+            Ok(s) if !s.trim().is_empty() => {
+                let threshold: f64 = s
+                    .trim()
+                    .parse()
+                    .context("Invalid CACHE_SIMILARITY_THRESHOLD value")?;
+                if !(threshold > 0.0 && threshold <= 1.0) {
+                    return Err(anyhow::anyhow!(
+                        "CACHE_SIMILARITY_THRESHOLD must be above 0 and at most 1"
+                    ));
+                }
+                // The index holds as many prompts as the cache, so it needs a bound
+                if cache_max_entries == 0 {
+                    return Err(anyhow::anyhow!(
+                        "CACHE_SIMILARITY_THRESHOLD requires a CACHE_MAX_ENTRIES above 0"
+                    ));
+                }
+                Some(threshold)
+            }
+            _ => None,
+        };
+
+        let tcp_idle_timeout_seconds = env::var("TCP_IDLE_TIMEOUT_SEC")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10);
+
+        let tcp_max_connections = env::var("TCP_MAX_CONNECTIONS")
+            .unwrap_or_else(|_| "256".to_string())
+            .parse()
+            .unwrap_or(256);
+
+        // A certificate without its key (or the reverse) is a half-finished TLS
+        // setup. Fail loudly rather than silently serving plaintext only.
+        let tls_cert_path = env::var("TLS_CERT_PATH").ok().filter(|s| !s.is_empty());
+        let tls_key_path = env::var("TLS_KEY_PATH").ok().filter(|s| !s.is_empty());
+        if tls_cert_path.is_some() != tls_key_path.is_some() {
+            return Err(anyhow::anyhow!(
+                "TLS_CERT_PATH and TLS_KEY_PATH must be set together"
+            ));
+        }
+
+        let dot_port = env::var("DOT_PORT")
+            .unwrap_or_else(|_| "853".to_string())
+            .parse()
+            .context("Invalid DOT_PORT value")?;
+
+        let http_port = env::var("HTTP_PORT")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse())
+            .transpose()
+            .context("Invalid HTTP_PORT value")?;
+        let admin_token = env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        let dns_zone = env::var("DNS_ZONE").ok().filter(|s| !s.trim().is_empty());
+        let dns_zone_nameservers = env::var("DNS_ZONE_NS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let dns_zone_hostmaster = env::var("DNS_ZONE_HOSTMASTER")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        let hyphens_as_spaces = env::var("PROMPT_HYPHENS_AS_SPACES")
+            .map(|s| matches!(s.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+
+        let upload_max_sessions = env::var("UPLOAD_MAX_SESSIONS")
+            .unwrap_or_else(|_| "256".to_string())
+            .parse()
+            .unwrap_or(256);
+
+        let upload_max_bytes = env::var("UPLOAD_MAX_BYTES")
+            .unwrap_or_else(|_| "16384".to_string())
+            .parse()
+            .unwrap_or(16384);
+
+        let upload_ttl_seconds = env::var("UPLOAD_TTL_SEC")
+            .unwrap_or_else(|_| "120".to_string())
+            .parse()
+            .unwrap_or(120);
+
+        let ticket_max = env::var("TICKET_MAX")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+
+        let ticket_ttl_seconds = env::var("TICKET_TTL_SEC")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+
+        let response_deadline_ms = env::var("RESPONSE_DEADLINE_MS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+
+        let deadline_reply = env::var("DEADLINE_REPLY")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.parse())
+            .transpose()
+            .context("Invalid DEADLINE_REPLY value")?
+            .unwrap_or_default();
+
+        let txt_framing = env::var("TXT_FRAMING")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.parse())
+            .transpose()
+            .context("Invalid TXT_FRAMING value")?
+            .unwrap_or_default();
+
         Ok(Self {
             openrouter_api_key,
             openrouter_models,
@@ -269,10 +636,49 @@ impl Config {
             cache_ttl_seconds,
             rate_limit_rps,
             rate_limit_burst,
+            rate_limit_ipv4_prefix,
+            rate_limit_ipv6_prefix,
+            rate_limit_max_clients,
             max_concurrent_llm_requests,
             cache_max_entries,
+            cache_max_bytes,
+            cache_stale_seconds,
+            cache_prefetch_hits,
+            negative_ttl_bad_request_seconds,
+            negative_ttl_filtered_seconds,
+            negative_ttl_model_seconds,
+            cache_snapshot_path,
+            cache_snapshot_interval_seconds,
+            cache_snapshot_max_bytes,
+            cache_redis_url,
+            cache_ttl_rules,
+            cache_similarity_threshold,
+            tcp_idle_timeout_seconds,
+            tcp_max_connections,
+            tls_cert_path,
+            tls_key_path,
+            dot_port,
+            http_port,
+            admin_token,
+            dns_zone,
+            dns_zone_nameservers,
+            dns_zone_hostmaster,
+            hyphens_as_spaces,
+            upload_max_sessions,
+            upload_max_bytes,
+            upload_ttl_seconds,
+            ticket_max,
+            ticket_ttl_seconds,
+            response_deadline_ms,
+            deadline_reply,
+            txt_framing,
         })
     }
+
+    /// Returns true when a certificate and key are configured for TLS listeners.
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert_path.is_some() && self.tls_key_path.is_some()
+    }
 }
 
 #[cfg(test)]
@@ -353,6 +759,38 @@ mod tests {
         );
         assert_eq!(config.dns_port, 53);
         assert_eq!(config.dns_address, "0.0.0.0");
+        assert_eq!(config.cache_max_bytes, 64 << 20);
+        assert_eq!(config.cache_stale_seconds, 0);
+        assert_eq!(config.cache_prefetch_hits, 0);
+        assert_eq!(config.negative_ttl_bad_request_seconds, 60);
+        assert_eq!(config.negative_ttl_filtered_seconds, 300);
+        assert_eq!(config.negative_ttl_model_seconds, 30);
+        assert_eq!(config.cache_snapshot_path, None);
+        assert_eq!(config.cache_redis_url, None);
+        assert_eq!(config.cache_ttl_rules, None);
+        assert_eq!(config.cache_similarity_threshold, None);
+        assert_eq!(config.rate_limit_ipv4_prefix, 32);
+        assert_eq!(config.rate_limit_ipv6_prefix, 64);
+        assert_eq!(config.rate_limit_max_clients, 100_000);
+        assert_eq!(config.cache_snapshot_interval_seconds, 60);
+        assert_eq!(config.cache_snapshot_max_bytes, 16 << 20);
+        assert_eq!(config.tcp_idle_timeout_seconds, 10);
+        assert_eq!(config.tcp_max_connections, 256);
+        assert_eq!(config.dot_port, 853);
+        assert!(!config.tls_enabled());
+        assert_eq!(config.http_port, None);
+        assert_eq!(config.admin_token, None);
+        assert_eq!(config.dns_zone, None);
+        assert!(config.dns_zone_nameservers.is_empty());
+        assert!(!config.hyphens_as_spaces);
+        assert_eq!(config.upload_max_sessions, 256);
+        assert_eq!(config.upload_max_bytes, 16384);
+        assert_eq!(config.upload_ttl_seconds, 120);
+        assert_eq!(config.ticket_max, 0);
+        assert_eq!(config.ticket_ttl_seconds, 300);
+        assert_eq!(config.response_deadline_ms, 0);
+        assert_eq!(config.deadline_reply, DeadlineReply::ServFail);
+        assert_eq!(config.txt_framing, TxtFraming::Records);
         // Optional parameters should be None when not set
         assert_eq!(config.temperature, None);
         assert_eq!(config.max_tokens, None);
@@ -569,4 +1007,354 @@ mod tests {
         env::remove_var("ANYROUTER_API_KEY");
         env::remove_var("ANYROUTER_MODEL");
     }
+
+    #[test]
+    #[serial]
+    fn test_config_tls_paths() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("TLS_CERT_PATH", "/etc/llm-dns/cert.pem");
+        env::set_var("TLS_KEY_PATH", "/etc/llm-dns/key.pem");
+        env::set_var("DOT_PORT", "8853");
+
+        let config = Config::from_env().expect("Failed to load config");
+
+        assert!(config.tls_enabled());
+        assert_eq!(
+            config.tls_cert_path.as_deref(),
+            Some("/etc/llm-dns/cert.pem")
+        );
+        assert_eq!(config.tls_key_path.as_deref(), Some("/etc/llm-dns/key.pem"));
+        assert_eq!(config.dot_port, 8853);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("TLS_CERT_PATH");
+        env::remove_var("TLS_KEY_PATH");
+        env::remove_var("DOT_PORT");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_tls_cert_without_key() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("TLS_CERT_PATH", "/etc/llm-dns/cert.pem");
+        env::remove_var("TLS_KEY_PATH");
+
+        let result = Config::from_env();
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("TLS_KEY_PATH"));
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("TLS_CERT_PATH");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_http_port() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("HTTP_PORT", "8443");
+
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.http_port, Some(8443));
+
+        env::set_var("HTTP_PORT", "not-a-port");
+        assert!(Config::from_env().is_err());
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("HTTP_PORT");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_admin_token() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("ADMIN_TOKEN", "s3cret");
+
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.admin_token.as_deref(), Some("s3cret"));
+
+        env::set_var("ADMIN_TOKEN", "");
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.admin_token, None);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("ADMIN_TOKEN");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_dns_zone() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("DNS_ZONE", "llm.example.com");
+        env::set_var("DNS_ZONE_NS", "ns1.example.net, ns2.example.net");
+        env::set_var("DNS_ZONE_HOSTMASTER", "admin@example.com");
+
+        let config = Config::from_env().expect("Failed to load config");
+
+        assert_eq!(config.dns_zone.as_deref(), Some("llm.example.com"));
+        assert_eq!(
+            config.dns_zone_nameservers,
+            vec!["ns1.example.net".to_string(), "ns2.example.net".to_string()]
+        );
+        assert_eq!(
+            config.dns_zone_hostmaster.as_deref(),
+            Some("admin@example.com")
+        );
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("DNS_ZONE");
+        env::remove_var("DNS_ZONE_NS");
+        env::remove_var("DNS_ZONE_HOSTMASTER");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_hyphens_as_spaces() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+
+        for (value, expected) in [("true", true), ("1", true), ("no", false)] {
+            env::set_var("PROMPT_HYPHENS_AS_SPACES", value);
+            let config = Config::from_env().expect("Failed to load config");
+            assert_eq!(config.hyphens_as_spaces, expected, "{value}");
+        }
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("PROMPT_HYPHENS_AS_SPACES");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_upload_limits() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("UPLOAD_MAX_SESSIONS", "0");
+        env::set_var("UPLOAD_MAX_BYTES", "4096");
+        env::set_var("UPLOAD_TTL_SEC", "30");
+
+        let config = Config::from_env().expect("Failed to load config");
+
+        assert_eq!(config.upload_max_sessions, 0);
+        assert_eq!(config.upload_max_bytes, 4096);
+        assert_eq!(config.upload_ttl_seconds, 30);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("UPLOAD_MAX_SESSIONS");
+        env::remove_var("UPLOAD_MAX_BYTES");
+        env::remove_var("UPLOAD_TTL_SEC");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_serve_stale_and_prefetch() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("CACHE_STALE_SEC", "86400");
+        env::set_var("CACHE_PREFETCH_HITS", "5");
+
+        let config = Config::from_env().expect("Failed to load config");
+
+        assert_eq!(config.cache_stale_seconds, 86400);
+        assert_eq!(config.cache_prefetch_hits, 5);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("CACHE_STALE_SEC");
+        env::remove_var("CACHE_PREFETCH_HITS");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_cache_max_bytes() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("CACHE_MAX_BYTES", "1048576");
+
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.cache_max_bytes, 1 << 20);
+
+        env::set_var("CACHE_MAX_BYTES", "lots");
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.cache_max_bytes, 64 << 20);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("CACHE_MAX_BYTES");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_negative_ttls() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("NEGATIVE_TTL_BAD_REQUEST_SEC", "0");
+        env::set_var("NEGATIVE_TTL_FILTERED_SEC", "3600");
+        env::set_var("NEGATIVE_TTL_MODEL_SEC", "10");
+
+        let config = Config::from_env().expect("Failed to load config");
+
+        assert_eq!(config.negative_ttl_bad_request_seconds, 0);
+        assert_eq!(config.negative_ttl_filtered_seconds, 3600);
+        assert_eq!(config.negative_ttl_model_seconds, 10);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("NEGATIVE_TTL_BAD_REQUEST_SEC");
+        env::remove_var("NEGATIVE_TTL_FILTERED_SEC");
+        env::remove_var("NEGATIVE_TTL_MODEL_SEC");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_cache_snapshot() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("CACHE_SNAPSHOT_PATH", "/var/lib/llm-over-dns/cache.jsonl");
+        env::set_var("CACHE_SNAPSHOT_INTERVAL_SEC", "15");
+        env::set_var("CACHE_SNAPSHOT_MAX_BYTES", "1048576");
+
+        let config = Config::from_env().expect("Failed to load config");
+
+        assert_eq!(
+            config.cache_snapshot_path.as_deref(),
+            Some("/var/lib/llm-over-dns/cache.jsonl")
+        );
+        assert_eq!(config.cache_snapshot_interval_seconds, 15);
+        assert_eq!(config.cache_snapshot_max_bytes, 1048576);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("CACHE_SNAPSHOT_PATH");
+        env::remove_var("CACHE_SNAPSHOT_INTERVAL_SEC");
+        env::remove_var("CACHE_SNAPSHOT_MAX_BYTES");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_cache_redis_url() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("CACHE_REDIS_URL", "redis://:secret@cache.internal:6379/1");
+
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(
+            config.cache_redis_url.as_deref(),
+            Some("redis://:secret@cache.internal:6379/1")
+        );
+
+        env::set_var("CACHE_REDIS_URL", " ");
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.cache_redis_url, None);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("CACHE_REDIS_URL");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_cache_ttl_rules() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("CACHE_TTL_RULES", "today|weather|price=0;^define =3600");
+
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(
+            config.cache_ttl_rules.as_deref(),
+            Some("today|weather|price=0;^define =3600")
+        );
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("CACHE_TTL_RULES");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_cache_similarity_threshold() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("CACHE_SIMILARITY_THRESHOLD", "0.85");
+
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.cache_similarity_threshold, Some(0.85));
+
+        for invalid in ["0", "1.5", "-0.2", "close"] {
+            env::set_var("CACHE_SIMILARITY_THRESHOLD", invalid);
+            assert!(Config::from_env().is_err(), "{} was accepted", invalid);
+        }
+
+        // An unbounded cache would leave the index unbounded too
+        env::set_var("CACHE_SIMILARITY_THRESHOLD", "0.85");
+        env::set_var("CACHE_MAX_ENTRIES", "0");
+        assert!(Config::from_env().is_err());
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("CACHE_SIMILARITY_THRESHOLD");
+        env::remove_var("CACHE_MAX_ENTRIES");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_rate_limit_prefixes() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("RATE_LIMIT_IPV4_PREFIX", "24");
+        env::set_var("RATE_LIMIT_IPV6_PREFIX", "56");
+        env::set_var("RATE_LIMIT_MAX_CLIENTS", "5000");
+
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.rate_limit_ipv4_prefix, 24);
+        assert_eq!(config.rate_limit_ipv6_prefix, 56);
+        assert_eq!(config.rate_limit_max_clients, 5000);
+
+        env::set_var("RATE_LIMIT_IPV4_PREFIX", "33");
+        assert!(Config::from_env().is_err());
+        env::set_var("RATE_LIMIT_IPV4_PREFIX", "32");
+        env::set_var("RATE_LIMIT_IPV6_PREFIX", "129");
+        assert!(Config::from_env().is_err());
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("RATE_LIMIT_IPV4_PREFIX");
+        env::remove_var("RATE_LIMIT_IPV6_PREFIX");
+        env::remove_var("RATE_LIMIT_MAX_CLIENTS");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_tickets() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("TICKET_MAX", "64");
+        env::set_var("TICKET_TTL_SEC", "90");
+
+        let config = Config::from_env().expect("Failed to load config");
+
+        assert_eq!(config.ticket_max, 64);
+        assert_eq!(config.ticket_ttl_seconds, 90);
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("TICKET_MAX");
+        env::remove_var("TICKET_TTL_SEC");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_response_deadline() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+        env::set_var("RESPONSE_DEADLINE_MS", "1500");
+
+        env::set_var("DEADLINE_REPLY", "Thinking");
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.response_deadline_ms, 1500);
+        assert_eq!(config.deadline_reply, DeadlineReply::Thinking);
+
+        env::set_var("DEADLINE_REPLY", "later");
+        let err = Config::from_env().unwrap_err();
+        assert!(err.to_string().contains("DEADLINE_REPLY"));
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("RESPONSE_DEADLINE_MS");
+        env::remove_var("DEADLINE_REPLY");
+    }
+
+    #[test]
+    #[serial]
+    fn test_config_txt_framing() {
+        env::set_var("OPENROUTER_API_KEY", "test_key");
+
+        env::set_var("TXT_FRAMING", "sequenced");
+        let config = Config::from_env().expect("Failed to load config");
+        assert_eq!(config.txt_framing, TxtFraming::Sequenced);
+
+        env::set_var("TXT_FRAMING", "shuffled");
+        let err = Config::from_env().unwrap_err();
+        assert!(err.to_string().contains("TXT_FRAMING"));
+
+        env::remove_var("OPENROUTER_API_KEY");
+        env::remove_var("TXT_FRAMING");
+    }
 }
@@ -17,6 +17,19 @@
 //! - `dig @localhost -p 6000 'what is rust' TXT` → sends "what is rust" to LLM
 //! - Trailing dots (DNS format) are automatically removed
 //!
+//! When the server is delegated a zone (see [`crate::zone`]), the origin is
+//! stripped first and the remaining labels are joined with spaces:
+//! - `dig what.is.rust.llm.example.com TXT` → sends "what is rust" to LLM
+//!
+//! Resolvers lowercase names and mangle punctuation, so prompts that need
+//! exact bytes (code, URLs, capitals, `?`) can be encoded instead. A leading
+//! `b32` or `b64` label marks the remaining labels as one base32 or base64url
+//! string, split wherever the 63-byte label limit requires:
+//! - `dig b32.jbswy3dpeb3w64tmmq.llm.example.com TXT` → sends "Hello world" to LLM
+//!
+//! Base32 is case-insensitive and survives any resolver; base64url is denser
+//! but only safe when the client talks to the server directly.
+//!
 //! # Examples
 //!
 //! ```
@@ -38,6 +51,120 @@
 //! ```
 
 use anyhow::{anyhow, Result};
+use data_encoding::{BASE32_NOPAD, BASE64URL_NOPAD};
+use hickory_server::proto::rr::Name;
+use std::fmt;
+
+/// Encoding of a prompt carried after a marker label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptEncoding {
+    /// RFC 4648 base32, marked by `b32`; case-insensitive
+    Base32,
+    /// RFC 4648 base64url, marked by `b64`; case-sensitive
+    Base64Url,
+}
+
+impl PromptEncoding {
+    /// Returns the encoding a marker label selects, if it is one
+    ///
+    /// Markers are matched case-insensitively, like the rest of a DNS name.
+    pub fn from_marker(label: &[u8]) -> Option<Self> {
+        if label.eq_ignore_ascii_case(b"b32") {
+            Some(Self::Base32)
+        } else if label.eq_ignore_ascii_case(b"b64") {
+            Some(Self::Base64Url)
+        } else {
+            None
+        }
+    }
+
+    /// The marker label for this encoding
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Self::Base32 => "b32",
+            Self::Base64Url => "b64",
+        }
+    }
+
+    /// Encodes prompt bytes for the data labels, without padding
+    ///
+    /// Base32 is written in lowercase, as names usually are.
+    pub fn encode(&self, data: &[u8]) -> String {
+        match self {
+            Self::Base32 => BASE32_NOPAD.encode(data).to_ascii_lowercase(),
+            Self::Base64Url => BASE64URL_NOPAD.encode(data),
+        }
+    }
+
+    /// Decodes the concatenated data labels
+    ///
+    /// Padding is optional, and base32 accepts either case.
+    pub(crate) fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let end = data
+            .iter()
+            .rposition(|&b| b != b'=')
+            .map_or(0, |last| last + 1);
+        let data = &data[..end];
+
+        match self {
+            Self::Base32 => BASE32_NOPAD.decode(&data.to_ascii_uppercase()).ok(),
+            Self::Base64Url => BASE64URL_NOPAD.decode(data).ok(),
+        }
+    }
+}
+
+impl fmt::Display for PromptEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Base32 => write!(f, "base32"),
+            Self::Base64Url => write!(f, "base64url"),
+        }
+    }
+}
+
+/// Why a query name could not be turned into a prompt
+///
+/// Every variant is the client's fault, which the server reports as FORMERR
+/// rather than SERVFAIL. The exception is a page past the end of an answer,
+/// which simply does not exist (NXDOMAIN).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PromptError {
+    /// No labels were left to form a prompt
+    #[error("Empty query: no text provided")]
+    Empty,
+    /// The name is not below the configured zone
+    #[error("Query name is not below zone {0}")]
+    OutsideZone(String),
+    /// A label's bytes are not UTF-8
+    #[error("Label {index} is not valid UTF-8")]
+    InvalidUtf8 {
+        /// Position of the label, counting from the left
+        index: usize,
+    },
+    /// A label has the `xn--` prefix but is not valid punycode
+    #[error("Label {index} is not valid punycode")]
+    InvalidPunycode {
+        /// Position of the label, counting from the left
+        index: usize,
+    },
+    /// The labels after an encoding marker do not decode to UTF-8 text
+    #[error("Prompt is not valid {0}-encoded UTF-8")]
+    InvalidEncoding(PromptEncoding),
+    /// A page label asks for a page past the end of the answer
+    #[error("Page {page} requested, but the answer has {pages}")]
+    PageOutOfRange {
+        /// Requested page, counting from 1
+        page: usize,
+        /// Pages the answer has
+        pages: usize,
+    },
+    /// A page label asks for a later page of an answer no longer held
+    ///
+    /// Asking the LLM again would serve a page of a different answer, so the
+    /// client has to start over from page 1.
+    #[error("Page {0} is no longer held; ask for page 1 again")]
+    PageExpired(usize),
+}
 
 /// DNS Handler for parsing queries and building responses.
 ///
@@ -154,30 +281,214 @@ impl DnsHandler {
         Ok(query.to_string())
     }
 
-    /// Legacy method name for backwards compatibility
-    #[deprecated(since = "0.2.0", note = "Use parse_query instead")]
-    pub fn parse_subdomain_legacy(&self, domain: &str) -> Result<String> {
-        let domain = domain.trim().to_lowercase();
-
-        // Old subdomain parsing logic
-        let subdomain = domain
-            .strip_suffix(".llm.duyet.net")
-            .ok_or_else(|| anyhow!("Failed to extract subdomain"))?;
+    /// Decodes a query name into a prompt by walking its wire labels.
+    ///
+    /// [`Name::to_utf8`] renders presentation format, which is for zone files
+    /// and not for people: a space typed into `dig 'hello world'` comes back
+    /// as `hello\032world`, labels stay joined by dots, and IDNA labels stay
+    /// as punycode. This works on the raw label bytes instead:
+    ///
+    /// 1. the zone origin, if any, is stripped
+    /// 2. each label is read as raw bytes, so nothing needs unescaping
+    /// 3. `xn--` labels are decoded from punycode to Unicode
+    /// 4. hyphens become spaces, if `hyphens_as_spaces` is set
+    /// 5. labels are joined with spaces
+    ///
+    /// If the first label is a [`PromptEncoding`] marker, steps 3 to 5 are
+    /// replaced by decoding the remaining labels, concatenated, as one string.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Query name as received
+    /// * `origin` - Zone origin to strip, `None` to use every label
+    /// * `hyphens_as_spaces` - Read `what-is-rust` as "what is rust"
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hickory_proto::rr::Name;
+    /// use llm_over_dns::DnsHandler;
+    ///
+    /// let handler = DnsHandler::new();
+    ///
+    /// // `dig 'hello world' TXT` sends one label containing a space
+    /// let name = Name::from_labels(vec![&b"hello world"[..]]).unwrap();
+    /// assert_eq!(handler.decode_prompt(&name, None, false).unwrap(), "hello world");
+    ///
+    /// // Labels below a zone become words
+    /// let zone = Name::from_ascii("llm.example.com.").unwrap();
+    /// let name = Name::from_ascii("what.is.rust.llm.example.com.").unwrap();
+    /// assert_eq!(handler.decode_prompt(&name, Some(&zone), false).unwrap(), "what is rust");
+    ///
+    /// // Punycode is decoded
+    /// let name = Name::from_ascii("xn--caf-dma.").unwrap();
+    /// assert_eq!(handler.decode_prompt(&name, None, false).unwrap(), "café");
+    ///
+    /// // Encoded prompts keep their case and punctuation
+    /// let name = Name::from_ascii("b32.jbswy3dpeb3w64tmmq7q.").unwrap();
+    /// assert_eq!(handler.decode_prompt(&name, None, false).unwrap(), "Hello world?");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`PromptError`] if the name is outside the zone, carries no
+    /// labels, a label is not valid UTF-8 or punycode, or encoded labels do
+    /// not decode. These are problems with the query itself, so the server
+    /// answers FORMERR.
+    pub fn decode_prompt(
+        &self,
+        name: &Name,
+        origin: Option<&Name>,
+        hyphens_as_spaces: bool,
+    ) -> std::result::Result<String, PromptError> {
+        let depth = prompt_depth(name, origin).ok_or_else(|| {
+            PromptError::OutsideZone(origin.map(Name::to_utf8).unwrap_or_default())
+        })?;
 
-        if subdomain.is_empty() {
-            return Err(anyhow!("Empty subdomain: no labels before base domain"));
+        let mut labels = name.iter().take(depth).peekable();
+        if let Some(encoding) = labels.peek().and_then(|l| PromptEncoding::from_marker(l)) {
+            let data: Vec<u8> = labels.skip(1).flatten().copied().collect();
+            return decode_encoded_prompt(encoding, &data);
         }
 
-        // Split by dots and join with spaces
-        let prompt = subdomain.split('.').collect::<Vec<&str>>().join(" ");
+        let mut words = Vec::with_capacity(depth);
+        for (index, label) in labels.enumerate() {
+            let text =
+                std::str::from_utf8(label).map_err(|_| PromptError::InvalidUtf8 { index })?;
 
+            let text = match text.get(..4) {
+                Some(prefix) if prefix.eq_ignore_ascii_case("xn--") => {
+                    idna::punycode::decode_to_string(&text[4..])
+                        .ok_or(PromptError::InvalidPunycode { index })?
+                }
+                _ => text.to_string(),
+            };
+
+            words.push(if hyphens_as_spaces {
+                text.replace('-', " ")
+            } else {
+                text
+            });
+        }
+
+        let prompt = words.join(" ").trim().to_string();
         if prompt.is_empty() {
-            return Err(anyhow!("Empty prompt after parsing"));
+            return Err(PromptError::Empty);
         }
 
         Ok(prompt)
     }
 
+    /// Builds the query name that asks `prompt`, the inverse of
+    /// [`DnsHandler::decode_prompt`]
+    ///
+    /// Without an encoding each word becomes a label. With one, the marker
+    /// label comes first and the encoded prompt follows, split into labels of
+    /// at most 63 bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hickory_proto::rr::Name;
+    /// use llm_over_dns::{DnsHandler, PromptEncoding};
+    ///
+    /// let handler = DnsHandler::new();
+    /// let zone = Name::from_ascii("llm.example.com.").unwrap();
+    ///
+    /// let name = handler.encode_prompt("what is rust", Some(&zone), None).unwrap();
+    /// assert_eq!(name.to_ascii(), "what.is.rust.llm.example.com.");
+    ///
+    /// let name = handler
+    ///     .encode_prompt("Hello world?", None, Some(PromptEncoding::Base32))
+    ///     .unwrap();
+    /// assert_eq!(name.to_ascii(), "b32.jbswy3dpeb3w64tmmq7q.");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the prompt is empty, a word is longer than a label,
+    /// or the name would exceed 255 bytes.
+    pub fn encode_prompt(
+        &self,
+        prompt: &str,
+        origin: Option<&Name>,
+        encoding: Option<PromptEncoding>,
+    ) -> Result<Name> {
+        let prompt = prompt.trim();
+        if prompt.is_empty() {
+            return Err(PromptError::Empty.into());
+        }
+
+        let mut name = match encoding {
+            None => Name::from_labels(prompt.split_whitespace().map(str::as_bytes))?,
+            Some(encoding) => {
+                let data = encoding.encode(prompt.as_bytes());
+                let labels = std::iter::once(encoding.marker().as_bytes())
+                    .chain(data.as_bytes().chunks(MAX_LABEL_LEN));
+                Name::from_labels(labels)?
+            }
+        };
+
+        match origin {
+            Some(origin) => Ok(name.append_domain(origin)?),
+            None => {
+                name.set_fqdn(true);
+                Ok(name)
+            }
+        }
+    }
+
+    /// Returns the encoding marked in the first label below the zone, if any
+    ///
+    /// Encoded prompts are case-sensitive, so callers keying on the query
+    /// name must not fold its case for these.
+    pub fn prompt_encoding(&self, name: &Name, origin: Option<&Name>) -> Option<PromptEncoding> {
+        let depth = prompt_depth(name, origin)?;
+
+        name.iter()
+            .take(depth)
+            .next()
+            .and_then(PromptEncoding::from_marker)
+    }
+
+    /// Splits a page label off the front of a query name
+    ///
+    /// `p2.what.is.rust` asks for page 2 of the answer to `what.is.rust`.
+    /// Page 1 is the query itself, so only `p2` and up are page labels, and
+    /// at least one label must be left below the zone for the query.
+    ///
+    /// Returns the page number and the query name without the page label.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hickory_proto::rr::Name;
+    /// use llm_over_dns::DnsHandler;
+    ///
+    /// let handler = DnsHandler::new();
+    /// let name = Name::from_ascii("p3.what.is.rust.").unwrap();
+    ///
+    /// let (page, query) = handler.page_request(&name, None).unwrap();
+    /// assert_eq!(page, 3);
+    /// assert_eq!(query, Name::from_ascii("what.is.rust.").unwrap());
+    /// ```
+    pub fn page_request(&self, name: &Name, origin: Option<&Name>) -> Option<(usize, Name)> {
+        if prompt_depth(name, origin)? < 2 {
+            return None;
+        }
+
+        let label = name.iter().next()?;
+        let digits = label
+            .strip_prefix(b"p")
+            .or_else(|| label.strip_prefix(b"P"))?;
+        if digits.first() == Some(&b'0') || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        let page: usize = std::str::from_utf8(digits).ok()?.parse().ok()?;
+        (page >= 2).then(|| (page, name.base_name()))
+    }
+
     /// Builds a DNS TXT record from response chunks.
     ///
     /// Combines multiple response chunks into bytes for DNS TXT record format.
@@ -291,6 +602,40 @@ impl DnsHandler {
     }
 }
 
+/// Longest label a name may carry (RFC 1035 §2.3.4).
+pub(crate) const MAX_LABEL_LEN: usize = 63;
+
+/// Number of labels below `origin`, or `None` if the name is outside it.
+pub(crate) fn prompt_depth(name: &Name, origin: Option<&Name>) -> Option<usize> {
+    match origin {
+        Some(origin) if origin.zone_of(name) => Some(name.iter().count() - origin.iter().count()),
+        Some(_) => None,
+        None => Some(name.iter().count()),
+    }
+}
+
+/// Decodes the data labels of an encoded prompt into text.
+fn decode_encoded_prompt(
+    encoding: PromptEncoding,
+    data: &[u8],
+) -> std::result::Result<String, PromptError> {
+    if data.is_empty() {
+        return Err(PromptError::Empty);
+    }
+
+    let bytes = encoding
+        .decode(data)
+        .ok_or(PromptError::InvalidEncoding(encoding))?;
+    let prompt = String::from_utf8(bytes).map_err(|_| PromptError::InvalidEncoding(encoding))?;
+
+    let prompt = prompt.trim().to_string();
+    if prompt.is_empty() {
+        return Err(PromptError::Empty);
+    }
+
+    Ok(prompt)
+}
+
 impl Default for DnsHandler {
     fn default() -> Self {
         Self::new()
@@ -513,4 +858,239 @@ mod tests {
         let handler2 = DnsHandler;
         assert_eq!(handler1, handler2);
     }
+
+    fn wire_name(labels: &[&[u8]]) -> Name {
+        let mut name = Name::from_labels(labels.iter().copied()).unwrap();
+        name.set_fqdn(true);
+        name
+    }
+
+    #[test]
+    fn test_decode_prompt_from_dig_query_packet() {
+        // The question section of `dig 'hello world' TXT`, as captured on the
+        // wire: one 11-byte label with a literal space.
+        let mut packet = vec![0x12, 0x34, 0x01, 0x20, 0, 1, 0, 0, 0, 0, 0, 0];
+        packet.push(11);
+        packet.extend_from_slice(b"hello world");
+        packet.extend_from_slice(&[0, 0, 16, 0, 1]);
+        let message = hickory_server::proto::op::Message::from_vec(&packet).unwrap();
+        let name = message.queries[0].name();
+
+        // Presentation format escapes the space, which is what we used to send.
+        assert!(name.to_utf8().starts_with("hello\\"), "{}", name.to_utf8());
+
+        let handler = DnsHandler::new();
+        assert_eq!(
+            handler.decode_prompt(name, None, false).unwrap(),
+            "hello world"
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_joins_labels_with_spaces() {
+        let handler = DnsHandler::new();
+        let name = Name::from_ascii("what.is.rust.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, None, false).unwrap(),
+            "what is rust"
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_strips_zone_origin() {
+        let handler = DnsHandler::new();
+        let zone = Name::from_ascii("llm.example.com.").unwrap();
+        let name = Name::from_ascii("what.is.rust.LLM.Example.com.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, Some(&zone), false).unwrap(),
+            "what is rust"
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_requires_name_below_zone() {
+        let handler = DnsHandler::new();
+        let zone = Name::from_ascii("llm.example.com.").unwrap();
+
+        let outside = Name::from_ascii("hello.notllm.example.com.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&outside, Some(&zone), false),
+            Err(PromptError::OutsideZone("llm.example.com.".to_string()))
+        );
+
+        assert_eq!(
+            handler.decode_prompt(&zone, Some(&zone), false),
+            Err(PromptError::Empty)
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_decodes_punycode() {
+        // dig built with libidn2 encodes `xin.chào` before sending it.
+        let handler = DnsHandler::new();
+        let name = Name::from_ascii("xin.xn--cho-bla.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, None, false).unwrap(),
+            "xin chào"
+        );
+
+        let name = Name::from_ascii("XN--caf-dma.").unwrap();
+        assert_eq!(handler.decode_prompt(&name, None, false).unwrap(), "café");
+    }
+
+    #[test]
+    fn test_decode_prompt_rejects_bad_punycode() {
+        let handler = DnsHandler::new();
+        let name = Name::from_ascii("ok.xn--99999999999999999999.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, None, false),
+            Err(PromptError::InvalidPunycode { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_accepts_raw_utf8_labels() {
+        // Some stub resolvers send UTF-8 bytes without IDNA encoding.
+        let handler = DnsHandler::new();
+        let name = wire_name(&["thế giới".as_bytes()]);
+        assert_eq!(
+            handler.decode_prompt(&name, None, false).unwrap(),
+            "thế giới"
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_rejects_invalid_utf8() {
+        let handler = DnsHandler::new();
+        let name = wire_name(&[b"hello", &[0xff, 0xfe]]);
+        assert_eq!(
+            handler.decode_prompt(&name, None, false),
+            Err(PromptError::InvalidUtf8 { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_hyphens_as_spaces() {
+        let handler = DnsHandler::new();
+        let name = Name::from_ascii("what-is-rust.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, None, false).unwrap(),
+            "what-is-rust"
+        );
+        assert_eq!(
+            handler.decode_prompt(&name, None, true).unwrap(),
+            "what is rust"
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_root_is_empty() {
+        let handler = DnsHandler::new();
+        assert_eq!(
+            handler.decode_prompt(&Name::root(), None, false),
+            Err(PromptError::Empty)
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_base32_is_case_insensitive() {
+        let handler = DnsHandler::new();
+        let zone = Name::from_ascii("llm.example.com.").unwrap();
+
+        for encoded in [
+            "b32.LBUW4IDDNDB2A3ZMEBJHK43UEE.llm.example.com.",
+            "B32.lbuw4iddndb2a3zmebjhk43uee.llm.example.com.",
+            "b32.lbuw4iddndb2a.3zmebjhk43uee.llm.example.com.",
+        ] {
+            let name = Name::from_ascii(encoded).unwrap();
+            assert_eq!(
+                handler.decode_prompt(&name, Some(&zone), true).unwrap(),
+                "Xin chào, Rust!",
+                "{}",
+                encoded
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_prompt_base64url_across_labels() {
+        let handler = DnsHandler::new();
+        let encoded = "V2hhdCBkb2VzID9xPVJ1c3QmeD0xIG1lYW4gaW4gaHR0cHM6Ly9FeGFtcGxlLmNvbS9BPw";
+        let (first, second) = encoded.split_at(40);
+        let name = wire_name(&[b"b64", first.as_bytes(), second.as_bytes()]);
+
+        assert_eq!(
+            handler.decode_prompt(&name, None, false).unwrap(),
+            "What does ?q=Rust&x=1 mean in https://Example.com/A?"
+        );
+    }
+
+    #[test]
+    fn test_decode_prompt_rejects_malformed_encoding() {
+        let handler = DnsHandler::new();
+
+        // `1` and `8` are not in the base32 alphabet
+        let name = Name::from_ascii("b32.18.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, None, false),
+            Err(PromptError::InvalidEncoding(PromptEncoding::Base32))
+        );
+
+        // Valid base64url, but the bytes are not UTF-8
+        let name = Name::from_ascii("b64.__4.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, None, false),
+            Err(PromptError::InvalidEncoding(PromptEncoding::Base64Url))
+        );
+
+        let name = Name::from_ascii("b64.").unwrap();
+        assert_eq!(
+            handler.decode_prompt(&name, None, false),
+            Err(PromptError::Empty)
+        );
+    }
+
+    #[test]
+    fn test_page_request() {
+        let handler = DnsHandler::new();
+        let zone = Name::from_ascii("llm.example.com.").unwrap();
+        let page = |name: &str| {
+            handler
+                .page_request(&Name::from_ascii(name).unwrap(), Some(&zone))
+                .map(|(page, query)| (page, query.to_utf8()))
+        };
+
+        assert_eq!(
+            page("p2.what.is.rust.llm.example.com."),
+            Some((2, "what.is.rust.llm.example.com.".to_string()))
+        );
+        assert_eq!(
+            page("P12.rust.llm.example.com."),
+            Some((12, "rust.llm.example.com.".to_string()))
+        );
+
+        // Page 1 is the query itself; these are ordinary prompts
+        assert_eq!(page("p1.rust.llm.example.com."), None);
+        assert_eq!(page("p02.rust.llm.example.com."), None);
+        assert_eq!(page("p2p.networks.llm.example.com."), None);
+        assert_eq!(page("pages.llm.example.com."), None);
+        // Nothing left to ask once the page label is removed
+        assert_eq!(page("p2.llm.example.com."), None);
+    }
+
+    #[test]
+    fn test_prompt_encoding_marker() {
+        let handler = DnsHandler::new();
+        let zone = Name::from_ascii("llm.example.com.").unwrap();
+
+        let name = Name::from_ascii("B64.aGk.llm.example.com.").unwrap();
+        assert_eq!(
+            handler.prompt_encoding(&name, Some(&zone)),
+            Some(PromptEncoding::Base64Url)
+        );
+
+        let name = Name::from_ascii("what.b32.llm.example.com.").unwrap();
+        assert_eq!(handler.prompt_encoding(&name, Some(&zone)), None);
+        assert_eq!(handler.prompt_encoding(&zone, Some(&zone)), None);
+    }
 }
@@ -0,0 +1,1187 @@
+//! DNS over HTTPS (RFC 8484)
+//!
+//! Networks that block UDP and TCP port 53 almost always let HTTPS through.
+//! This module serves `/dns-query`, which carries ordinary DNS wire-format
+//! messages over HTTP: `POST` with an `application/dns-message` body, or `GET`
+//! with the message base64url-encoded in the `dns` query parameter.
+//!
+//! Queries are answered by the same [`build_response`] as the UDP and TCP
+//! listeners. HTTP has no datagram budget, so answers are never truncated,
+//! and the `Cache-Control` header carries the answer TTL so browsers and
+//! proxies can cache responses (RFC 8484 §5.1).
+//!
+//! Two JSON endpoints sit beside it for callers that would rather not handle
+//! wire format:
+//!
+//! - `GET /resolve?name=<name>&type=TXT` answers in the JSON shape popularised
+//!   by Google Public DNS, and goes through the same DNS path.
+//! - `POST /ask` with `{"prompt": "..."}` returns the whole answer, the model
+//!   that produced it and the TXT chunks of every page DNS would serve. It is
+//!   rate limited per client and shares the LLM concurrency ceiling, and the
+//!   cache of recurring failures, with DNS queries.
+//!
+//! With an admin token configured, `/admin/cache` administers the response
+//! cache for callers presenting it as `Authorization: Bearer <token>`:
+//!
+//! - `GET /admin/cache?limit=<n>` lists the most hit answers with their hit
+//!   counts and remaining TTL.
+//! - `DELETE /admin/cache?name=<name>` purges the answer to a query name,
+//!   every page and spelling of it; `?prefix=<text>` and `?pattern=<regex>`
+//!   purge every prompt starting with or matching it. Purged answers also
+//!   leave the similarity index and the shared cache, but other replicas
+//!   serve the copies in their memory until those expire. When the shared
+//!   cache cannot be purged, the response is `502 Bad Gateway` with the
+//!   reason in `shared_error`.
+//! - `GET /admin/cache/export` and `POST /admin/cache/import` move answers in
+//!   the [`crate::snapshot`] format.
+//!
+//! The listener speaks HTTPS (HTTP/2 or HTTP/1.1, negotiated by ALPN) when a
+//! certificate is configured, and plain HTTP otherwise so it can sit behind a
+//! TLS-terminating proxy.
+
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Query, State};
+use axum::http::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use data_encoding::BASE64URL_NOPAD;
+use hickory_server::proto::op::{Message, MessageType, OpCode, Query as DnsQuery, ResponseCode};
+use hickory_server::proto::rr::{Name, RData, Record, RecordType};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Semaphore};
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, error, info, warn};
+
+use crate::cache::key_parts;
+use crate::server::build_response;
+use crate::{snapshot, IpRateLimiter, LlmBusy, LlmDnsHandler};
+
+/// Media type of a DNS wire-format message (RFC 8484 §6).
+pub const DNS_MESSAGE_MEDIA_TYPE: &str = "application/dns-message";
+
+/// Largest DNS message accepted in a request body.
+///
+/// Matches the ceiling of the TCP length prefix; nothing legitimate is larger.
+const MAX_DNS_MESSAGE: usize = u16::MAX as usize;
+
+/// Largest cache export accepted by `POST /admin/cache/import`
+const MAX_IMPORT_BYTES: usize = 64 << 20;
+
+/// Answers listed by `GET /admin/cache` without a `limit`
+const DEFAULT_LISTING: usize = 100;
+
+#[derive(Clone)]
+struct HttpState {
+    handler: Arc<LlmDnsHandler>,
+    rate_limiter: Arc<IpRateLimiter>,
+    /// Bearer token for `/admin`. `None` leaves the admin routes out.
+    admin_token: Option<Arc<str>>,
+}
+
+#[derive(Deserialize)]
+struct DohParams {
+    dns: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResolveParams {
+    name: Option<String>,
+    #[serde(rename = "type")]
+    record_type: Option<String>,
+}
+
+/// JSON DNS response, field names as used by Google Public DNS
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonResponse {
+    #[serde(rename = "Status")]
+    status: u16,
+    #[serde(rename = "TC")]
+    truncated: bool,
+    #[serde(rename = "RD")]
+    recursion_desired: bool,
+    #[serde(rename = "RA")]
+    recursion_available: bool,
+    #[serde(rename = "AD")]
+    authentic_data: bool,
+    #[serde(rename = "CD")]
+    checking_disabled: bool,
+    #[serde(rename = "Question")]
+    question: Vec<JsonQuestion>,
+    #[serde(rename = "Answer", default, skip_serializing_if = "Vec::is_empty")]
+    answer: Vec<JsonRecord>,
+    #[serde(rename = "Authority", default, skip_serializing_if = "Vec::is_empty")]
+    authority: Vec<JsonRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonQuestion {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonRecord {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: u16,
+    #[serde(rename = "TTL")]
+    ttl: u32,
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct AskRequest {
+    prompt: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AskResponse {
+    answer: String,
+    model: String,
+    chunks: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Deserialize)]
+struct ListingParams {
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct PurgeParams {
+    name: Option<String>,
+    prefix: Option<String>,
+    pattern: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheListing {
+    entries: usize,
+    bytes: usize,
+    hits: CacheHits,
+    top: Vec<CachedAnswer>,
+}
+
+/// Queries answered from a cache since the server started
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheHits {
+    exact: u64,
+    shared: u64,
+    similar: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedAnswer {
+    key: String,
+    prompt: String,
+    page: usize,
+    hits: u64,
+    /// Seconds left; 0 once the answer is only kept to be served stale
+    ttl: u64,
+    bytes: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PurgeResponse {
+    purged: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shared_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ImportResponse {
+    restored: usize,
+}
+
+/// Builds the HTTP routes
+///
+/// Handlers read the client address from a [`ConnectInfo<SocketAddr>`]
+/// request extension, which [`serve`] inserts for every request.
+///
+/// # Arguments
+///
+/// * `handler` - LLM DNS handler shared with the DNS listeners
+/// * `rate_limiter` - Per-client rate limiter shared with the DNS listeners
+/// * `admin_token` - Bearer token for `/admin`, `None` to leave it out
+pub(crate) fn router(
+    handler: Arc<LlmDnsHandler>,
+    rate_limiter: Arc<IpRateLimiter>,
+    admin_token: Option<String>,
+) -> Router {
+    let mut router = Router::new()
+        .route("/dns-query", get(dns_query_get).post(dns_query_post))
+        .route("/resolve", get(resolve))
+        .route("/ask", post(ask));
+    if admin_token.is_some() {
+        router = router
+            .route("/admin/cache", get(admin_cache).delete(admin_purge))
+            .route("/admin/cache/export", get(admin_export))
+            .route(
+                "/admin/cache/import",
+                post(admin_import).layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES)),
+            );
+    }
+
+    router
+        .layer(DefaultBodyLimit::max(MAX_DNS_MESSAGE))
+        .with_state(HttpState {
+            handler,
+            rate_limiter,
+            admin_token: admin_token.map(Arc::from),
+        })
+}
+
+/// Accepts HTTP (or HTTPS) connections until shutdown is signalled
+///
+/// # Arguments
+///
+/// * `listener` - Bound TCP listener
+/// * `tls` - TLS acceptor for HTTPS, `None` for plain HTTP
+/// * `router` - Routes built by [`router`]
+/// * `idle_timeout` - How long a client may take to send request headers
+/// * `max_connections` - Ceiling on open connections, 0 for no limit
+/// * `shutdown_rx` - Server shutdown signal
+pub(crate) async fn serve(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    router: Router,
+    idle_timeout: Duration,
+    max_connections: usize,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    let connection_permits =
+        (max_connections > 0).then(|| Arc::new(Semaphore::new(max_connections)));
+
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.recv() => {
+                info!("Shutdown signal received, stopping HTTP listener");
+                break;
+            }
+
+            result = listener.accept() => {
+                let (stream, remote_addr) = match result {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("HTTP accept error: {}", e);
+                        // Small delay to prevent tight loop on persistent errors
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+
+                let permit = match connection_permits.as_ref() {
+                    Some(sem) => match sem.clone().try_acquire_owned() {
+                        Ok(permit) => Some(permit),
+                        Err(_) => {
+                            warn!("HTTP connection limit reached, dropping {}", remote_addr);
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                debug!("Accepted HTTP connection from {}", remote_addr);
+                let tls = tls.clone();
+                let router = router.clone();
+
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let result = match tls {
+                        Some(acceptor) => match timeout(idle_timeout, acceptor.accept(stream)).await {
+                            Ok(Ok(tls_stream)) => {
+                                serve_connection(tls_stream, remote_addr, router, idle_timeout).await
+                            }
+                            Ok(Err(e)) => Err(anyhow::anyhow!("TLS handshake failed: {}", e)),
+                            Err(_) => Err(anyhow::anyhow!("TLS handshake timed out")),
+                        },
+                        None => serve_connection(stream, remote_addr, router, idle_timeout).await,
+                    };
+                    if let Err(e) = result {
+                        debug!("HTTP connection from {} ended with error: {}", remote_addr, e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Serves HTTP/1.1 or HTTP/2 requests on one connection
+async fn serve_connection<S>(
+    stream: S,
+    remote_addr: SocketAddr,
+    router: Router,
+    idle_timeout: Duration,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(remote_addr));
+        router.clone().oneshot(request)
+    });
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(idle_timeout);
+    builder.http2().timer(TokioTimer::new());
+
+    builder
+        .serve_connection(TokioIo::new(stream), service)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+/// `GET /dns-query?dns=<base64url>`
+async fn dns_query_get(
+    State(state): State<HttpState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<DohParams>,
+) -> Response {
+    let Some(encoded) = params.dns else {
+        return (StatusCode::BAD_REQUEST, "Missing dns parameter").into_response();
+    };
+
+    // RFC 8484 §4.1 forbids padding, but stripping it costs nothing and spares
+    // clients built on a padded encoder.
+    let wire = match BASE64URL_NOPAD.decode(encoded.trim_end_matches('=').as_bytes()) {
+        Ok(wire) => wire,
+        Err(e) => {
+            debug!("Invalid base64url from {}: {}", remote_addr, e);
+            return (
+                StatusCode::BAD_REQUEST,
+                "Invalid base64url in dns parameter",
+            )
+                .into_response();
+        }
+    };
+
+    answer(&state, remote_addr, &wire).await
+}
+
+/// `POST /dns-query` with an `application/dns-message` body
+async fn dns_query_post(
+    State(state): State<HttpState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let is_dns_message = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| {
+            media_type
+                .trim()
+                .eq_ignore_ascii_case(DNS_MESSAGE_MEDIA_TYPE)
+        });
+    if !is_dns_message {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/dns-message",
+        )
+            .into_response();
+    }
+
+    answer(&state, remote_addr, &body).await
+}
+
+/// Answers one wire-format query
+async fn answer(state: &HttpState, remote_addr: SocketAddr, wire: &[u8]) -> Response {
+    let request_msg = match Message::from_vec(wire) {
+        Ok(msg) => msg,
+        Err(e) => {
+            warn!("Failed to parse DNS message from {}: {}", remote_addr, e);
+            return (StatusCode::BAD_REQUEST, "Malformed DNS message").into_response();
+        }
+    };
+
+    let response = build_response(
+        &request_msg,
+        remote_addr,
+        &state.handler,
+        &state.rate_limiter,
+    )
+    .await;
+
+    match response.to_vec() {
+        Ok(bytes) => (
+            [
+                (CONTENT_TYPE, DNS_MESSAGE_MEDIA_TYPE.to_string()),
+                (CACHE_CONTROL, cache_control(&response)),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Err(e) => {
+            error!(
+                "Failed to serialize DNS response for {}: {}",
+                remote_addr, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// `GET /resolve?name=<name>&type=<type>`
+///
+/// `type` accepts a mnemonic or a number and defaults to TXT.
+async fn resolve(
+    State(state): State<HttpState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<ResolveParams>,
+) -> Response {
+    let Some(name) = params.name.filter(|name| !name.is_empty()) else {
+        return error_response(StatusCode::BAD_REQUEST, "Missing name parameter");
+    };
+    // Names are absolute here, as in every JSON DNS API; `example.com` and
+    // `example.com.` are the same question.
+    let name = match Name::from_utf8(&name) {
+        Ok(mut name) => {
+            name.set_fqdn(true);
+            name
+        }
+        Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid name parameter"),
+    };
+    let record_type = match params.record_type.as_deref() {
+        None | Some("") => RecordType::TXT,
+        Some(value) => match parse_record_type(value) {
+            Some(record_type) => record_type,
+            None => return error_response(StatusCode::BAD_REQUEST, "Invalid type parameter"),
+        },
+    };
+
+    let mut request_msg = Message::new(0, MessageType::Query, OpCode::Query);
+    request_msg.metadata.recursion_desired = true;
+    request_msg.add_query(DnsQuery::query(name, record_type));
+
+    let response = build_response(
+        &request_msg,
+        remote_addr,
+        &state.handler,
+        &state.rate_limiter,
+    )
+    .await;
+
+    (
+        [(CACHE_CONTROL, cache_control(&response))],
+        Json(json_response(&response)),
+    )
+        .into_response()
+}
+
+/// `POST /ask` with `{"prompt": "..."}`
+async fn ask(
+    State(state): State<HttpState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<AskRequest>,
+) -> Response {
+    if !state.rate_limiter.check_allowed(remote_addr.ip()) {
+        warn!("Rate limit exceeded for client {}", remote_addr);
+        return error_response(StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded");
+    }
+
+    let prompt = request.prompt.trim();
+    if prompt.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "Prompt cannot be empty");
+    }
+
+    match state.handler.ask(prompt).await {
+        Ok(answer) => Json(AskResponse {
+            answer: answer.answer,
+            model: answer.model,
+            chunks: answer.chunks,
+        })
+        .into_response(),
+        Err(e) if e.is::<LlmBusy>() => {
+            error_response(StatusCode::SERVICE_UNAVAILABLE, "Server busy, try again")
+        }
+        Err(e) => {
+            warn!("Failed to answer prompt from {}: {}", remote_addr, e);
+            error_response(StatusCode::BAD_GATEWAY, "LLM request failed")
+        }
+    }
+}
+
+/// The rejection for a request to `/admin` that does not carry the admin
+/// token, if it does not
+fn unauthorized(state: &HttpState, headers: &HeaderMap) -> Option<Response> {
+    let given = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (state.admin_token.as_deref(), given) {
+        (Some(token), Some(given)) if constant_time_eq(token.as_bytes(), given.as_bytes()) => None,
+        _ => Some(error_response(
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid admin token",
+        )),
+    }
+}
+
+/// Compares without returning early, so timing does not reveal how much of
+/// a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// `GET /admin/cache?limit=<n>`
+async fn admin_cache(
+    State(state): State<HttpState>,
+    headers: HeaderMap,
+    Query(params): Query<ListingParams>,
+) -> Response {
+    if let Some(rejection) = unauthorized(&state, &headers) {
+        return rejection;
+    }
+
+    let cache = &state.handler.cache;
+    let top = cache
+        .top(params.limit.unwrap_or(DEFAULT_LISTING))
+        .await
+        .into_iter()
+        .map(|entry| {
+            let (page, prompt) = key_parts(&entry.key);
+            CachedAnswer {
+                prompt: prompt.to_string(),
+                page,
+                hits: entry.hits,
+                ttl: entry.ttl.as_secs(),
+                bytes: entry.bytes,
+                key: entry.key,
+            }
+        })
+        .collect();
+
+    let hits = state.handler.hit_stats();
+    Json(CacheListing {
+        entries: cache.len().await,
+        bytes: cache.bytes().await,
+        hits: CacheHits {
+            exact: hits.exact,
+            shared: hits.shared,
+            similar: hits.similar,
+        },
+        top,
+    })
+    .into_response()
+}
+
+/// `DELETE /admin/cache?name=<name>`, `?prefix=<text>` or `?pattern=<regex>`
+async fn admin_purge(
+    State(state): State<HttpState>,
+    headers: HeaderMap,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<PurgeParams>,
+) -> Response {
+    if let Some(rejection) = unauthorized(&state, &headers) {
+        return rejection;
+    }
+
+    let purged = match (params.name, params.prefix, params.pattern) {
+        (Some(name), None, None) => {
+            let name = match Name::from_utf8(&name) {
+                Ok(mut name) => {
+                    name.set_fqdn(true);
+                    name
+                }
+                Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid name parameter"),
+            };
+            match state.handler.purge_name(&name).await {
+                Ok(purged) => purged,
+                Err(_) => return error_response(StatusCode::BAD_REQUEST, "Name is not a prompt"),
+            }
+        }
+        (None, Some(prefix), None) if !prefix.trim().is_empty() => {
+            state.handler.purge_prefix(&prefix).await
+        }
+        (None, None, Some(pattern)) => match regex::RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+        {
+            Ok(pattern) => state.handler.purge_matching(&pattern).await,
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid pattern parameter"),
+        },
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "Expected one of name, prefix or pattern",
+            )
+        }
+    };
+    info!(
+        "Admin {} purged {} cached answers",
+        remote_addr, purged.pages
+    );
+
+    // The purge still went through in memory, so the count is reported too
+    let status = match purged.shared_error {
+        Some(_) => StatusCode::BAD_GATEWAY,
+        None => StatusCode::OK,
+    };
+    let response = PurgeResponse {
+        purged: purged.pages,
+        shared_error: purged.shared_error,
+    };
+    (status, Json(response)).into_response()
+}
+
+/// `GET /admin/cache/export`
+async fn admin_export(State(state): State<HttpState>, headers: HeaderMap) -> Response {
+    if let Some(rejection) = unauthorized(&state, &headers) {
+        return rejection;
+    }
+
+    match snapshot::export(&state.handler.cache, u64::MAX).await {
+        Ok((contents, _)) => ([(CONTENT_TYPE, "application/jsonl")], contents).into_response(),
+        Err(e) => {
+            error!("Failed to export cache: {:#}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Export failed")
+        }
+    }
+}
+
+/// `POST /admin/cache/import` with a body from `GET /admin/cache/export`
+async fn admin_import(State(state): State<HttpState>, headers: HeaderMap, body: Bytes) -> Response {
+    if let Some(rejection) = unauthorized(&state, &headers) {
+        return rejection;
+    }
+
+    let Ok(contents) = std::str::from_utf8(&body) else {
+        return error_response(StatusCode::BAD_REQUEST, "Export is not UTF-8");
+    };
+    match snapshot::import(&state.handler.cache, contents).await {
+        Ok(restored) => Json(ImportResponse { restored }).into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, &format!("{:#}", e)),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        Json(ErrorResponse {
+            error: message.to_string(),
+        }),
+    )
+        .into_response()
+}
+
+/// Parses `TXT`, `txt` or `16` into a record type
+fn parse_record_type(value: &str) -> Option<RecordType> {
+    match value.parse::<u16>() {
+        Ok(number) => Some(RecordType::from(number)),
+        Err(_) => value.to_ascii_uppercase().parse().ok(),
+    }
+}
+
+fn json_response(response: &Message) -> JsonResponse {
+    JsonResponse {
+        status: response.metadata.response_code.into(),
+        truncated: response.metadata.truncation,
+        recursion_desired: response.metadata.recursion_desired,
+        recursion_available: response.metadata.recursion_available,
+        authentic_data: response.metadata.authentic_data,
+        checking_disabled: response.metadata.checking_disabled,
+        question: response
+            .queries
+            .iter()
+            .map(|query| JsonQuestion {
+                name: query.name().to_utf8(),
+                record_type: query.query_type().into(),
+            })
+            .collect(),
+        answer: response.answers.iter().map(json_record).collect(),
+        authority: response.authorities.iter().map(json_record).collect(),
+    }
+}
+
+fn json_record(record: &Record) -> JsonRecord {
+    // TXT strings are joined the way resolvers present them, so a chunked
+    // answer reads as one string per record rather than escaped presentation
+    // format.
+    let data = match &record.data {
+        RData::TXT(txt) => txt
+            .txt_data
+            .iter()
+            .map(|part| String::from_utf8_lossy(part))
+            .collect(),
+        other => other.to_string(),
+    };
+
+    JsonRecord {
+        name: record.name.to_utf8(),
+        record_type: record.record_type().into(),
+        ttl: record.ttl,
+        data,
+    }
+}
+
+/// `Cache-Control` value for a response (RFC 8484 §5.1)
+///
+/// The freshness lifetime is the smallest TTL in the answer and authority
+/// sections, so an HTTP cache never outlives a record. Failures such as
+/// SERVFAIL or REFUSED are transient and must not be cached at all.
+fn cache_control(response: &Message) -> String {
+    match response.metadata.response_code {
+        ResponseCode::NoError | ResponseCode::NXDomain => {
+            let ttl = response
+                .answers
+                .iter()
+                .chain(response.authorities.iter())
+                .map(|record| record.ttl)
+                .min()
+                .unwrap_or(0);
+            format!("max-age={}", ttl)
+        }
+        _ => "no-store".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{FailureKind, NegativeTtls, RespCache};
+    use crate::server::test_llm_client;
+    use crate::{Chunker, DnsCache, DnsHandler};
+    use axum::body::Body;
+    use hickory_server::proto::rr::rdata::TXT;
+    use http_body_util::BodyExt;
+
+    fn test_handler() -> Arc<LlmDnsHandler> {
+        Arc::new(handler_with_llm_url("http://127.0.0.1:9".to_string()))
+    }
+
+    fn handler_with_llm_url(url: String) -> LlmDnsHandler {
+        LlmDnsHandler::new(
+            Arc::new(test_llm_client(url)),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+    }
+
+    async fn cache_story(handler: &LlmDnsHandler, name: &Name) {
+        let records = (0..8)
+            .map(|_| {
+                Record::from_rdata(
+                    name.clone(),
+                    120,
+                    RData::TXT(TXT::new(vec!["a".repeat(250)])),
+                )
+            })
+            .collect();
+        let key = handler.cache_key(name).unwrap();
+        handler.cache.insert(&key.page(1), records).await;
+    }
+
+    fn ask_request(body: &str) -> Request<Body> {
+        Request::post("/ask")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn test_router(handler: Arc<LlmDnsHandler>) -> Router {
+        router(handler, Arc::new(IpRateLimiter::new(0.0, 0.0)), None)
+    }
+
+    fn query_bytes(name: &str, record_type: RecordType) -> Vec<u8> {
+        // RFC 8484 §4.1 asks clients to use ID 0 so GET URLs are cacheable.
+        let mut msg = Message::new(0, MessageType::Query, OpCode::Query);
+        msg.add_query(DnsQuery::query(Name::from_utf8(name).unwrap(), record_type));
+        msg.to_vec().unwrap()
+    }
+
+    async fn send(router: Router, request: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let mut request = request;
+        request
+            .extensions_mut()
+            .insert(ConnectInfo::<SocketAddr>("127.0.0.1:443".parse().unwrap()));
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, headers, body.to_vec())
+    }
+
+    fn get_request(encoded: &str) -> Request<Body> {
+        Request::get(format!("/dns-query?dns={}", encoded))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_answers_base64url_query() {
+        let encoded = BASE64URL_NOPAD.encode(&query_bytes("what.is.rust.", RecordType::A));
+        let (status, headers, body) =
+            send(test_router(test_handler()), get_request(&encoded)).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CONTENT_TYPE], DNS_MESSAGE_MEDIA_TYPE);
+        let response = Message::from_vec(&body).unwrap();
+        assert_eq!(response.metadata.response_code, ResponseCode::NotImp);
+        assert_eq!(headers[CACHE_CONTROL], "no-store");
+    }
+
+    #[tokio::test]
+    async fn test_post_answers_without_udp_truncation() {
+        // Well past the 512-byte plain-DNS budget that UDP would enforce.
+        let handler = test_handler();
+        cache_story(&handler, &Name::from_utf8("tell.me.a.story.").unwrap()).await;
+
+        let request = Request::post("/dns-query")
+            .header(CONTENT_TYPE, DNS_MESSAGE_MEDIA_TYPE)
+            .body(Body::from(query_bytes("tell.me.a.story.", RecordType::TXT)))
+            .unwrap();
+        let (status, headers, body) = send(test_router(handler), request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CACHE_CONTROL], "max-age=120");
+        let response = Message::from_vec(&body).unwrap();
+        assert!(!response.metadata.truncation);
+        assert_eq!(response.answers.len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_post_requires_dns_message_content_type() {
+        let request = Request::post("/dns-query")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(query_bytes("what.is.rust.", RecordType::A)))
+            .unwrap();
+        let (status, _, _) = send(test_router(test_handler()), request).await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_get_rejects_missing_or_malformed_parameter() {
+        let missing = Request::get("/dns-query").body(Body::empty()).unwrap();
+        let (status, _, _) = send(test_router(test_handler()), missing).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _, _) = send(test_router(test_handler()), get_request("!!!")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Valid base64url, but not a DNS message.
+        let (status, _, _) = send(test_router(test_handler()), get_request("AAAA")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_cache_control_uses_smallest_ttl() {
+        let name = Name::from_utf8("what.is.rust.").unwrap();
+        let mut response = Message::new(0, MessageType::Response, OpCode::Query);
+        for ttl in [300, 60, 120] {
+            response.add_answer(Record::from_rdata(
+                name.clone(),
+                ttl,
+                RData::TXT(TXT::new(vec!["chunk".to_string()])),
+            ));
+        }
+        assert_eq!(cache_control(&response), "max-age=60");
+
+        response.metadata.response_code = ResponseCode::ServFail;
+        assert_eq!(cache_control(&response), "no-store");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_returns_json_answer() {
+        let handler = test_handler();
+        cache_story(&handler, &Name::from_utf8("tell.me.a.story.").unwrap()).await;
+
+        let request = Request::get("/resolve?name=tell.me.a.story&type=txt")
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, body) = send(test_router(handler), request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CACHE_CONTROL], "max-age=120");
+        let json: JsonResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json.status, 0);
+        assert_eq!(json.question[0].name, "tell.me.a.story.");
+        assert_eq!(json.question[0].record_type, 16);
+        assert_eq!(json.answer.len(), 8);
+        assert_eq!(json.answer[0].ttl, 120);
+        assert_eq!(json.answer[0].data, "a".repeat(250));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_reports_dns_status() {
+        let request = Request::get("/resolve?name=what.is.rust&type=1")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = send(test_router(test_handler()), request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: JsonResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json.status, u16::from(ResponseCode::NotImp));
+        assert!(json.answer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_rejects_bad_parameters() {
+        for uri in ["/resolve", "/resolve?name=what.is.rust&type=NOPE"] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let (status, _, _) = send(test_router(test_handler()), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ask_returns_full_answer_model_and_chunks() {
+        let mut llm = mockito::Server::new_async().await;
+        let content = "b".repeat(5000);
+        let _mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{"choices": [{{"message": {{"content": "{}"}}}}]}}"#,
+                content
+            ))
+            .create_async()
+            .await;
+
+        let handler = Arc::new(handler_with_llm_url(llm.url()));
+        let (status, _, body) = send(
+            test_router(handler),
+            ask_request(r#"{"prompt": "tell me a story"}"#),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: AskResponse = serde_json::from_slice(&body).unwrap();
+        // The answer is complete even though DNS would page it past 4096
+        // bytes, and so are the chunks, which span every page.
+        assert_eq!(json.answer, content);
+        assert_eq!(json.model, "model");
+        assert!(!json.chunks.is_empty());
+        assert!(json.chunks.iter().all(|chunk| chunk.len() <= 250));
+        assert_eq!(json.chunks.concat(), content);
+    }
+
+    #[tokio::test]
+    async fn test_ask_shares_calls_and_caches_failures() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(400)
+            .with_body(r#"{"error": "no"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(
+                DnsCache::new(Duration::from_secs(300)).with_negative_ttls(NegativeTtls {
+                    bad_request: Duration::from_secs(60),
+                    content_filtered: Duration::from_secs(60),
+                    model_not_found: Duration::from_secs(60),
+                }),
+            ),
+        );
+
+        // Two requests at once make one call, and a third is answered from
+        // the cached failure
+        let (a, b) = tokio::join!(
+            handler.ask("tell me a story"),
+            handler.ask("tell me a story")
+        );
+        assert!(a.is_err() && b.is_err());
+        let err = handler.ask("tell me a story").await.unwrap_err();
+        assert_eq!(FailureKind::of(&err), Some(FailureKind::BadRequest));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_ask_rejects_empty_prompt() {
+        let (status, _, body) = send(
+            test_router(test_handler()),
+            ask_request(r#"{"prompt": "  "}"#),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let json: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert!(json.error.contains("empty"));
+    }
+
+    #[tokio::test]
+    async fn test_ask_is_rate_limited() {
+        // One token, no refill to speak of: the second request is refused.
+        let router = router(
+            test_handler(),
+            Arc::new(IpRateLimiter::new(0.001, 1.0)),
+            None,
+        );
+
+        let (status, _, _) = send(router.clone(), ask_request(r#"{"prompt": ""}"#)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _, _) = send(router, ask_request(r#"{"prompt": ""}"#)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    fn admin_router(handler: Arc<LlmDnsHandler>) -> Router {
+        router(
+            handler,
+            Arc::new(IpRateLimiter::new(0.0, 0.0)),
+            Some("s3cret".to_string()),
+        )
+    }
+
+    fn admin_request(method: &str, uri: &str, body: Body) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, "Bearer s3cret")
+            .body(body)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_the_token() {
+        let request = || Request::get("/admin/cache").body(Body::empty()).unwrap();
+
+        // Without a token configured there is nothing to find
+        let (status, _, _) = send(test_router(test_handler()), request()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _, _) = send(admin_router(test_handler()), request()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut wrong = request();
+        wrong
+            .headers_mut()
+            .insert(AUTHORIZATION, "Bearer s3cre".parse().unwrap());
+        let (status, _, _) = send(admin_router(test_handler()), wrong).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let request = admin_request("GET", "/admin/cache", Body::empty());
+        let (status, _, _) = send(admin_router(test_handler()), request).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_admin_lists_and_purges_answers() {
+        let handler = test_handler();
+        for name in ["tell.me.a.story.", "tell.me.a.joke.", "what.is.rust."] {
+            cache_story(&handler, &Name::from_utf8(name).unwrap()).await;
+        }
+        let key = handler
+            .cache_key(&Name::from_utf8("what.is.rust.").unwrap())
+            .unwrap();
+        handler.cache.get(&key.page(1)).await;
+
+        let request = admin_request("GET", "/admin/cache?limit=2", Body::empty());
+        let (status, _, body) = send(admin_router(handler.clone()), request).await;
+        assert_eq!(status, StatusCode::OK);
+        let listing: CacheListing = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listing.entries, 3);
+        assert_eq!(listing.top.len(), 2);
+        assert_eq!(listing.top[0].prompt, "what is rust");
+        assert_eq!((listing.top[0].page, listing.top[0].hits), (1, 1));
+        assert!(listing.top[0].ttl > 0 && listing.top[0].ttl <= 300);
+
+        let purge = |query: &str| {
+            let request = admin_request("DELETE", &format!("/admin/cache?{query}"), Body::empty());
+            send(admin_router(handler.clone()), request)
+        };
+        let (status, _, body) = purge("name=What-Is-Rust").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<PurgeResponse>(&body)
+                .unwrap()
+                .purged,
+            1
+        );
+
+        let (_, _, body) = purge("pattern=STORY%7Cjoke$").await;
+        assert_eq!(
+            serde_json::from_slice::<PurgeResponse>(&body)
+                .unwrap()
+                .purged,
+            2
+        );
+        assert!(handler.cache.is_empty().await);
+
+        let (status, _, _) = purge("pattern=(").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _, _) = purge("name=a.b.&prefix=a").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_admin_purge_reports_an_unreachable_shared_cache() {
+        // A port nothing listens on
+        let address = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+        let shared = RespCache::new(&format!("redis://{}", address)).unwrap();
+        let handler = Arc::new(
+            handler_with_llm_url("http://127.0.0.1:9".to_string())
+                .with_shared_cache(Arc::new(shared)),
+        );
+        let name = Name::from_utf8("what.is.rust.").unwrap();
+        cache_story(&handler, &name).await;
+
+        let request = admin_request("DELETE", "/admin/cache?name=what.is.rust", Body::empty());
+        let (status, _, body) = send(admin_router(handler.clone()), request).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let response: PurgeResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response.purged, 1);
+        assert!(response.shared_error.is_some());
+        assert!(handler.cache.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_admin_export_imports_into_another_server() {
+        let handler = test_handler();
+        let name = Name::from_utf8("tell.me.a.story.").unwrap();
+        cache_story(&handler, &name).await;
+
+        let request = admin_request("GET", "/admin/cache/export", Body::empty());
+        let (status, _, export) = send(admin_router(handler.clone()), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let other = test_handler();
+        let request = admin_request("POST", "/admin/cache/import", Body::from(export));
+        let (status, _, body) = send(admin_router(other.clone()), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<ImportResponse>(&body)
+                .unwrap()
+                .restored,
+            1
+        );
+
+        let key = other.cache_key(&name).unwrap();
+        assert_eq!(other.cache.get(&key.page(1)).await.unwrap().len(), 8);
+
+        let request = admin_request("POST", "/admin/cache/import", Body::from("not an export"));
+        let (status, _, _) = send(admin_router(other), request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -33,12 +33,16 @@
 //!
 //! # Usage
 //!
-//! Query the server using `dig`:
+//! Query the server using `dig`, below the zone set in `DNS_ZONE`
+//! (`llm.example.com` here):
 //!
 //! ```bash
-//! dig @localhost hello-world.llm.duyet.net TXT
+//! dig @localhost hello-world.llm.example.com TXT
 //! ```
 //!
+//! Without `DNS_ZONE`, the whole name is the prompt:
+//! `dig @localhost hello-world TXT`.
+//!
 //! The server will:
 //! 1. Parse the question from DNS subdomain labels
 //! 2. Call OpenRouter LLM API
@@ -53,16 +57,24 @@
 //! OPENROUTER_MODEL=nvidia/nemotron-nano-12b-v2-vl:free
 //! DNS_PORT=53
 //! DNS_ADDRESS=0.0.0.0
+//! DNS_ZONE=llm.example.com
 //! RUST_LOG=info
 //! ```
 //!
 //! # Module Organization
 //!
 //! - [`config`] - Configuration loading and validation
-//! - [`server`] - DNS server lifecycle management
+//! - [`server`] - DNS server lifecycle management (UDP, TCP and HTTPS listeners)
+//! - [`tls`] - Certificate loading and hot reload for DNS-over-TLS and DNS-over-HTTPS
+//! - [`zone`] - Authoritative zone (SOA/NS, negative answers) for delegated deployments
 //! - [`dns_handler`] - DNS query parsing and response building
+//! - [`upload`] - Multi-query upload sessions for prompts longer than a DNS name
+//! - [`ticket`] - Asynchronous tickets for prompts slower than a resolver waits
+//! - [`coalesce`] - Single-flight deduplication of identical in-flight LLM calls
+//! - [`snapshot`] - Cache snapshots on disk for a warm start after a restart
 //! - [`llm_client`] - OpenRouter API client with error handling
 //! - [`chunker`] - Text chunking utilities for DNS limitations
+//! - [`client`] - DNS client that asks prompts and reassembles answers
 //!
 //! # Examples
 //!
@@ -73,16 +85,26 @@
 
 pub mod cache;
 pub mod chunker;
+pub mod client;
+pub mod coalesce;
 pub mod config;
 pub mod dns_handler;
+mod http;
 pub mod llm_client;
 pub mod rate_limiter;
 pub mod server;
+pub mod snapshot;
+mod tcp;
+pub mod ticket;
+pub mod tls;
+pub mod upload;
+pub mod zone;
 
 pub use cache::DnsCache;
-pub use chunker::Chunker;
+pub use chunker::{Chunker, TxtFraming};
+pub use client::DnsClient;
 pub use config::Config;
-pub use dns_handler::DnsHandler;
-pub use llm_client::LlmClient;
+pub use dns_handler::{DnsHandler, PromptEncoding, PromptError};
+pub use llm_client::{LlmClient, LlmError};
 pub use rate_limiter::IpRateLimiter;
-pub use server::{LlmDnsHandler, Server};
+pub use server::{DeadlineReply, LlmBusy, LlmDnsHandler, PromptAnswer, Purged, Server};
@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tracing::{debug, error};
 
+use crate::cache::Fingerprint;
+
 /// Message in the OpenRouter API request
 #[derive(Debug, Clone, Serialize)]
 struct Message {
@@ -48,6 +50,63 @@ struct OpenRouterResponse {
     choices: Vec<Choice>,
 }
 
+/// A failure status returned by the LLM API
+///
+/// Transport failures (connection errors, timeouts, unparseable bodies) are
+/// reported as plain errors, since they say nothing about the prompt.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LlmError {
+    /// 400: the provider rejected the request itself
+    #[error("Bad request (400): {0}")]
+    BadRequest(String),
+    /// 401: the API key was refused
+    #[error("Unauthorized: Invalid API key (401)")]
+    Unauthorized,
+    /// 403: the prompt was flagged by the provider's moderation
+    #[error("Prompt flagged by moderation (403): {0}")]
+    ContentFiltered(String),
+    /// 404: the model does not exist or the account's data policy excludes it
+    #[error("Model not found or data policy restriction (404)")]
+    ModelNotFound,
+    /// 429: too many requests
+    #[error("Rate limit exceeded (429)")]
+    RateLimited,
+    /// 500: the provider failed
+    #[error("OpenRouter API server error (500)")]
+    ServerError,
+    /// Any other status
+    #[error("Unexpected status code {status}: {body}")]
+    UnexpectedStatus {
+        /// HTTP status
+        status: reqwest::StatusCode,
+        /// Response body
+        body: String,
+    },
+}
+
+impl LlmError {
+    /// Whether asking again later may succeed
+    ///
+    /// Only a rejected request, a moderation flag and a missing model are
+    /// answers about the prompt or model; everything else can clear up on
+    /// its own.
+    pub fn is_transient(&self) -> bool {
+        !matches!(
+            self,
+            Self::BadRequest(_) | Self::ContentFiltered(_) | Self::ModelNotFound
+        )
+    }
+}
+
+/// A completed LLM response along with the model that produced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LlmAnswer {
+    /// Model identifier from the fallback chain that answered
+    pub model: String,
+    /// Response text
+    pub content: String,
+}
+
 /// LLM client for querying the OpenRouter API with automatic model fallback
 #[derive(Debug, Clone)]
 pub struct LlmClient {
@@ -129,18 +188,53 @@ impl LlmClient {
         self
     }
 
+    /// Fingerprint of the model chain, system prompt and sampling parameters,
+    /// which together with the prompt decide the answer
+    pub fn fingerprint(&self) -> Fingerprint {
+        let sampling = format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?}",
+            self.temperature,
+            self.max_tokens,
+            self.top_p,
+            self.top_k,
+            self.frequency_penalty,
+            self.presence_penalty
+        );
+        Fingerprint::of(
+            self.models
+                .iter()
+                .map(String::as_str)
+                .chain([self.system_prompt.as_str(), sampling.as_str()]),
+        )
+    }
+
     /// Query the LLM with a prompt using automatic model fallback
     ///
     /// Tries each configured model in order until one succeeds. If a model fails
     /// due to rate limiting, data policy restrictions, or other errors, the next
     /// model in the list is tried automatically.
     ///
+    /// When every model fails, the error returned is the last transient one
+    /// (see [`LlmError::is_transient`]) if there was any, since a retry may get
+    /// past it, and otherwise the last error.
+    ///
     /// # Arguments
     /// * `prompt` - The user prompt to send to the LLM
     ///
     /// # Returns
     /// * `Result<String>` - The LLM response or error if all models fail
     pub async fn query(&self, prompt: &str) -> Result<String> {
+        Ok(self.query_with_model(prompt).await?.content)
+    }
+
+    /// Query the LLM like [`query`](Self::query), also reporting which model answered
+    ///
+    /// # Arguments
+    /// * `prompt` - The user prompt to send to the LLM
+    ///
+    /// # Returns
+    /// * `Result<LlmAnswer>` - The response and model, or error if all models fail
+    pub async fn query_with_model(&self, prompt: &str) -> Result<LlmAnswer> {
         if prompt.is_empty() {
             return Err(anyhow!("Prompt cannot be empty"));
         }
@@ -149,6 +243,7 @@ impl LlmClient {
         debug!("Available models for fallback: {:?}", self.models);
 
         let mut last_error = None;
+        let mut last_transient = None;
 
         // Try each model in order
         for (index, model) in self.models.iter().enumerate() {
@@ -160,13 +255,22 @@ impl LlmClient {
             );
 
             match self.query_single_model(prompt, model).await {
-                Ok(response) => {
+                Ok(content) => {
                     debug!("Successfully received response from model: {}", model);
-                    return Ok(response);
+                    return Ok(LlmAnswer {
+                        model: model.clone(),
+                        content,
+                    });
                 }
                 Err(e) => {
                     error!("Model {} failed: {}", model, e);
-                    last_error = Some(e);
+                    if e.downcast_ref::<LlmError>()
+                        .is_none_or(LlmError::is_transient)
+                    {
+                        last_transient = Some(e);
+                    } else {
+                        last_error = Some(e);
+                    }
 
                     // If there are more models to try, continue
                     if index < self.models.len() - 1 {
@@ -179,7 +283,9 @@ impl LlmClient {
         }
 
         // All models failed
-        Err(last_error.unwrap_or_else(|| anyhow!("All models failed without specific error")))
+        Err(last_transient
+            .or(last_error)
+            .unwrap_or_else(|| anyhow!("All models failed without specific error")))
     }
 
     /// Query a single specific model
@@ -248,23 +354,21 @@ impl LlmClient {
                 let content = body.choices[0].message.content.clone();
                 Ok(content)
             }
-            reqwest::StatusCode::TOO_MANY_REQUESTS => Err(anyhow!("Rate limit exceeded (429)")),
-            reqwest::StatusCode::NOT_FOUND => {
-                Err(anyhow!("Model not found or data policy restriction (404)"))
-            }
-            reqwest::StatusCode::INTERNAL_SERVER_ERROR => {
-                Err(anyhow!("OpenRouter API server error (500)"))
-            }
-            reqwest::StatusCode::UNAUTHORIZED => {
-                Err(anyhow!("Unauthorized: Invalid API key (401)"))
-            }
+            reqwest::StatusCode::TOO_MANY_REQUESTS => Err(LlmError::RateLimited.into()),
+            reqwest::StatusCode::NOT_FOUND => Err(LlmError::ModelNotFound.into()),
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR => Err(LlmError::ServerError.into()),
+            reqwest::StatusCode::UNAUTHORIZED => Err(LlmError::Unauthorized.into()),
             reqwest::StatusCode::BAD_REQUEST => {
                 let text = response.text().await.unwrap_or_default();
-                Err(anyhow!("Bad request (400): {}", text))
+                Err(LlmError::BadRequest(text).into())
             }
-            _ => {
+            reqwest::StatusCode::FORBIDDEN => {
                 let text = response.text().await.unwrap_or_default();
-                Err(anyhow!("Unexpected status code {}: {}", status, text))
+                Err(LlmError::ContentFiltered(text).into())
+            }
+            _ => {
+                let body = response.text().await.unwrap_or_default();
+                Err(LlmError::UnexpectedStatus { status, body }.into())
             }
         }
     }
@@ -294,6 +398,31 @@ mod tests {
         assert_eq!(client.system_prompt, "Test system prompt");
     }
 
+    #[test]
+    fn test_fingerprint_tracks_what_shapes_the_answer() {
+        let client = |models: &[&str], system_prompt: &str, temperature: Option<f32>| {
+            LlmClient::new(
+                "test_api_key".to_string(),
+                models.iter().map(|m| m.to_string()).collect(),
+                system_prompt.to_string(),
+                temperature,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .fingerprint()
+        };
+
+        let base = client(&["a", "b"], "Be brief", None);
+        assert_eq!(base, client(&["a", "b"], "Be brief", None));
+        assert_ne!(base, client(&["b", "a"], "Be brief", None));
+        assert_ne!(base, client(&["a", "b"], "Be verbose", None));
+        assert_ne!(base, client(&["a", "b"], "Be brief", Some(0.2)));
+    }
+
     #[test]
     fn test_llm_client_creation_empty_api_key() {
         let result = LlmClient::new(
@@ -783,6 +912,85 @@ mod tests {
         assert!(error.contains("service_unavailable"));
     }
 
+    #[tokio::test]
+    async fn test_moderation_flag_403() {
+        let mut server = mockito::Server::new_async().await;
+
+        let _mock = server
+            .mock("POST", mockito::Matcher::Regex(r"^/.*".to_string()))
+            .with_status(403)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"error": "input was flagged"}"#)
+            .create_async()
+            .await;
+
+        let client = LlmClient::new(
+            "test_key".to_string(),
+            vec!["test_model".to_string()],
+            "Test system prompt".to_string(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .expect("Failed to create client")
+        .with_base_url(server.url());
+
+        let error = client.query("Test prompt").await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<LlmError>(),
+            Some(&LlmError::ContentFiltered(
+                r#"{"error": "input was flagged"}"#.to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transient_failure_outranks_later_permanent_one() {
+        let mut server = mockito::Server::new_async().await;
+
+        // The first model is only rate limited; the second does not exist
+        let _mock1 = server
+            .mock("POST", mockito::Matcher::Regex(r"^/.*".to_string()))
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"model": "model1"}),
+            ))
+            .with_status(429)
+            .create_async()
+            .await;
+        let _mock2 = server
+            .mock("POST", mockito::Matcher::Regex(r"^/.*".to_string()))
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"model": "model2"}),
+            ))
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let client = LlmClient::new(
+            "test_key".to_string(),
+            vec!["model1".to_string(), "model2".to_string()],
+            "Test system prompt".to_string(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .expect("Failed to create client")
+        .with_base_url(server.url());
+
+        // Asking again later may succeed, so the failure must not look final
+        let error = client.query("Test prompt").await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<LlmError>(),
+            Some(&LlmError::RateLimited)
+        );
+    }
+
     #[tokio::test]
     async fn test_fallback_to_second_model() {
         let mut server = mockito::Server::new_async().await;
@@ -911,6 +1119,11 @@ mod tests {
         let result = client.query("Test prompt").await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "Success from model3");
+
+        // The answering model is reported, not the first one tried.
+        let answer = client.query_with_model("Test prompt").await.unwrap();
+        assert_eq!(answer.model, "model3");
+        assert_eq!(answer.content, "Success from model3");
     }
 
     #[tokio::test]
@@ -19,6 +19,25 @@ fn mask_api_key(key: &str) -> String {
     }
 }
 
+/// Waits for Ctrl+C, or SIGTERM as sent by `docker stop` and systemd, and
+/// names the signal
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            return tokio::select! {
+                _ = tokio::signal::ctrl_c() => "Ctrl+C",
+                _ = terminate.recv() => "SIGTERM",
+            };
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl+C"
+}
+
 /// Main async entry point
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -80,6 +99,7 @@ async fn main() -> Result<()> {
     info!("Note: DNS queries are sent directly to the LLM (no domain parsing)");
     info!("Tip: Use +time=30 to increase dig timeout (LLM calls can take 5-15 seconds)");
     info!("Tip: Add +short to show only TXT record content without DNS metadata");
+    info!("Tip: Add +tcp for long answers that would be truncated over UDP");
     info!("");
 
     info!("=== Server Ready ===");
@@ -88,7 +108,7 @@ async fn main() -> Result<()> {
 
     // Spawn server task
     let server_clone = server.clone();
-    let server_task = tokio::spawn(async move {
+    let mut server_task = tokio::spawn(async move {
         info!("Server task starting...");
         match server_clone.start().await {
             Ok(_) => {
@@ -102,9 +122,9 @@ async fn main() -> Result<()> {
         }
     });
 
-    // Wait for either Ctrl+C or server task to complete/fail
+    // Wait for Ctrl+C, SIGTERM or the server task to complete/fail
     tokio::select! {
-        result = server_task => {
+        result = &mut server_task => {
             match result {
                 Ok(Ok(_)) => {
                     info!("Server stopped normally");
@@ -119,10 +139,14 @@ async fn main() -> Result<()> {
                 }
             }
         }
-        _ = tokio::signal::ctrl_c() => {
-            info!("Received shutdown signal (Ctrl+C)");
+        signal = shutdown_signal() => {
+            info!("Received shutdown signal ({})", signal);
             // Trigger graceful shutdown
             server.shutdown()?;
+            // Let the server finish up (such as saving the cache snapshot)
+            if let Ok(Err(e)) = server_task.await {
+                error!("Server error during shutdown: {:?}", e);
+            }
             info!("Server shutdown complete");
         }
     }
@@ -1,14 +1,47 @@
-use std::collections::HashMap;
-use std::net::IpAddr;
-use std::sync::Mutex;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
+use crate::cache::lru::LruList;
+
+/// Most shards the buckets are split into
+const MAX_SHARDS: usize = 16;
+
+/// Buckets a shard should track before the map is split further
+const MIN_SHARD_CLIENTS: usize = 256;
+
+/// Buckets tracked at most, unless [`IpRateLimiter::with_max_clients`] says
+/// otherwise
+pub const DEFAULT_MAX_CLIENTS: usize = 100_000;
+
 /// A thread-safe Token Bucket rate limiter for IP addresses.
+///
+/// Clients are bucketed by network prefix rather than by address: an IPv6
+/// host is usually handed a whole /64, so per-address buckets would give it
+/// billions of fresh allowances. IPv6 addresses share a bucket per /64 and
+/// IPv4 addresses get one each, unless [`IpRateLimiter::with_prefixes`] says
+/// otherwise.
+///
+/// Buckets are spread over independently locked shards, and their number is
+/// capped, so a flood from spoofed sources cannot grow the map without bound
+/// between [`IpRateLimiter::cleanup`] runs. Each shard keeps its buckets in
+/// the order their clients were last seen, and a full shard forgets the
+/// longest idle one, which has had the longest to refill. A drained bucket
+/// is never forgotten, or a client could reset its own limit by spoofing;
+/// while even the longest idle bucket in a shard is drained, new clients
+/// hashed to it are refused.
 #[derive(Debug)]
 pub struct IpRateLimiter {
-    clients: Mutex<HashMap<IpAddr, TokenBucket>>,
+    shards: Box<[Mutex<LruList<TokenBucket, IpAddr>>]>,
+    hasher: RandomState,
+    /// Buckets each shard tracks at most. 0 means unbounded.
+    shard_capacity: usize,
     max_tokens: f64,
     refill_rate: f64, // tokens per second
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
 }
 
 #[derive(Debug, Clone)]
@@ -24,10 +57,39 @@ impl IpRateLimiter {
     /// * `burst_limit` - Maximum burst allowed (e.g., 10.0).
     pub fn new(refill_rate: f64, burst_limit: f64) -> Self {
         Self {
-            clients: Mutex::new(HashMap::new()),
+            shards: Box::new([]),
+            hasher: RandomState::new(),
+            shard_capacity: 0,
             max_tokens: burst_limit,
             refill_rate,
+            ipv4_prefix: 32,
+            ipv6_prefix: 64,
         }
+        .with_max_clients(DEFAULT_MAX_CLIENTS)
+    }
+
+    /// Buckets IPv4 clients by their first `ipv4` bits and IPv6 clients by
+    /// their first `ipv6` bits.
+    ///
+    /// Prefixes longer than the address are capped at its length, so `32`
+    /// and `128` give every address its own bucket.
+    pub fn with_prefixes(mut self, ipv4: u8, ipv6: u8) -> Self {
+        self.ipv4_prefix = ipv4.min(32);
+        self.ipv6_prefix = ipv6.min(128);
+        self
+    }
+
+    /// Caps the buckets tracked at once; 0 leaves them unbounded.
+    ///
+    /// Any buckets already tracked are forgotten.
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        let count = match max_clients {
+            0 => MAX_SHARDS,
+            max => (max / MIN_SHARD_CLIENTS).clamp(1, MAX_SHARDS),
+        };
+        self.shards = (0..count).map(|_| Mutex::new(LruList::default())).collect();
+        self.shard_capacity = max_clients.div_ceil(count);
+        self
     }
 
     /// Check if a request from the given IP address is allowed.
@@ -40,12 +102,26 @@ impl IpRateLimiter {
         }
 
         let now = Instant::now();
-        let mut clients = self.clients.lock().unwrap();
+        let client = self.client(ip);
+        let mut clients = self.shard(&client);
 
-        let bucket = clients.entry(ip).or_insert_with(|| TokenBucket {
-            tokens: self.max_tokens,
-            last_update: now,
-        });
+        let bucket = match clients.get_mut(&client) {
+            Some(bucket) => bucket,
+            None => {
+                if self.shard_capacity > 0
+                    && clients.len() >= self.shard_capacity
+                    && !self.make_room(&mut clients, now)
+                {
+                    return false;
+                }
+                let bucket = TokenBucket {
+                    tokens: self.max_tokens,
+                    last_update: now,
+                };
+                clients.insert(client, bucket);
+                clients.get_mut(&client).expect("bucket was just inserted")
+            }
+        };
 
         // Refill tokens based on time elapsed
         let elapsed = now.duration_since(bucket.last_update).as_secs_f64();
@@ -62,9 +138,65 @@ impl IpRateLimiter {
 
     /// Cleans up old inactive IP buckets to prevent memory leaks.
     pub fn cleanup(&self, inactive_duration: Duration) {
-        let mut clients = self.clients.lock().unwrap();
         let now = Instant::now();
-        clients.retain(|_, bucket| now.duration_since(bucket.last_update) < inactive_duration);
+        for shard in self.shards.iter() {
+            shard
+                .lock()
+                .unwrap()
+                .retain(|_, bucket| now.duration_since(bucket.last_update) < inactive_duration);
+        }
+    }
+
+    /// Number of buckets tracked
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The network `ip` is bucketed under
+    ///
+    /// IPv4 addresses reaching a dual-stack socket as `::ffff:a.b.c.d` count
+    /// as IPv4.
+    fn client(&self, ip: IpAddr) -> IpAddr {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.ipv4_prefix))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.ipv6_prefix))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        }
+    }
+
+    fn shard(&self, client: &IpAddr) -> MutexGuard<'_, LruList<TokenBucket, IpAddr>> {
+        let index = self.hasher.hash_one(client) as usize % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+
+    /// Frees a slot in a full shard by forgetting its longest idle bucket,
+    /// returning false when that bucket is drained
+    fn make_room(&self, clients: &mut LruList<TokenBucket, IpAddr>, now: Instant) -> bool {
+        let Some((_, bucket)) = clients.peek_lru() else {
+            return false;
+        };
+        let elapsed = now.duration_since(bucket.last_update).as_secs_f64();
+        if bucket.tokens + elapsed * self.refill_rate < 1.0 {
+            return false;
+        }
+        clients.pop_lru();
+        true
     }
 }
 
@@ -73,6 +205,11 @@ mod tests {
     use super::*;
     use std::net::Ipv4Addr;
 
+    fn tracks(limiter: &IpRateLimiter, ip: IpAddr) -> bool {
+        let client = limiter.client(ip);
+        limiter.shard(&client).peek(&client).is_some()
+    }
+
     #[test]
     fn test_rate_limiter_allows_burst() {
         let ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
@@ -149,9 +286,86 @@ mod tests {
         limiter.cleanup(Duration::from_millis(100));
 
         // Total clients should be 1 (only ip2 remains active/recent)
-        let clients = limiter.clients.lock().unwrap();
-        assert_eq!(clients.len(), 1);
-        assert!(clients.contains_key(&ip2));
-        assert!(!clients.contains_key(&ip1));
+        assert_eq!(limiter.len(), 1);
+        assert!(tracks(&limiter, ip2));
+        assert!(!tracks(&limiter, ip1));
+    }
+
+    #[test]
+    fn test_rate_limiter_buckets_by_prefix() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let limiter = IpRateLimiter::new(1.0, 1.0);
+
+        // One /64 is one client, however many addresses it rotates through
+        assert!(limiter.check_allowed(ip("2001:db8:1:2::1")));
+        assert!(!limiter.check_allowed(ip("2001:db8:1:2:ffff:ffff:ffff:ffff")));
+        assert!(limiter.check_allowed(ip("2001:db8:1:3::1")));
+
+        // IPv4 addresses are their own clients by default, mapped or not
+        assert!(limiter.check_allowed(ip("192.0.2.1")));
+        assert!(!limiter.check_allowed(ip("::ffff:192.0.2.1")));
+        assert!(limiter.check_allowed(ip("192.0.2.2")));
+
+        let limiter = IpRateLimiter::new(1.0, 1.0).with_prefixes(24, 128);
+        assert!(limiter.check_allowed(ip("192.0.2.1")));
+        assert!(!limiter.check_allowed(ip("192.0.2.200")));
+        assert!(limiter.check_allowed(ip("192.0.3.1")));
+        assert!(limiter.check_allowed(ip("2001:db8::1")));
+        assert!(limiter.check_allowed(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn test_flood_past_the_cap_evicts_in_constant_time() {
+        let limiter = IpRateLimiter::new(1.0, 2.0);
+        let flood = 3 * DEFAULT_MAX_CLIENTS as u32;
+
+        // Each new source past the cap forgets one bucket rather than
+        // scanning a shard, so this takes well under a second
+        let start = Instant::now();
+        for n in 0..flood {
+            assert!(limiter.check_allowed(IpAddr::V4(Ipv4Addr::from(n))));
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(limiter.len(), DEFAULT_MAX_CLIENTS);
+        assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+
+        // The newest sources are the ones kept
+        assert!(tracks(&limiter, IpAddr::V4(Ipv4Addr::from(flood - 1))));
+        assert!(!tracks(&limiter, IpAddr::V4(Ipv4Addr::from(0))));
+    }
+
+    #[test]
+    fn test_rate_limiter_caps_tracked_buckets() {
+        let limiter = IpRateLimiter::new(1.0, 2.0).with_max_clients(100);
+
+        let limited = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(limiter.check_allowed(limited));
+        assert!(limiter.check_allowed(limited));
+        assert!(!limiter.check_allowed(limited));
+
+        // A flood of spoofed sources cannot grow the map past the cap
+        for n in 0..10_000u32 {
+            limiter.check_allowed(IpAddr::V4(Ipv4Addr::from(0xC000_0000 + n)));
+        }
+        assert_eq!(limiter.len(), 100);
+        assert!(!limiter.check_allowed(limited));
+
+        // A shard of drained buckets turns new clients away
+        let drained = IpRateLimiter::new(1.0, 1.0).with_max_clients(1);
+        assert!(drained.check_allowed(limited));
+        assert!(!drained.check_allowed(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+        assert!(!drained.check_allowed(limited));
+        assert_eq!(drained.len(), 1);
+
+        // ...until the longest idle one has refilled a token
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(drained.check_allowed(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+        assert!(!tracks(&drained, limited));
+
+        let unbounded = IpRateLimiter::new(1.0, 2.0).with_max_clients(0);
+        for n in 0..1_000u32 {
+            unbounded.check_allowed(IpAddr::V4(Ipv4Addr::from(n)));
+        }
+        assert_eq!(unbounded.len(), 1_000);
     }
 }
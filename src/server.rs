@@ -24,19 +24,96 @@
 //! # }
 //! ```
 
-use anyhow::{Context, Result};
-use hickory_server::proto::op::{Message, MessageType, OpCode, ResponseCode};
+use anyhow::{anyhow, Context, Result};
+use hickory_server::proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
 use hickory_server::proto::rr::rdata::TXT;
 use hickory_server::proto::rr::{Name, RData, Record, RecordType};
+use std::collections::HashSet;
 use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tokio::sync::{broadcast, Semaphore};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
 
-use crate::{Chunker, Config, DnsCache, DnsHandler, IpRateLimiter, LlmClient};
+use crate::cache::{
+    key_parts, normalize_prompt, CacheBackend, CacheKey, FailureKind, Fingerprint, NegativeTtls,
+    RespCache, SimilarPrompts, TtlPolicy, STALE_TTL,
+};
+use crate::coalesce::{FlightStats, SingleFlight};
+use crate::dns_handler::PromptError;
+use crate::snapshot::CacheSnapshot;
+use crate::ticket::{TicketError, TicketQuery, TicketState, TicketStore};
+use crate::tls::{ReloadingCertResolver, CERT_RELOAD_INTERVAL, DOH_ALPN, DOT_ALPN};
+use crate::upload::{UploadError, UploadQuery, UploadSessions};
+use crate::zone::{Zone, ZoneMatch};
+use crate::{http, tcp, Chunker, Config, DnsCache, DnsHandler, IpRateLimiter, LlmClient, LlmError};
 use std::time::Duration;
 
+/// Shortest time the later pages of a multi-page answer are held, so a
+/// client walking them gets one answer instead of an LLM call per page
+///
+/// The records keep the answer's own TTL, so resolvers hold them no longer
+/// than the TTL policy allows. The first page is held like any other answer,
+/// so a prompt the TTL policy does not cache is still asked anew.
+const MIN_PAGE_HOLD: Duration = Duration::from_secs(30);
+
+/// How long an answer that missed the response deadline waits for the retry,
+/// if the response cache does not keep it
+const LATE_ANSWER_HOLD: Duration = Duration::from_secs(30);
+
+/// Most answers waiting for a retry at once
+const MAX_LATE_ANSWERS: usize = 1024;
+
+/// Returned when a query is shed because every LLM permit is in use
+///
+/// Callers that can say "try again later" more precisely than SERVFAIL (such
+/// as the HTTP API) detect it with `anyhow::Error::is::<LlmBusy>()`.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("LLM concurrency limit reached")]
+pub struct LlmBusy;
+
+/// What a query that misses the response deadline is answered with
+///
+/// Either way the LLM call keeps running and its answer is cached, so the
+/// resolver's retry of the same name is answered from the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeadlineReply {
+    /// SERVFAIL, which resolvers retry on their own
+    #[default]
+    ServFail,
+    /// A `thinking` TXT record with a TTL of 0, for clients that re-ask
+    Thinking,
+}
+
+impl FromStr for DeadlineReply {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "servfail" => Ok(Self::ServFail),
+            "thinking" => Ok(Self::Thinking),
+            other => Err(anyhow!(
+                "Unknown deadline reply '{}' (expected servfail or thinking)",
+                other
+            )),
+        }
+    }
+}
+
+/// A prompt answered outside DNS, with the detail TXT records cannot carry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptAnswer {
+    /// Full response text
+    pub answer: String,
+    /// Model from the fallback chain that answered
+    pub model: String,
+    /// The TXT strings of every page DNS would serve the response in
+    pub chunks: Vec<String>,
+}
+
 /// DNS query handler that integrates with LLM
 ///
 /// This handler processes DNS TXT queries by:
@@ -44,6 +121,11 @@ use std::time::Duration;
 /// 2. Querying the LLM with the extracted prompt
 /// 3. Chunking the response into DNS-compliant TXT records
 /// 4. Building and returning DNS records
+///
+/// Every field is shared, so clones are cheap and serve the same state; the
+/// ticket flow clones the handler into the task that answers in the
+/// background.
+#[derive(Clone)]
 pub struct LlmDnsHandler {
     llm_client: Arc<LlmClient>,
     chunker: Arc<Chunker>,
@@ -51,6 +133,124 @@ pub struct LlmDnsHandler {
     pub cache: Arc<DnsCache>,
     /// Global ceiling on concurrent LLM calls. `None` disables the limit.
     llm_permits: Option<Arc<Semaphore>>,
+    /// Delegated zone. `None` treats every query name as a prompt.
+    zone: Option<Arc<Zone>>,
+    /// Read hyphens in query labels as spaces.
+    hyphens_as_spaces: bool,
+    /// Multi-query upload sessions. `None` disables uploads.
+    uploads: Option<Arc<UploadSessions>>,
+    /// Asynchronous tickets for slow prompts. `None` disables tickets.
+    tickets: Option<Arc<TicketStore>>,
+    /// How long a query waits for its answer. `None` waits for the LLM.
+    response_deadline: Option<Duration>,
+    /// Reply to a query that misses the deadline.
+    deadline_reply: DeadlineReply,
+    /// Uncached answers that missed the deadline, by page key, until the
+    /// retry collects them.
+    late_answers: Arc<DnsCache>,
+    /// LLM calls in progress, keyed on the answer's cache key.
+    flights: Arc<SingleFlight<SharedAnswer>>,
+    /// HTTP API calls in progress, keyed like `flights`.
+    asks: Arc<SingleFlight<SharedPromptAnswer>>,
+    /// What besides the prompt shapes an answer, part of every cache key.
+    fingerprint: Fingerprint,
+    /// Cache shared with other replicas, behind `cache`. `None` keeps
+    /// answers to this process.
+    shared_cache: Option<Arc<dyn CacheBackend>>,
+    /// Shorter lifetimes for answers to time-sensitive prompts.
+    ttl_policy: Arc<TtlPolicy>,
+    /// Cached prompts by similarity. `None` serves exact matches only.
+    similar: Option<Arc<SimilarPrompts>>,
+    /// Queries answered from a cache, by how they matched.
+    hits: Arc<HitCounters>,
+}
+
+/// Queries answered from a cache, by how the answer was found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheHitStats {
+    /// Answers cached under the query's own key
+    pub exact: u64,
+    /// Answers another replica cached under the query's own key
+    pub shared: u64,
+    /// Answers to a similar prompt (see [`SimilarPrompts`])
+    pub similar: u64,
+}
+
+#[derive(Debug, Default)]
+struct HitCounters {
+    exact: AtomicU64,
+    shared: AtomicU64,
+    similar: AtomicU64,
+}
+
+impl HitCounters {
+    fn stats(&self) -> CacheHitStats {
+        CacheHitStats {
+            exact: self.exact.load(Ordering::Relaxed),
+            shared: self.shared.load(Ordering::Relaxed),
+            similar: self.similar.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// What an admin purge removed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Purged {
+    /// Pages removed from this replica's memory
+    pub pages: usize,
+    /// Why pages may be left in the shared cache, if they may
+    pub shared_error: Option<String>,
+}
+
+/// The pages of an answer, or why there is none, as shared between the
+/// queries waiting on one LLM call
+type SharedAnswer = std::result::Result<Arc<Vec<Vec<Record>>>, Arc<anyhow::Error>>;
+
+/// A whole answer for the HTTP API, or why there is none, as shared between
+/// the requests waiting on one LLM call
+type SharedPromptAnswer = std::result::Result<PromptAnswer, Arc<anyhow::Error>>;
+
+/// The answer to one question, before it is placed in a response message
+#[derive(Debug, Clone)]
+pub(crate) struct QueryOutcome {
+    pub response_code: ResponseCode,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+}
+
+impl QueryOutcome {
+    fn answer(answers: Vec<Record>) -> Self {
+        Self {
+            response_code: ResponseCode::NoError,
+            answers,
+            authorities: Vec::new(),
+        }
+    }
+
+    fn error(response_code: ResponseCode) -> Self {
+        Self {
+            response_code,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+        }
+    }
+
+    /// NODATA: the name exists but has no records of the requested type.
+    fn no_data(zone: &Zone) -> Self {
+        Self {
+            response_code: ResponseCode::NoError,
+            answers: Vec::new(),
+            authorities: vec![zone.negative_soa_record()],
+        }
+    }
+
+    fn nx_domain(zone: &Zone) -> Self {
+        Self {
+            response_code: ResponseCode::NXDomain,
+            answers: Vec::new(),
+            authorities: vec![zone.negative_soa_record()],
+        }
+    }
 }
 
 impl LlmDnsHandler {
@@ -69,11 +269,25 @@ impl LlmDnsHandler {
         cache: Arc<DnsCache>,
     ) -> Self {
         Self {
+            fingerprint: llm_client.fingerprint(),
             llm_client,
             chunker,
             dns_handler,
             cache,
             llm_permits: None,
+            zone: None,
+            hyphens_as_spaces: false,
+            uploads: None,
+            tickets: None,
+            response_deadline: None,
+            deadline_reply: DeadlineReply::default(),
+            late_answers: Arc::new(DnsCache::with_capacity(LATE_ANSWER_HOLD, MAX_LATE_ANSWERS)),
+            flights: Arc::new(SingleFlight::new()),
+            asks: Arc::new(SingleFlight::new()),
+            shared_cache: None,
+            ttl_policy: Arc::new(TtlPolicy::default()),
+            similar: None,
+            hits: Arc::new(HitCounters::default()),
         }
     }
 
@@ -87,705 +301,3175 @@ impl LlmDnsHandler {
         self
     }
 
-    /// Processes a single DNS query and returns DNS records
+    /// Makes the handler authoritative for a delegated zone.
     ///
-    /// # Arguments
+    /// The origin is stripped from query names before prompting, SOA and NS
+    /// are answered at the apex, and names outside the zone are refused.
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.zone = Some(Arc::new(zone));
+        self
+    }
+
+    /// Reads hyphens in query labels as spaces, so `what-is-rust` asks
+    /// "what is rust".
     ///
-    /// * `query_name` - The DNS name from the query
+    /// Off by default, since hyphens are also part of ordinary words.
+    pub fn with_hyphens_as_spaces(mut self, enabled: bool) -> Self {
+        self.hyphens_as_spaces = enabled;
+        self
+    }
+
+    /// Accepts prompts uploaded over several queries (see [`crate::upload`]).
+    pub fn with_uploads(mut self, uploads: UploadSessions) -> Self {
+        self.uploads = Some(Arc::new(uploads));
+        self
+    }
+
+    /// Answers `ask.<prompt>` with a ticket at once and the prompt later at
+    /// `<ticket>.result` (see [`crate::ticket`]).
+    pub fn with_tickets(mut self, tickets: TicketStore) -> Self {
+        self.tickets = Some(Arc::new(tickets));
+        self
+    }
+
+    /// Shares answers with other replicas through `backend` (see
+    /// [`CacheBackend`]).
     ///
-    /// # Returns
+    /// A miss in this process's cache is looked up there before asking the
+    /// LLM, and every new answer is written there too.
+    pub fn with_shared_cache(mut self, backend: Arc<dyn CacheBackend>) -> Self {
+        self.shared_cache = Some(backend);
+        self
+    }
+
+    /// Gives answers to prompts matching `policy` a shorter lifetime in the
+    /// cache and in their record TTL; a lifetime of 0 is not cached at all.
+    pub fn with_ttl_policy(mut self, policy: TtlPolicy) -> Self {
+        self.ttl_policy = Arc::new(policy);
+        self
+    }
+
+    /// Answers a prompt missing the cache with the cached answer to a prompt
+    /// spelled similarly enough (see [`SimilarPrompts`]).
     ///
-    /// Vector of DNS records containing the chunked LLM response
+    /// Only prompts asked by name take part; uploads are always asked.
+    pub fn with_similar_prompts(mut self, similar: SimilarPrompts) -> Self {
+        self.similar = Some(Arc::new(similar));
+        self
+    }
+
+    /// Answers queries that outlast `deadline` with `reply` and finishes
+    /// them in the background.
     ///
-    /// # Errors
+    /// Resolvers abandon an upstream after a few seconds, well inside an LLM
+    /// call. Past the deadline the call keeps running and fills the cache, so
+    /// the retry of the same name gets the answer. A zero deadline waits for
+    /// the LLM.
+    pub fn with_response_deadline(mut self, deadline: Duration, reply: DeadlineReply) -> Self {
+        self.response_deadline = (!deadline.is_zero()).then_some(deadline);
+        self.deadline_reply = reply;
+        self
+    }
+
+    /// Answers one question according to the zone, if any
     ///
-    /// Returns error if:
-    /// - Subdomain parsing fails
-    /// - LLM API call fails
-    /// - Response chunking fails
-    pub async fn process_query(&self, query_name: &Name) -> Result<Vec<Record>> {
-        // Extract the query domain from the DNS name
-        let query_str = query_name.to_utf8();
-        debug!("Raw query string: {}", query_str);
+    /// Without a zone every name is a prompt and only TXT is implemented.
+    /// With one, the server behaves as an authoritative nameserver for it.
+    pub(crate) async fn answer(&self, query: &Query) -> QueryOutcome {
+        let name = query.name();
+        let query_type = query.query_type();
+
+        let Some(zone) = self.zone.as_deref() else {
+            if query_type != RecordType::TXT {
+                warn!("Unsupported query type {:?} for {}", query_type, name);
+                return QueryOutcome::error(ResponseCode::NotImp);
+            }
+            return self.answer_prompt(name).await;
+        };
 
-        // Check cache first
-        if let Some(cached_records) = self.cache.get(&query_str).await {
-            info!("Cache hit for query '{}'", query_str);
-            return Ok(cached_records);
+        match zone.classify(name) {
+            ZoneMatch::Outside => {
+                debug!("Refusing {} outside zone {}", name, zone.origin());
+                QueryOutcome::error(ResponseCode::Refused)
+            }
+            ZoneMatch::Apex => match query_type {
+                RecordType::SOA => QueryOutcome::answer(vec![zone.soa_record()]),
+                RecordType::NS => QueryOutcome::answer(zone.ns_records()),
+                _ => QueryOutcome::no_data(zone),
+            },
+            ZoneMatch::Reserved => QueryOutcome::nx_domain(zone),
+            // Every name below the apex is a prompt, so it exists, but only
+            // with TXT records.
+            ZoneMatch::Subdomain if query_type == RecordType::TXT => self.answer_prompt(name).await,
+            ZoneMatch::Subdomain => QueryOutcome::no_data(zone),
         }
+    }
 
-        // Parse subdomain to get the prompt
-        let prompt = self.dns_handler.parse_subdomain(&query_str)?;
-        debug!("Parsed prompt: {}", prompt);
-
-        // Take a permit before spending money. try_acquire sheds load rather
-        // than queueing: a spoofed-source flood would otherwise park hundreds of
-        // thousands of tasks waiting on the semaphore, which is the exhaustion
-        // we are trying to prevent. The client sees SERVFAIL and may retry.
-        let _permit = match self.llm_permits.as_ref() {
-            Some(sem) => match sem.clone().try_acquire_owned() {
-                Ok(permit) => Some(permit),
-                Err(_) => {
-                    warn!("LLM concurrency limit reached, shedding query '{}'", prompt);
-                    anyhow::bail!("LLM concurrency limit reached");
-                }
-            },
-            None => None,
+    /// Answers a prompt query, within the response deadline if one is set
+    async fn answer_prompt(&self, name: &Name) -> QueryOutcome {
+        let Some(deadline) = self.response_deadline else {
+            return self.resolve_prompt(name).await;
         };
 
-        // Query the LLM with the prompt
-        let response_text = self.llm_client.query(&prompt).await?;
-        debug!("LLM response length: {}", response_text.len());
+        let handler = self.clone();
+        let task_name = name.clone();
+        let mut task = tokio::spawn(async move { handler.resolve_prompt(&task_name).await });
 
-        // Chunk the response for DNS TXT records
-        let chunks = self.chunker.chunk_text(&response_text);
-        debug!("Chunked into {} parts", chunks.len());
+        match tokio::time::timeout(deadline, &mut task).await {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(e)) => {
+                error!("Query task for {} failed: {}", name, e);
+                QueryOutcome::error(ResponseCode::ServFail)
+            }
+            // The task keeps running; a retry joins its LLM call, or collects
+            // the answer once the call has landed
+            Err(_) => {
+                info!("Answer for {} missed the {:?} deadline", name, deadline);
+                self.hold_late_answer(name.clone(), task);
+                match self.stale_answer(name).await {
+                    Some(records) => QueryOutcome::answer(records),
+                    None => self.late_reply(name),
+                }
+            }
+        }
+    }
 
-        // Build TXT records from chunks
-        let mut records = Vec::new();
+    /// Holds the answer `task` finds for `name` for [`LATE_ANSWER_HOLD`],
+    /// unless the response cache keeps it anyway
+    fn hold_late_answer(&self, name: Name, task: JoinHandle<QueryOutcome>) {
+        let handler = self.clone();
+        tokio::spawn(async move {
+            let Ok(outcome) = task.await else { return };
+            // Answers with a TTL are in the response cache already
+            let uncached = outcome.answers.iter().any(|record| record.ttl == 0);
+            if outcome.response_code != ResponseCode::NoError || !uncached {
+                return;
+            }
+            if let Some(key) = handler.page_key(&name) {
+                debug!("Holding late answer for {} until the retry", name);
+                handler.late_answers.insert(&key, outcome.answers).await;
+            }
+        });
+    }
 
-        for (index, chunk) in chunks.iter().enumerate() {
-            let txt_record = TXT::new(vec![chunk.clone()]);
+    /// The reply for a query whose answer is not ready by the deadline
+    fn late_reply(&self, name: &Name) -> QueryOutcome {
+        match self.deadline_reply {
+            DeadlineReply::ServFail => QueryOutcome::error(ResponseCode::ServFail),
+            // A TTL of 0 keeps resolvers from caching it over the real answer
+            DeadlineReply::Thinking => QueryOutcome::answer(vec![Record::from_rdata(
+                name.clone(),
+                0,
+                RData::TXT(TXT::new(vec![THINKING.to_string()])),
+            )]),
+        }
+    }
 
-            let record = Record::from_rdata(
-                query_name.clone(),
-                300, // TTL in seconds
-                RData::TXT(txt_record),
-            );
+    /// Answers a prompt query however long it takes
+    async fn resolve_prompt(&self, name: &Name) -> QueryOutcome {
+        match self.process_query(name).await {
+            Ok(records) => {
+                debug!("Adding {} answer records", records.len());
+                QueryOutcome::answer(records)
+            }
+            Err(e) => {
+                if let Some(err) = e.downcast_ref::<UploadError>() {
+                    return self.reject_upload(name, err);
+                }
+                if let Some(err) = e.downcast_ref::<TicketError>() {
+                    return self.reject_ticket(name, err);
+                }
+                if let Some(kind) = FailureKind::of(&e) {
+                    warn!("LLM failed for {}: {}", name, e);
+                    return QueryOutcome::error(kind.response_code());
+                }
 
-            records.push(record);
-            debug!("Created TXT record {}: {} bytes", index + 1, chunk.len());
+                match e.downcast_ref::<PromptError>() {
+                    Some(PromptError::PageOutOfRange { .. } | PromptError::PageExpired(_)) => {
+                        debug!("No such page {}: {}", name, e);
+                        self.no_such_name()
+                    }
+                    // An undecodable name is the client's mistake, not ours.
+                    Some(_) => {
+                        debug!("Malformed prompt in {}: {}", name, e);
+                        QueryOutcome::error(ResponseCode::FormErr)
+                    }
+                    None => {
+                        warn!("Failed to process query for {}: {}", name, e);
+                        QueryOutcome::error(ResponseCode::ServFail)
+                    }
+                }
+            }
         }
+    }
 
-        // Cache the records
-        self.cache.insert(&query_str, records.clone()).await;
+    /// NXDOMAIN, carrying the SOA when authoritative for a zone
+    fn no_such_name(&self) -> QueryOutcome {
+        match self.zone.as_deref() {
+            Some(zone) => QueryOutcome::nx_domain(zone),
+            None => QueryOutcome::error(ResponseCode::NXDomain),
+        }
+    }
 
-        info!(
-            "Successfully processed query '{}': {} chunks",
-            prompt,
-            records.len()
-        );
-        Ok(records)
+    /// Maps a rejected upload query to the closest response code
+    fn reject_upload(&self, name: &Name, err: &UploadError) -> QueryOutcome {
+        debug!("Rejected upload query {}: {}", name, err);
+        match err {
+            // Expired or never opened: the name no longer exists.
+            UploadError::UnknownSession(_) => self.no_such_name(),
+            UploadError::TooManySessions => QueryOutcome::error(ResponseCode::Refused),
+            _ => QueryOutcome::error(ResponseCode::FormErr),
+        }
     }
-}
 
-/// Main DNS server with LLM integration
-///
-/// Manages the complete server lifecycle including:
-/// - UDP socket binding and management
-/// - Request handling and routing
-/// - Graceful shutdown coordination
-/// - Resource cleanup
-pub struct Server {
-    config: Config,
-    handler: Arc<LlmDnsHandler>,
-    rate_limiter: Arc<IpRateLimiter>,
-    shutdown_tx: broadcast::Sender<()>,
-}
+    /// Maps a rejected ticket query to the closest response code
+    fn reject_ticket(&self, name: &Name, err: &TicketError) -> QueryOutcome {
+        debug!("Rejected ticket query {}: {}", name, err);
+        match err {
+            TicketError::UnknownTicket(_) | TicketError::Failed(ResponseCode::NXDomain) => {
+                self.no_such_name()
+            }
+            TicketError::TooManyTickets => QueryOutcome::error(ResponseCode::Refused),
+            TicketError::Failed(code) => QueryOutcome::error(*code),
+        }
+    }
 
-impl Server {
-    /// Creates a new DNS server with the provided configuration
+    /// Processes a single DNS query and returns DNS records
     ///
     /// # Arguments
     ///
-    /// * `config` - Server configuration including DNS address/port and LLM settings
+    /// * `query_name` - The DNS name from the query
     ///
     /// # Returns
     ///
-    /// A configured Server instance ready to start
+    /// Vector of DNS records containing the chunked LLM response
     ///
     /// # Errors
     ///
     /// Returns error if:
-    /// - LLM client initialization fails
-    /// - Configuration is invalid
-    pub fn new(config: Config) -> Result<Self> {
-        // Initialize LLM client
-        let mut llm_client = LlmClient::new(
-            config.openrouter_api_key.clone(),
-            config.openrouter_models.clone(),
-            config.system_prompt.clone(),
-            config.temperature,
-            config.max_tokens,
-            config.top_p,
-            config.top_k,
-            config.frequency_penalty,
-            config.presence_penalty,
-        )
-        .context("Failed to create LLM client")?;
+    /// - The name does not decode to a prompt, or asks for a later page of
+    ///   an answer no longer held ([`PromptError`])
+    /// - An upload fragment or `go` query is rejected ([`UploadError`])
+    /// - A ticket is unknown, failed, or cannot be issued ([`TicketError`])
+    /// - LLM API call fails, or failed recently in a way that recurs
+    ///   ([`FailureKind`])
+    /// - Response chunking fails
+    pub async fn process_query(&self, query_name: &Name) -> Result<Vec<Record>> {
+        let origin = self.zone.as_deref().map(Zone::origin);
 
-        // Repoint client base URL from config
-        llm_client = llm_client.with_base_url(config.llm_base_url.clone());
-        let llm_client = Arc::new(llm_client);
+        // `p<n>.<query>` asks for page n of the answer to <query>
+        let (page, base_name) = match self.dns_handler.page_request(query_name, origin) {
+            Some((page, base_name)) => (page, base_name),
+            None => (1, query_name.clone()),
+        };
 
-        // Initialize chunker
-        let chunker = Arc::new(Chunker::new());
+        // `ask.<prompt>` is answered with a ticket while the LLM works;
+        // `<ticket>.result` collects the answer
+        if let Some(tickets) = self.tickets.as_ref() {
+            match TicketQuery::parse(&base_name, origin) {
+                Some(TicketQuery::Ask { prompt }) if page == 1 => {
+                    return self.issue_ticket(tickets, query_name, prompt);
+                }
+                Some(TicketQuery::Result { ticket }) => {
+                    return self.ticket_result(tickets, query_name, &ticket, page).await;
+                }
+                _ => {}
+            }
+        }
 
-        // Initialize DNS handler
-        let dns_handler = Arc::new(DnsHandler::new());
+        // Upload fragments are stored, not asked; `go` asks what they built
+        let mut uploaded = None;
+        if let Some(uploads) = self.uploads.as_deref() {
+            match UploadQuery::parse(&base_name, origin).transpose()? {
+                Some(UploadQuery::Fragment { session, seq, data }) => {
+                    let bytes = uploads.add_fragment(&session, seq, data)?;
+                    debug!(
+                        "Upload {}: stored fragment {}, {} bytes",
+                        session, seq, bytes
+                    );
+
+                    // Nothing worth caching, so the acknowledgement has no TTL
+                    let ack = TXT::new(vec![format!("ok {} {}", seq, bytes)]);
+                    return Ok(vec![Record::from_rdata(
+                        query_name.clone(),
+                        0,
+                        RData::TXT(ack),
+                    )]);
+                }
+                Some(UploadQuery::Commit { session }) => uploaded = Some(uploads.ask(&session)?),
+                None => {}
+            }
+        }
 
-        // Initialize cache
-        let cache = Arc::new(DnsCache::with_capacity(
-            Duration::from_secs(config.cache_ttl_seconds),
-            config.cache_max_entries,
-        ));
+        // Key the answer on the prompt the name asks, or the one it uploaded
+        let key = match uploaded.as_deref() {
+            Some(prompt) => CacheKey::exact(prompt, self.fingerprint),
+            None => self.cache_key(&base_name)?,
+        };
+        let query_str = base_name.to_utf8();
+        debug!("Cache key for '{}': {}", query_str, key);
+
+        // Check cache first; every page was stored when the answer arrived
+        let page_key = key.page(page);
+        if let Some(mut cached_records) = self.cache.get(&page_key).await {
+            info!("Cache hit for query '{}' page {}", query_str, page);
+            self.hits.exact.fetch_add(1, Ordering::Relaxed);
+            // Answers restored from a snapshot are indexed once asked for
+            if let Some(similar) = self.similar.as_deref() {
+                similar.insert(&key);
+            }
+            // An upload expires with its session, so only names can be asked again
+            if uploaded.is_none() && self.cache.claim_prefetch(&page_key).await {
+                self.prefetch(base_name, key);
+            }
+            // The entry may have been cached by another spelling of the name
+            for record in &mut cached_records {
+                record.name = query_name.clone();
+            }
+            return Ok(cached_records);
+        }
 
-        // Create the main handler
-        let handler = Arc::new(
-            LlmDnsHandler::new(llm_client, chunker, dns_handler, cache)
-                .with_max_concurrent_llm_requests(config.max_concurrent_llm_requests),
-        );
+        // An answer that missed the deadline is kept for the retry
+        if let Some(mut late_records) = self.late_answers.get(&page_key).await {
+            info!("Late answer for query '{}' page {}", query_str, page);
+            for record in &mut late_records {
+                record.name = query_name.clone();
+            }
+            return Ok(late_records);
+        }
 
-        // Initialize rate limiter
-        let rate_limiter = Arc::new(IpRateLimiter::new(
-            config.rate_limit_rps,
-            config.rate_limit_burst,
-        ));
+        // Another replica may have paid for the answer already
+        if let Some(shared) = self.shared_cache.as_ref() {
+            if let Some(mut shared_records) = shared.get(&page_key).await {
+                info!("Shared cache hit for query '{}' page {}", query_str, page);
+                self.hits.shared.fetch_add(1, Ordering::Relaxed);
+                if let Some(similar) = self.similar.as_deref() {
+                    similar.insert(&key);
+                }
+                // Kept only for what is left of the answer's lifetime
+                let left = shared_records.iter().map(|r| r.ttl).min().unwrap_or(0);
+                self.cache
+                    .insert_with_ttl(
+                        &page_key,
+                        shared_records.clone(),
+                        Duration::from_secs(left.into()),
+                    )
+                    .await;
+                for record in &mut shared_records {
+                    record.name = query_name.clone();
+                }
+                return Ok(shared_records);
+            }
+        }
 
-        // Create shutdown channel
-        let (shutdown_tx, _) = broadcast::channel(1);
+        // A question asked another way may have been answered already; its
+        // later pages are then cached under this key too
+        if page == 1 {
+            if let Some(records) = self.similar_records(&key, query_name).await {
+                return Ok(records);
+            }
+        }
 
-        Ok(Self {
-            config,
-            handler,
-            rate_limiter,
-            shutdown_tx,
-        })
-    }
+        // A failure that recurs is answered from the cache like a success
+        let fetched = match self.cache.get_failure(key.as_str()).await {
+            Some(kind) => {
+                info!("Cached failure for query '{}': {}", query_str, kind);
+                Err(kind.into())
+            }
+            // A fresh call would answer differently, splicing its page n onto
+            // the pages the client already holds
+            None if page > 1 => {
+                debug!("Page {} of '{}' is no longer held", page, query_str);
+                return Err(PromptError::PageExpired(page).into());
+            }
+            None => self
+                .shared_fetch(uploaded.as_deref(), &base_name, &key)
+                .await
+                .map_err(|e| shared_error(&e)),
+        };
 
-    /// Creates a new server with custom dependencies (for testing)
-    ///
-    /// # Arguments
-    ///
-    /// * `config` - Server configuration
-    /// * `handler` - Custom LLM DNS handler (e.g., with mocked dependencies)
-    ///
-    /// # Returns
-    ///
-    /// A configured Server instance with injected dependencies
-    #[cfg(test)]
-    pub fn with_handler(config: Config, handler: Arc<LlmDnsHandler>) -> Self {
-        let (shutdown_tx, _) = broadcast::channel(1);
-        let rate_limiter = Arc::new(IpRateLimiter::new(
-            config.rate_limit_rps,
-            config.rate_limit_burst,
-        ));
+        let pages = match fetched {
+            Ok(pages) => pages,
+            Err(e) => {
+                // The client's mistakes are not papered over with old answers
+                if !e.is::<PromptError>() && !e.is::<UploadError>() {
+                    if let Some(records) = self.stale_records(&page_key, query_name).await {
+                        warn!("Serving stale answer for '{}': {:#}", query_str, e);
+                        return Ok(records);
+                    }
+                }
+                return Err(e);
+            }
+        };
 
-        Self {
-            config,
-            handler,
-            rate_limiter,
-            shutdown_tx,
+        let total = pages.len();
+        let mut records = pages
+            .get(page - 1)
+            .cloned()
+            .ok_or(PromptError::PageOutOfRange { page, pages: total })?;
+        for record in &mut records {
+            record.name = query_name.clone();
         }
+
+        info!(
+            "Successfully processed query '{}': page {} of {}, {} records",
+            query_str,
+            page,
+            total,
+            records.len()
+        );
+        Ok(records)
     }
 
-    /// Starts the DNS server
-    ///
-    /// This method:
-    /// 1. Binds to the configured UDP address
-    /// 2. Begins accepting DNS queries
-    /// 3. Spawns async tasks for each query
-    /// 4. Handles graceful shutdown on signal
-    ///
-    /// # Returns
-    ///
-    /// Ok(()) when the server shuts down gracefully
+    /// Fetches the answer to `base_name`, joining the call already running
+    /// for it if there is one
     ///
-    /// # Errors
+    /// Calls are keyed like the cache, so every spelling of a prompt shares
+    /// one call.
+    async fn shared_fetch(
+        &self,
+        uploaded: Option<&str>,
+        base_name: &Name,
+        key: &CacheKey,
+    ) -> SharedAnswer {
+        self.flights
+            .run(key.as_str(), || async {
+                self.fetch_answer(uploaded, base_name, key)
+                    .await
+                    .map_err(Arc::new)
+            })
+            .await
+    }
+
+    /// Refreshes a hot answer in the background before its cache entry
+    /// expires
+    fn prefetch(&self, base_name: Name, key: CacheKey) {
+        let handler = self.clone();
+        tokio::spawn(async move {
+            debug!("Prefetching answer for '{}'", base_name);
+            if let Err(e) = handler.shared_fetch(None, &base_name, &key).await {
+                debug!("Prefetch for '{}' failed: {:#}", base_name, e);
+            }
+        });
+    }
+
+    /// The cached answer to `query_name` served stale, if the cache still
+    /// holds one
+    async fn stale_answer(&self, query_name: &Name) -> Option<Vec<Record>> {
+        let key = self.page_key(query_name)?;
+        self.stale_records(&key, query_name).await
+    }
+
+    /// Cache key of the page `query_name` asks for, if it asks for one
+    fn page_key(&self, query_name: &Name) -> Option<String> {
+        let origin = self.zone.as_deref().map(Zone::origin);
+        let (page, base_name) = self
+            .dns_handler
+            .page_request(query_name, origin)
+            .unwrap_or_else(|| (1, query_name.clone()));
+
+        let uploaded = match self.uploads.as_deref() {
+            Some(uploads) => match UploadQuery::parse(&base_name, origin) {
+                Some(Ok(UploadQuery::Commit { session })) => Some(uploads.ask(&session).ok()?),
+                _ => None,
+            },
+            None => None,
+        };
+        let key = match uploaded {
+            Some(prompt) => CacheKey::exact(&prompt, self.fingerprint),
+            None => self.cache_key(&base_name).ok()?,
+        };
+        Some(key.page(page))
+    }
+
+    /// Records cached under `key`, owned by `query_name` and with the TTL
+    /// lowered to [`STALE_TTL`]
+    async fn stale_records(&self, key: &str, query_name: &Name) -> Option<Vec<Record>> {
+        let mut records = self.cache.get_stale(key).await?;
+        for record in &mut records {
+            record.name = query_name.clone();
+            record.ttl = STALE_TTL;
+        }
+        Some(records)
+    }
+
+    /// Asks the LLM for the answer to `base_name` and caches every page
     ///
-    /// Returns error if:
-    /// - Socket binding fails
-    /// - Address parsing fails
-    /// - Fatal UDP errors occur
-    pub async fn start(&self) -> Result<()> {
-        // Parse bind address
-        let bind_addr: SocketAddr = format!("{}:{}", self.config.dns_address, self.config.dns_port)
-            .parse()
-            .context("Failed to parse bind address")?;
+    /// Returns the records of each page in order, owned by the name that
+    /// asks for that page.
+    async fn fetch_answer(
+        &self,
+        uploaded: Option<&str>,
+        base_name: &Name,
+        key: &CacheKey,
+    ) -> Result<Arc<Vec<Vec<Record>>>> {
+        let origin = self.zone.as_deref().map(Zone::origin);
+
+        // Decode the wire labels into the prompt, unless it was uploaded
+        let prompt = match uploaded {
+            Some(prompt) => prompt.to_string(),
+            None => self
+                .dns_handler
+                .decode_prompt(base_name, origin, self.hyphens_as_spaces)?,
+        };
+        debug!("Parsed prompt: {}", prompt);
 
-        // Bind UDP socket
-        let socket = UdpSocket::bind(&bind_addr)
-            .await
-            .context("Failed to bind UDP socket")?;
+        let _permit = self.acquire_llm_permit(&prompt)?;
 
-        info!("DNS server listening on {}", bind_addr);
-        info!("Waiting for DNS queries...");
-        info!("Example: dig @localhost 'hello.world.llm.duyet.net' TXT");
+        // Query the LLM with the prompt, remembering failures that will recur
+        let response_text = match self.llm_client.query(&prompt).await {
+            Ok(text) => text,
+            Err(e) => {
+                if let Some(kind) = FailureKind::of(&e) {
+                    self.cache.insert_failure(key.as_str(), kind).await;
+                }
+                return Err(e);
+            }
+        };
+        debug!("LLM response length: {}", response_text.len());
 
-        // Spawn background cleanup task for cache and rate limiter
-        let cache_clone = self.handler.cache.clone();
-        let rate_limiter_clone = self.rate_limiter.clone();
-        let mut shutdown_rx_cleanup = self.shutdown_tx.subscribe();
+        // Time-sensitive prompts are kept for less, or not at all
+        let ttl = match self.ttl_policy.ttl_for(&prompt) {
+            Some(ttl) => ttl.min(self.cache.ttl()),
+            None => self.cache.ttl(),
+        };
+        let record_ttl = u32::try_from(ttl.as_secs()).unwrap_or(u32::MAX);
 
-        tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    _ = shutdown_rx_cleanup.recv() => {
-                        break;
-                    }
-                    _ = tokio::time::sleep(Duration::from_secs(30)) => {
-                        debug!("Running background cleanup for cache and rate limiter...");
-                        cache_clone.cleanup().await;
-                        rate_limiter_clone.cleanup(Duration::from_secs(300));
-                    }
+        // Split the response into pages of DNS TXT chunks
+        let mut pages = self.chunker.paginate(&response_text);
+        if pages.is_empty() {
+            pages.push(Vec::new());
+        }
+        let total = pages.len();
+        debug!("Paginated into {} pages", total);
+
+        // Store every page now, so later pages need no LLM call
+        let mut answer = Vec::with_capacity(total);
+        for (index, chunks) in pages.into_iter().enumerate() {
+            let number = index + 1;
+            let owner = match number {
+                1 => Ok(base_name.clone()),
+                _ => base_name.prepend_label(format!("p{}", number)),
+            };
+
+            let records = page_records(
+                owner.as_ref().unwrap_or(base_name),
+                self.chunker.txt_records(chunks),
+                number,
+                total,
+                record_ttl,
+            );
+            // Too long to carry a page label: no client can ask for it
+            if owner.is_ok() {
+                let page_key = key.page(number);
+                let hold = match number {
+                    1 => ttl,
+                    _ => ttl.max(MIN_PAGE_HOLD),
+                };
+                self.cache
+                    .insert_with_ttl(&page_key, records.clone(), hold)
+                    .await;
+                // The answer goes out without waiting on the shared store
+                if let Some(shared) = self.shared_cache.clone().filter(|_| !ttl.is_zero()) {
+                    let records = records.clone();
+                    tokio::spawn(async move { shared.insert(&page_key, records, ttl).await });
                 }
             }
+            answer.push(records);
+        }
+        if let Some(similar) = self.similar.as_deref() {
+            if !ttl.is_zero() {
+                similar.insert(key);
+            }
+        }
+
+        Ok(Arc::new(answer))
+    }
+
+    /// The first page of the cached answer to the prompt most similar to the
+    /// one `key` was made from, owned by `query_name`
+    ///
+    /// The answer's later pages are copied under `key`, so a client walking
+    /// them reads the same answer as an exact hit.
+    async fn similar_records(&self, key: &CacheKey, query_name: &Name) -> Option<Vec<Record>> {
+        let similar = self.similar.as_deref()?;
+        let found = similar.find(key)?;
+        let Some(mut records) = self.cache.get(&found.key.page(1)).await else {
+            // Every answer has a first page, so without one it is gone
+            similar.remove(&found.key);
+            return None;
+        };
+
+        for page in 2..=first_page_total(&records) {
+            let Some(page_records) = self.cache.get(&found.key.page(page)).await else {
+                break;
+            };
+            let left = page_records.iter().map(|r| r.ttl).min().unwrap_or(0);
+            self.cache
+                .insert_with_ttl(
+                    &key.page(page),
+                    page_records,
+                    Duration::from_secs(left.into()),
+                )
+                .await;
+        }
+
+        info!(
+            "Similar cache hit for query '{}': {:.2} similar to '{}'",
+            query_name,
+            found.similarity,
+            crate::cache::key_parts(found.key.as_str()).1
+        );
+        self.hits.similar.fetch_add(1, Ordering::Relaxed);
+        for record in &mut records {
+            record.name = query_name.clone();
+        }
+        Some(records)
+    }
+
+    /// Issues a ticket for `prompt` and starts answering it in the background
+    fn issue_ticket(
+        &self,
+        tickets: &Arc<TicketStore>,
+        query_name: &Name,
+        prompt: Name,
+    ) -> Result<Vec<Record>> {
+        // Reject a malformed prompt now, while the client is still listening
+        let origin = self.zone.as_deref().map(Zone::origin);
+        if !matches!(
+            UploadQuery::parse(&prompt, origin),
+            Some(Ok(UploadQuery::Commit { .. }))
+        ) {
+            self.dns_handler
+                .decode_prompt(&prompt, origin, self.hyphens_as_spaces)?;
+        }
+
+        let ticket = tickets.issue(prompt.clone())?;
+        debug!("Issued ticket {} for {}", ticket, prompt);
+
+        let handler = self.clone();
+        let tickets = tickets.clone();
+        let id = ticket.clone();
+        tokio::spawn(async move {
+            match handler.resolve_pages(&prompt).await {
+                Ok(pages) => tickets.complete(&id, pages),
+                Err(code) => tickets.fail(&id, code),
+            }
         });
 
-        // Wrap socket in Arc for sharing across tasks
-        let socket = Arc::new(socket);
-        // recv_from silently discards whatever does not fit, so a 512-byte
-        // buffer truncated any EDNS0-sized query into an unparseable fragment:
-        // Message::from_vec then failed and the client got no reply at all,
-        // just a timeout. Size for the largest datagram we are willing to read.
-        let mut buffer = vec![0u8; MAX_UDP_REQUEST];
+        // Tickets are single-use, so the answer must not be cached
+        let txt = TXT::new(vec![ticket]);
+        Ok(vec![Record::from_rdata(
+            query_name.clone(),
+            0,
+            RData::TXT(txt),
+        )])
+    }
 
-        // Subscribe to shutdown signal
-        let mut shutdown_rx = self.shutdown_tx.subscribe();
+    /// Every page of the answer to `prompt`, or the error code its first
+    /// failing page was answered with
+    ///
+    /// Later pages are collected the way a client walks them, while the first
+    /// page's answer still holds them.
+    async fn resolve_pages(
+        &self,
+        prompt: &Name,
+    ) -> std::result::Result<Vec<Vec<Record>>, ResponseCode> {
+        let first = self.resolve_prompt(prompt).await;
+        if first.response_code != ResponseCode::NoError {
+            return Err(first.response_code);
+        }
+        let total = first_page_total(&first.answers);
+
+        let mut pages = vec![first.answers];
+        for page in 2..=total {
+            let name = prompt
+                .prepend_label(format!("p{}", page))
+                .map_err(|_| ResponseCode::ServFail)?;
+            let outcome = self.resolve_prompt(&name).await;
+            if outcome.response_code != ResponseCode::NoError {
+                return Err(outcome.response_code);
+            }
+            pages.push(outcome.answers);
+        }
+        Ok(pages)
+    }
 
-        // Main server loop
-        loop {
-            tokio::select! {
-                // Shutdown signal received
-                _ = shutdown_rx.recv() => {
-                    info!("Shutdown signal received, stopping server");
-                    break;
+    /// Answers `[p<n>.]<ticket>.result` from the answer kept on the ticket
+    async fn ticket_result(
+        &self,
+        tickets: &TicketStore,
+        query_name: &Name,
+        ticket: &str,
+        page: usize,
+    ) -> Result<Vec<Record>> {
+        let ticket = tickets.get(ticket)?;
+        match ticket.state {
+            TicketState::Pending => {
+                let txt = TXT::new(vec![TICKET_PENDING.to_string()]);
+                Ok(vec![Record::from_rdata(
+                    query_name.clone(),
+                    0,
+                    RData::TXT(txt),
+                )])
+            }
+            TicketState::Failed(code) => Err(TicketError::Failed(code).into()),
+            TicketState::Ready(pages) => {
+                let mut records =
+                    pages
+                        .get(page - 1)
+                        .cloned()
+                        .ok_or(PromptError::PageOutOfRange {
+                            page,
+                            pages: pages.len(),
+                        })?;
+                for record in &mut records {
+                    record.name = query_name.clone();
                 }
+                Ok(records)
+            }
+        }
+    }
 
-                // Receive DNS query
-                result = socket.recv_from(&mut buffer) => {
-                    match result {
-                        Ok((n, remote_addr)) => {
-                            debug!("Received {} bytes from {}", n, remote_addr);
+    /// Cache key for the prompt `query_name` asks
+    ///
+    /// Keys are built from the decoded prompt, so the same question spelled
+    /// differently in plain labels shares one answer. Encoded prompts are
+    /// keyed as spelled (see [`CacheKey::exact`]), keeping the code and
+    /// capitals they were encoded to carry.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PromptError`] if the name does not decode to a prompt.
+    pub(crate) fn cache_key(&self, query_name: &Name) -> Result<CacheKey> {
+        let origin = self.zone.as_deref().map(Zone::origin);
+        let prompt = self
+            .dns_handler
+            .decode_prompt(query_name, origin, self.hyphens_as_spaces)?;
+        Ok(match self.dns_handler.prompt_encoding(query_name, origin) {
+            Some(_) => CacheKey::exact(&prompt, self.fingerprint),
+            None => CacheKey::prompt(&prompt, self.fingerprint),
+        })
+    }
 
-                            // Parse DNS message
-                            match Message::from_vec(&buffer[..n]) {
-                                Ok(request_msg) => {
-                                    let handler_clone = self.handler.clone();
-                                    let rate_limiter_clone = self.rate_limiter.clone();
-                                    let socket_clone = socket.clone();
+    /// Removes every page of the cached answer to `query_name`, whichever
+    /// spelling cached it.
+    ///
+    /// With a shared cache, the pages are removed from it too, so no replica
+    /// fetches them again; [`Purged::shared_error`] says if that failed.
+    /// Replicas already holding the answer in memory serve it until it
+    /// expires there.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PromptError`] if the name does not decode to a prompt.
+    pub async fn purge_name(&self, query_name: &Name) -> Result<Purged> {
+        let origin = self.zone.as_deref().map(Zone::origin);
+        let base_name = match self.dns_handler.page_request(query_name, origin) {
+            Some((_, base_name)) => base_name,
+            None => query_name.clone(),
+        };
+        let key = self.cache_key(&base_name)?;
 
-                                    // Process DNS query in background task
-                                    tokio::spawn(async move {
-                                        if let Err(e) = handle_dns_request(
-                                            request_msg,
-                                            remote_addr,
-                                            handler_clone,
-                                            rate_limiter_clone,
-                                            socket_clone,
-                                        )
-                                        .await
-                                        {
-                                            error!("Failed to handle DNS request from {}: {}", remote_addr, e);
-                                        }
-                                    });
-                                }
-                                Err(e) => {
-                                    warn!("Failed to parse DNS message from {}: {}", remote_addr, e);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("UDP socket error: {}", e);
-                            // Small delay to prevent tight loop on persistent errors
-                            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                        }
-                    }
+        let pages = self.cache.purge_answer(&key).await;
+        if let Some(similar) = self.similar.as_deref() {
+            similar.remove(&key);
+        }
+        let shared_error = self
+            .purge_shared(&key)
+            .await
+            .err()
+            .map(|e| format!("{:#}", e));
+        info!("Purged {} cached pages for '{}'", pages, base_name);
+        Ok(Purged {
+            pages,
+            shared_error,
+        })
+    }
+
+    /// Removes the cached answers to every prompt starting with `prefix`,
+    /// compared once both are normalized.
+    ///
+    /// See [`LlmDnsHandler::purge_matching`] for what is removed.
+    pub async fn purge_prefix(&self, prefix: &str) -> Purged {
+        let prefix = normalize_prompt(prefix);
+        self.purge_prompts(|prompt| prompt.starts_with(&prefix))
+            .await
+    }
+
+    /// Removes the cached answers to every normalized prompt `pattern`
+    /// matches.
+    ///
+    /// The answers also leave the similarity index and, with a shared cache,
+    /// the shared store; [`Purged::shared_error`] says if that failed. Only
+    /// answers this replica holds or has indexed can be found there, and
+    /// replicas holding them in memory serve them until they expire there.
+    pub async fn purge_matching(&self, pattern: &regex::Regex) -> Purged {
+        self.purge_prompts(|prompt| pattern.is_match(prompt)).await
+    }
+
+    async fn purge_prompts(&self, mut matches: impl FnMut(&str) -> bool) -> Purged {
+        let mut answers = HashSet::new();
+        let pages = self
+            .cache
+            .purge(|stored| {
+                let doomed = matches(key_parts(stored).1);
+                if doomed {
+                    answers.insert(CacheKey::of_stored(stored));
                 }
+                doomed
+            })
+            .await;
+        if let Some(similar) = self.similar.as_deref() {
+            answers.extend(similar.remove_matching(&mut matches));
+        }
+        let mut failed = 0;
+        let mut shared_error = None;
+        for key in &answers {
+            if let Err(e) = self.purge_shared(key).await {
+                failed += 1;
+                shared_error.get_or_insert(e);
             }
         }
+        info!("Purged {} cached pages of {} answers", pages, answers.len());
+        Purged {
+            pages,
+            shared_error: shared_error.map(|e| {
+                format!(
+                    "{} of {} answers may be left in the shared cache: {:#}",
+                    failed,
+                    answers.len(),
+                    e
+                )
+            }),
+        }
+    }
 
-        info!("DNS server shutdown complete");
+    /// Removes every page of the answer under `key` from the shared cache
+    async fn purge_shared(&self, key: &CacheKey) -> Result<()> {
+        let Some(shared) = self.shared_cache.as_ref() else {
+            return Ok(());
+        };
+        if let Err(e) = shared.purge_answer(key).await {
+            warn!("Shared cache purge of '{}' failed: {:#}", key, e);
+            return Err(e);
+        }
         Ok(())
     }
 
-    /// Triggers graceful shutdown of the server
-    ///
-    /// This sends a shutdown signal to the running server, allowing it to
-    /// complete in-flight requests and clean up resources.
-    ///
-    /// # Returns
+    /// LLM calls made for DNS queries, and how many queries shared another's
+    pub fn flight_stats(&self) -> FlightStats {
+        self.flights.stats()
+    }
+
+    /// Queries answered from a cache, by how the answer was found
+    pub fn hit_stats(&self) -> CacheHitStats {
+        self.hits.stats()
+    }
+
+    /// Answers a prompt directly, bypassing DNS encoding
     ///
-    /// Ok(()) if shutdown signal was sent successfully
+    /// Used by the HTTP API, which can return the whole answer at once. The
+    /// prompt is keyed as an uploaded one (see [`CacheKey::exact`]): requests
+    /// for it share one call, and a failure that recurs is answered from the
+    /// cache. Answers are not taken from the response cache, which keeps the
+    /// TXT records DNS serves but not the model that answered. The call still
+    /// counts against the LLM concurrency ceiling.
     ///
     /// # Errors
     ///
-    /// Returns error if no receivers are listening (server not running)
-    pub fn shutdown(&self) -> Result<()> {
-        self.shutdown_tx
-            .send(())
-            .context("Failed to send shutdown signal")?;
-        Ok(())
+    /// Returns [`LlmBusy`] if the concurrency ceiling is reached, or the LLM
+    /// error, or its cached [`FailureKind`], if every model fails.
+    pub async fn ask(&self, prompt: &str) -> Result<PromptAnswer> {
+        let key = CacheKey::exact(prompt, self.fingerprint);
+        if let Some(kind) = self.cache.get_failure(key.as_str()).await {
+            info!("Cached failure for prompt '{}': {}", prompt, kind);
+            return Err(kind.into());
+        }
+
+        self.asks
+            .run(key.as_str(), || async {
+                self.ask_llm(prompt, &key).await.map_err(Arc::new)
+            })
+            .await
+            .map_err(|e| shared_error(&e))
     }
 
-    /// Returns the configured bind address
-    pub fn bind_address(&self) -> String {
-        format!("{}:{}", self.config.dns_address, self.config.dns_port)
+    /// Asks the LLM for the whole answer to `prompt`, remembering failures
+    /// that will recur under `key`
+    async fn ask_llm(&self, prompt: &str, key: &CacheKey) -> Result<PromptAnswer> {
+        let _permit = self.acquire_llm_permit(prompt)?;
+
+        let response = match self.llm_client.query_with_model(prompt).await {
+            Ok(response) => response,
+            Err(e) => {
+                if let Some(kind) = FailureKind::of(&e) {
+                    self.cache.insert_failure(key.as_str(), kind).await;
+                }
+                return Err(e);
+            }
+        };
+        // Every page DNS would serve, so the chunks carry the whole answer
+        let chunks = self
+            .chunker
+            .paginate(&response.content)
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Ok(PromptAnswer {
+            answer: response.content,
+            model: response.model,
+            chunks,
+        })
+    }
+
+    /// Takes a permit before spending money
+    ///
+    /// try_acquire sheds load rather than queueing: a spoofed-source flood
+    /// would otherwise park hundreds of thousands of tasks waiting on the
+    /// semaphore, which is the exhaustion we are trying to prevent. The client
+    /// sees SERVFAIL and may retry.
+    fn acquire_llm_permit(&self, prompt: &str) -> Result<Option<OwnedSemaphorePermit>> {
+        match self.llm_permits.as_ref() {
+            Some(sem) => match sem.clone().try_acquire_owned() {
+                Ok(permit) => Ok(Some(permit)),
+                Err(_) => {
+                    warn!("LLM concurrency limit reached, shedding query '{}'", prompt);
+                    Err(LlmBusy.into())
+                }
+            },
+            None => Ok(None),
+        }
     }
 }
 
-/// Size of the UDP receive buffer.
+/// Rebuilds a failure shared by coalesced queries
 ///
-/// Large enough for any EDNS0 query a client may send; anything beyond this is
-/// discarded by the kernel and will fail to parse, which is the correct outcome
-/// for a datagram that large.
-const MAX_UDP_REQUEST: usize = 4096;
+/// Every query waiting on the call gets its own error, keeping the types the
+/// response code is chosen from; anything else becomes a plain message.
+fn shared_error(error: &anyhow::Error) -> anyhow::Error {
+    if let Some(e) = error.downcast_ref::<PromptError>() {
+        e.clone().into()
+    } else if let Some(e) = error.downcast_ref::<UploadError>() {
+        e.clone().into()
+    } else if let Some(e) = error.downcast_ref::<LlmBusy>() {
+        (*e).into()
+    } else if let Some(e) = error.downcast_ref::<LlmError>() {
+        e.clone().into()
+    } else {
+        anyhow!("{:#}", error)
+    }
+}
 
-/// Largest UDP response permitted to a client that sent no EDNS0 OPT record.
-///
-/// RFC 1035 §4.2.1 fixes the plain-DNS UDP message size at 512 bytes.
-const DEFAULT_MAX_UDP_RESPONSE: usize = 512;
+/// Text of the record [`DeadlineReply::Thinking`] answers with
+pub const THINKING: &str = "thinking";
 
-/// Ceiling applied even when a client advertises a larger EDNS0 buffer.
-///
-/// 1232 bytes is the DNS Flag Day 2020 recommendation: it stays under the
-/// common 1280-byte IPv6 MTU so responses avoid IP fragmentation, and it bounds
-/// the amplification factor available to an attacker spoofing a source address.
-const MAX_UDP_RESPONSE: usize = 1232;
+/// Text of the record a ticket answers with until its prompt is answered
+pub const TICKET_PENDING: &str = "pending";
 
-/// Determine how many bytes of UDP response this client may receive.
+/// Builds the TXT records for one page of an answer
 ///
-/// A client advertises its receive buffer in the EDNS0 OPT record (RFC 6891).
-/// Absent that, plain DNS limits us to [`DEFAULT_MAX_UDP_RESPONSE`]. The value
-/// is clamped into `[DEFAULT_MAX_UDP_RESPONSE, MAX_UDP_RESPONSE]` so a spoofed
-/// query cannot request a large datagram.
-fn client_udp_payload_size(request: &Message) -> usize {
-    request
-        .edns
-        .as_ref()
-        .map(|edns| edns.max_payload() as usize)
-        .unwrap_or(DEFAULT_MAX_UDP_RESPONSE)
-        .clamp(DEFAULT_MAX_UDP_RESPONSE, MAX_UDP_RESPONSE)
+/// Each entry of `txt_records` is the character-strings of one record, as
+/// laid out by [`Chunker::txt_records`]. A multi-page answer ends each page
+/// with a `page <n>/<total>` record, so a client knows whether to ask for
+/// `p<n+1>.<query>`. Every record carries `ttl`, the answer's lifetime in
+/// the cache.
+fn page_records(
+    owner: &Name,
+    mut txt_records: Vec<Vec<String>>,
+    page: usize,
+    total: usize,
+    ttl: u32,
+) -> Vec<Record> {
+    if total > 1 {
+        txt_records.push(vec![Chunker::page_marker(page, total)]);
+    }
+
+    txt_records
+        .into_iter()
+        .map(|strings| Record::from_rdata(owner.clone(), ttl, RData::TXT(TXT::new(strings))))
+        .collect()
 }
 
-/// Handles a single incoming DNS request and sends the response
-///
-/// # Arguments
-///
-/// * `request_msg` - Parsed DNS request message
-/// * `remote_addr` - Address of the client
-/// * `handler` - LLM DNS handler for processing queries
-/// * `socket` - UDP socket for sending responses
-///
-/// # Returns
-///
-/// Ok(()) when response is sent successfully
-///
-/// # Errors
+/// Pages in the answer whose first page is `records`, from the marker
+/// [`page_records`] ends it with
+fn first_page_total(records: &[Record]) -> usize {
+    records
+        .last()
+        .and_then(|record| match &record.data {
+            RData::TXT(txt) => txt.txt_data.first(),
+            _ => None,
+        })
+        .and_then(|marker| Chunker::parse_page_marker(&String::from_utf8_lossy(marker)))
+        .filter(|&(page, _)| page == 1)
+        .map_or(1, |(_, total)| total)
+}
+
+/// Main DNS server with LLM integration
 ///
-/// Returns error if:
-/// - DNS response serialization fails
-/// - UDP send fails
-async fn handle_dns_request(
-    request_msg: Message,
-    remote_addr: SocketAddr,
+/// Manages the complete server lifecycle including:
+/// - UDP socket and TCP listener binding and management
+/// - Request handling and routing
+/// - Graceful shutdown coordination
+/// - Resource cleanup
+pub struct Server {
+    config: Config,
     handler: Arc<LlmDnsHandler>,
     rate_limiter: Arc<IpRateLimiter>,
-    socket: Arc<UdpSocket>,
-) -> Result<()> {
-    // Check rate limit first
-    if !rate_limiter.check_allowed(remote_addr.ip()) {
-        warn!("Rate limit exceeded for client {}", remote_addr);
-        let mut response = Message::new(
-            request_msg.metadata.id,
-            MessageType::Response,
-            OpCode::Query,
+    shutdown_tx: broadcast::Sender<()>,
+}
+
+impl Server {
+    /// Creates a new DNS server with the provided configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration including DNS address/port and LLM settings
+    ///
+    /// # Returns
+    ///
+    /// A configured Server instance ready to start
+    ///
+    /// # Errors
+    ///
+    /// Returns error if:
+    /// - LLM client initialization fails
+    /// - Configuration is invalid
+    pub fn new(config: Config) -> Result<Self> {
+        // Initialize LLM client
+        let mut llm_client = LlmClient::new(
+            config.openrouter_api_key.clone(),
+            config.openrouter_models.clone(),
+            config.system_prompt.clone(),
+            config.temperature,
+            config.max_tokens,
+            config.top_p,
+            config.top_k,
+            config.frequency_penalty,
+            config.presence_penalty,
+        )
+        .context("Failed to create LLM client")?;
+
+        // Repoint client base URL from config
+        llm_client = llm_client.with_base_url(config.llm_base_url.clone());
+        let llm_client = Arc::new(llm_client);
+
+        // Initialize chunker
+        let chunker = Arc::new(Chunker::new().with_framing(config.txt_framing));
+
+        // Initialize DNS handler
+        let dns_handler = Arc::new(DnsHandler::new());
+
+        // Initialize cache
+        let cache = Arc::new(
+            DnsCache::with_capacity(
+                Duration::from_secs(config.cache_ttl_seconds),
+                config.cache_max_entries,
+            )
+            .with_max_bytes(config.cache_max_bytes)
+            .with_serve_stale(Duration::from_secs(config.cache_stale_seconds))
+            .with_prefetch(config.cache_prefetch_hits)
+            .with_negative_ttls(NegativeTtls {
+                bad_request: Duration::from_secs(config.negative_ttl_bad_request_seconds),
+                content_filtered: Duration::from_secs(config.negative_ttl_filtered_seconds),
+                model_not_found: Duration::from_secs(config.negative_ttl_model_seconds),
+            }),
         );
-        response.metadata.recursion_available = false;
-        response.metadata.recursion_desired = request_msg.metadata.recursion_desired;
-        response.metadata.authoritative = true;
-        response.metadata.response_code = ResponseCode::Refused;
 
-        let response_bytes = response.to_vec()?;
-        socket.send_to(&response_bytes, remote_addr).await?;
-        return Ok(());
+        // Create the main handler
+        let mut handler = LlmDnsHandler::new(llm_client, chunker, dns_handler, cache)
+            .with_max_concurrent_llm_requests(config.max_concurrent_llm_requests)
+            .with_hyphens_as_spaces(config.hyphens_as_spaces)
+            .with_response_deadline(
+                Duration::from_millis(config.response_deadline_ms),
+                config.deadline_reply,
+            );
+
+        if let Some(origin) = &config.dns_zone {
+            let mut zone = Zone::new(origin).context("Invalid DNS_ZONE")?;
+            if !config.dns_zone_nameservers.is_empty() {
+                zone = zone
+                    .with_nameservers(&config.dns_zone_nameservers)
+                    .context("Invalid DNS_ZONE_NS")?;
+            }
+            if let Some(hostmaster) = &config.dns_zone_hostmaster {
+                zone = zone
+                    .with_hostmaster(hostmaster)
+                    .context("Invalid DNS_ZONE_HOSTMASTER")?;
+            }
+            handler = handler.with_zone(zone);
+        }
+        if config.upload_max_sessions > 0 {
+            handler = handler.with_uploads(UploadSessions::new(
+                config.upload_max_sessions,
+                config.upload_max_bytes,
+                Duration::from_secs(config.upload_ttl_seconds),
+            ));
+        }
+        if config.ticket_max > 0 {
+            handler = handler.with_tickets(TicketStore::new(
+                config.ticket_max,
+                Duration::from_secs(config.ticket_ttl_seconds),
+            ));
+        }
+        if let Some(url) = &config.cache_redis_url {
+            let shared = RespCache::new(url).context("Invalid CACHE_REDIS_URL")?;
+            handler = handler.with_shared_cache(Arc::new(shared));
+        }
+        if let Some(rules) = &config.cache_ttl_rules {
+            let policy = TtlPolicy::parse(rules).context("Invalid CACHE_TTL_RULES")?;
+            handler = handler.with_ttl_policy(policy);
+        }
+        if let Some(threshold) = config.cache_similarity_threshold {
+            handler = handler
+                .with_similar_prompts(SimilarPrompts::new(threshold, config.cache_max_entries));
+        }
+        let handler = Arc::new(handler);
+
+        // Initialize rate limiter
+        let rate_limiter = Arc::new(rate_limiter(&config));
+
+        // Create shutdown channel
+        let (shutdown_tx, _) = broadcast::channel(1);
+
+        Ok(Self {
+            config,
+            handler,
+            rate_limiter,
+            shutdown_tx,
+        })
     }
 
-    // Create DNS response message
-    let mut response = Message::new(
-        request_msg.metadata.id,
-        MessageType::Response,
-        OpCode::Query,
-    );
-    response.metadata.recursion_available = false;
-    response.metadata.recursion_desired = request_msg.metadata.recursion_desired;
-    response.add_queries(request_msg.queries.clone());
+    /// Creates a new server with custom dependencies (for testing)
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration
+    /// * `handler` - Custom LLM DNS handler (e.g., with mocked dependencies)
+    ///
+    /// # Returns
+    ///
+    /// A configured Server instance with injected dependencies
+    #[cfg(test)]
+    pub fn with_handler(config: Config, handler: Arc<LlmDnsHandler>) -> Self {
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let rate_limiter = Arc::new(rate_limiter(&config));
 
-    // Set authoritative answer bit
-    response.metadata.authoritative = true;
+        Self {
+            config,
+            handler,
+            rate_limiter,
+            shutdown_tx,
+        }
+    }
 
-    // Process each query in the request
-    let mut response_code = ResponseCode::NoError;
+    /// Starts the DNS server
+    ///
+    /// This method:
+    /// 1. Binds UDP and TCP listeners on the configured address, plus a
+    ///    DNS-over-TLS listener when a certificate is configured and a
+    ///    DNS-over-HTTPS listener when `HTTP_PORT` is set
+    /// 2. Begins accepting DNS queries
+    /// 3. Spawns async tasks for each query
+    /// 4. Handles graceful shutdown on signal
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) when the server shuts down gracefully
+    ///
+    /// # Errors
+    ///
+    /// Returns error if:
+    /// - Socket binding fails
+    /// - Address parsing fails
+    /// - TLS certificate loading fails
+    /// - Fatal UDP errors occur
+    pub async fn start(&self) -> Result<()> {
+        // Warm the cache from the last snapshot before answering anything
+        let snapshot = self.config.cache_snapshot_path.as_ref().map(|path| {
+            Arc::new(CacheSnapshot::new(
+                path,
+                self.config.cache_snapshot_max_bytes,
+            ))
+        });
+        if let Some(snapshot) = &snapshot {
+            match snapshot.load(&self.handler.cache).await {
+                Ok(loaded) => info!(
+                    "Loaded {} cached answers from {}",
+                    loaded,
+                    snapshot.path().display()
+                ),
+                Err(e) => warn!("Starting with an empty cache: {:#}", e),
+            }
+        }
 
-    for query in &request_msg.queries {
-        debug!(
-            "Processing query: {} {:?}",
-            query.name(),
-            query.query_type()
-        );
+        // Parse bind address
+        let bind_addr: SocketAddr = format!("{}:{}", self.config.dns_address, self.config.dns_port)
+            .parse()
+            .context("Failed to parse bind address")?;
+
+        // Bind UDP socket
+        let socket = UdpSocket::bind(&bind_addr)
+            .await
+            .context("Failed to bind UDP socket")?;
+
+        // Bind TCP on the same address. Answers too large for the client's UDP
+        // budget are sent with TC set, and TCP is where the client retries.
+        let tcp_listener = TcpListener::bind(&bind_addr)
+            .await
+            .context("Failed to bind TCP listener")?;
+
+        info!("DNS server listening on {} (UDP and TCP)", bind_addr);
+
+        // One resolver serves every TLS listener, so a renewal reaches them all.
+        let cert_resolver = match (&self.config.tls_cert_path, &self.config.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                let resolver = Arc::new(
+                    ReloadingCertResolver::new(cert_path, key_path)
+                        .context("Failed to load TLS certificate")?,
+                );
+                tokio::spawn(
+                    resolver
+                        .clone()
+                        .watch(CERT_RELOAD_INTERVAL, self.shutdown_tx.subscribe()),
+                );
+                Some(resolver)
+            }
+            _ => None,
+        };
+
+        if let Some(resolver) = &cert_resolver {
+            let dot_addr: SocketAddr =
+                format!("{}:{}", self.config.dns_address, self.config.dot_port)
+                    .parse()
+                    .context("Failed to parse DNS-over-TLS bind address")?;
+
+            let acceptor = resolver.acceptor(vec![DOT_ALPN.to_vec()])?;
+            let dot_listener = TcpListener::bind(&dot_addr)
+                .await
+                .context("Failed to bind DNS-over-TLS listener")?;
+
+            tokio::spawn(tcp::serve(
+                dot_listener,
+                Some(acceptor),
+                self.handler.clone(),
+                self.rate_limiter.clone(),
+                Duration::from_secs(self.config.tcp_idle_timeout_seconds),
+                self.config.tcp_max_connections,
+                self.shutdown_tx.subscribe(),
+            ));
+
+            info!("DNS-over-TLS listening on {}", dot_addr);
+        }
 
-        // Only handle TXT queries
-        if query.query_type() != RecordType::TXT {
-            warn!(
-                "Unsupported query type {:?} for {}",
-                query.query_type(),
-                query.name()
+        if let Some(http_port) = self.config.http_port {
+            let http_addr: SocketAddr = format!("{}:{}", self.config.dns_address, http_port)
+                .parse()
+                .context("Failed to parse DNS-over-HTTPS bind address")?;
+
+            let acceptor = cert_resolver
+                .as_ref()
+                .map(|resolver| resolver.acceptor(DOH_ALPN.iter().map(|p| p.to_vec()).collect()))
+                .transpose()?;
+            let scheme = if acceptor.is_some() { "https" } else { "http" };
+            let http_listener = TcpListener::bind(&http_addr)
+                .await
+                .context("Failed to bind DNS-over-HTTPS listener")?;
+
+            tokio::spawn(http::serve(
+                http_listener,
+                acceptor,
+                http::router(
+                    self.handler.clone(),
+                    self.rate_limiter.clone(),
+                    self.config.admin_token.clone(),
+                ),
+                Duration::from_secs(self.config.tcp_idle_timeout_seconds),
+                self.config.tcp_max_connections,
+                self.shutdown_tx.subscribe(),
+            ));
+
+            info!(
+                "DNS-over-HTTPS listening on {}://{}/dns-query",
+                scheme, http_addr
             );
-            response_code = ResponseCode::NotImp;
-            continue;
+        }
+        info!("Waiting for DNS queries...");
+        match &self.config.dns_zone {
+            Some(zone) => {
+                info!("Authoritative for zone {}", zone);
+                info!("Example: dig @localhost 'what.is.rust.{}' TXT", zone);
+            }
+            None => info!("Example: dig @localhost 'what is rust' TXT"),
         }
 
-        // Process the query
-        match handler.process_query(query.name()).await {
-            Ok(records) => {
-                debug!("Adding {} answer records", records.len());
-                for record in records {
-                    response.add_answer(record);
+        // Spawn background cleanup task for cache, uploads, tickets and rate limiter
+        let cache_clone = self.handler.cache.clone();
+        let late_answers_clone = self.handler.late_answers.clone();
+        let uploads_clone = self.handler.uploads.clone();
+        let tickets_clone = self.handler.tickets.clone();
+        let flights_clone = self.handler.flights.clone();
+        let hits_clone = self.handler.hits.clone();
+        let rate_limiter_clone = self.rate_limiter.clone();
+        let mut shutdown_rx_cleanup = self.shutdown_tx.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown_rx_cleanup.recv() => {
+                        break;
+                    }
+                    _ = tokio::time::sleep(Duration::from_secs(30)) => {
+                        debug!("Running background cleanup for cache, uploads, tickets and rate limiter...");
+                        cache_clone.cleanup().await;
+                        late_answers_clone.cleanup().await;
+                        if let Some(uploads) = &uploads_clone {
+                            uploads.cleanup();
+                        }
+                        if let Some(tickets) = &tickets_clone {
+                            tickets.cleanup();
+                        }
+                        rate_limiter_clone.cleanup(Duration::from_secs(300));
+
+                        debug!(
+                            "Response cache: {} answers, {} bytes",
+                            cache_clone.len().await,
+                            cache_clone.bytes().await
+                        );
+                        let stats = flights_clone.stats();
+                        debug!(
+                            "LLM calls: {} made, {} saved by coalescing",
+                            stats.calls, stats.coalesced
+                        );
+                        let hits = hits_clone.stats();
+                        debug!(
+                            "Cache hits: {} exact, {} shared, {} similar",
+                            hits.exact, hits.shared, hits.similar
+                        );
+                    }
                 }
             }
-            Err(e) => {
-                warn!("Failed to process query for {}: {}", query.name(), e);
-                response_code = ResponseCode::ServFail;
-            }
+        });
+
+        if let Some(snapshot) = snapshot.clone() {
+            let cache = self.handler.cache.clone();
+            let interval = Duration::from_secs(self.config.cache_snapshot_interval_seconds.max(1));
+            let mut shutdown_rx_snapshot = self.shutdown_tx.subscribe();
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = shutdown_rx_snapshot.recv() => break,
+                        _ = tokio::time::sleep(interval) => save_snapshot(&snapshot, &cache).await,
+                    }
+                }
+            });
         }
-    }
 
-    // Set response code
-    response.metadata.response_code = response_code;
+        tokio::spawn(tcp::serve(
+            tcp_listener,
+            None,
+            self.handler.clone(),
+            self.rate_limiter.clone(),
+            Duration::from_secs(self.config.tcp_idle_timeout_seconds),
+            self.config.tcp_max_connections,
+            self.shutdown_tx.subscribe(),
+        ));
 
-    // Serialize DNS response to bytes
-    let mut response_bytes = response.to_vec()?;
+        // Wrap socket in Arc for sharing across tasks
+        let socket = Arc::new(socket);
+        // recv_from silently discards whatever does not fit, so a 512-byte
+        // buffer truncated any EDNS0-sized query into an unparseable fragment:
+        // Message::from_vec then failed and the client got no reply at all,
+        // just a timeout. Size for the largest datagram we are willing to read.
+        let mut buffer = vec![0u8; MAX_UDP_REQUEST];
+
+        // Subscribe to shutdown signal
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+
+        // Main server loop
+        loop {
+            tokio::select! {
+                // Shutdown signal received
+                _ = shutdown_rx.recv() => {
+                    info!("Shutdown signal received, stopping server");
+                    break;
+                }
+
+                // Receive DNS query
+                result = socket.recv_from(&mut buffer) => {
+                    match result {
+                        Ok((n, remote_addr)) => {
+                            debug!("Received {} bytes from {}", n, remote_addr);
+
+                            // Parse DNS message
+                            match Message::from_vec(&buffer[..n]) {
+                                Ok(request_msg) => {
+                                    let handler_clone = self.handler.clone();
+                                    let rate_limiter_clone = self.rate_limiter.clone();
+                                    let socket_clone = socket.clone();
+
+                                    // Process DNS query in background task
+                                    tokio::spawn(async move {
+                                        if let Err(e) = handle_dns_request(
+                                            request_msg,
+                                            remote_addr,
+                                            handler_clone,
+                                            rate_limiter_clone,
+                                            socket_clone,
+                                        )
+                                        .await
+                                        {
+                                            error!("Failed to handle DNS request from {}: {}", remote_addr, e);
+                                        }
+                                    });
+                                }
+                                Err(e) => {
+                                    warn!("Failed to parse DNS message from {}: {}", remote_addr, e);
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!("UDP socket error: {}", e);
+                            // Small delay to prevent tight loop on persistent errors
+                            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(snapshot) = &snapshot {
+            save_snapshot(snapshot, &self.handler.cache).await;
+        }
+
+        info!("DNS server shutdown complete");
+        Ok(())
+    }
+
+    /// Triggers graceful shutdown of the server
+    ///
+    /// This sends a shutdown signal to the running server, allowing it to
+    /// complete in-flight requests and clean up resources.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if shutdown signal was sent successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns error if no receivers are listening (server not running)
+    pub fn shutdown(&self) -> Result<()> {
+        self.shutdown_tx
+            .send(())
+            .context("Failed to send shutdown signal")?;
+        Ok(())
+    }
+
+    /// Returns the configured bind address
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.config.dns_address, self.config.dns_port)
+    }
+}
+
+/// The rate limiter `config` describes
+fn rate_limiter(config: &Config) -> IpRateLimiter {
+    IpRateLimiter::new(config.rate_limit_rps, config.rate_limit_burst)
+        .with_prefixes(config.rate_limit_ipv4_prefix, config.rate_limit_ipv6_prefix)
+        .with_max_clients(config.rate_limit_max_clients)
+}
+
+/// Saves a cache snapshot, logging rather than failing: a missed snapshot
+/// only costs a colder start
+async fn save_snapshot(snapshot: &CacheSnapshot, cache: &DnsCache) {
+    match snapshot.save(cache).await {
+        Ok(saved) => debug!(
+            "Saved {} cached answers to {}",
+            saved,
+            snapshot.path().display()
+        ),
+        Err(e) => warn!("Failed to save cache snapshot: {:#}", e),
+    }
+}
+
+/// Size of the UDP receive buffer.
+///
+/// Large enough for any EDNS0 query a client may send; anything beyond this is
+/// discarded by the kernel and will fail to parse, which is the correct outcome
+/// for a datagram that large.
+const MAX_UDP_REQUEST: usize = 4096;
+
+/// Largest UDP response permitted to a client that sent no EDNS0 OPT record.
+///
+/// RFC 1035 §4.2.1 fixes the plain-DNS UDP message size at 512 bytes.
+const DEFAULT_MAX_UDP_RESPONSE: usize = 512;
+
+/// Ceiling applied even when a client advertises a larger EDNS0 buffer.
+///
+/// 1232 bytes is the DNS Flag Day 2020 recommendation: it stays under the
+/// common 1280-byte IPv6 MTU so responses avoid IP fragmentation, and it bounds
+/// the amplification factor available to an attacker spoofing a source address.
+const MAX_UDP_RESPONSE: usize = 1232;
+
+/// Determine how many bytes of UDP response this client may receive.
+///
+/// A client advertises its receive buffer in the EDNS0 OPT record (RFC 6891).
+/// Absent that, plain DNS limits us to [`DEFAULT_MAX_UDP_RESPONSE`]. The value
+/// is clamped into `[DEFAULT_MAX_UDP_RESPONSE, MAX_UDP_RESPONSE]` so a spoofed
+/// query cannot request a large datagram.
+fn client_udp_payload_size(request: &Message) -> usize {
+    request
+        .edns
+        .as_ref()
+        .map(|edns| edns.max_payload() as usize)
+        .unwrap_or(DEFAULT_MAX_UDP_RESPONSE)
+        .clamp(DEFAULT_MAX_UDP_RESPONSE, MAX_UDP_RESPONSE)
+}
+
+/// Builds the DNS response for a single request
+///
+/// This is the transport-independent half of request handling: it applies
+/// the per-client rate limit, answers each question and sets the response
+/// code. Size limits are left to the caller, because they differ by
+/// transport: UDP must fit the client's advertised buffer, while TCP frames
+/// may carry up to 64 KiB.
+///
+/// # Arguments
+///
+/// * `request_msg` - Parsed DNS request message
+/// * `remote_addr` - Address of the client
+/// * `handler` - LLM DNS handler for processing queries
+/// * `rate_limiter` - Per-client rate limiter
+///
+/// # Returns
+///
+/// The response message, ready to be serialized
+pub(crate) async fn build_response(
+    request_msg: &Message,
+    remote_addr: SocketAddr,
+    handler: &LlmDnsHandler,
+    rate_limiter: &IpRateLimiter,
+) -> Message {
+    // Check rate limit first
+    if !rate_limiter.check_allowed(remote_addr.ip()) {
+        warn!("Rate limit exceeded for client {}", remote_addr);
+        let mut response = Message::new(
+            request_msg.metadata.id,
+            MessageType::Response,
+            OpCode::Query,
+        );
+        response.metadata.recursion_available = false;
+        response.metadata.recursion_desired = request_msg.metadata.recursion_desired;
+        response.metadata.authoritative = true;
+        response.metadata.response_code = ResponseCode::Refused;
+        return response;
+    }
+
+    // Create DNS response message
+    let mut response = Message::new(
+        request_msg.metadata.id,
+        MessageType::Response,
+        OpCode::Query,
+    );
+    response.metadata.recursion_available = false;
+    response.metadata.recursion_desired = request_msg.metadata.recursion_desired;
+    response.add_queries(request_msg.queries.clone());
+
+    // Set authoritative answer bit
+    response.metadata.authoritative = true;
+
+    // Process each query in the request
+    let mut response_code = ResponseCode::NoError;
+
+    for query in &request_msg.queries {
+        debug!(
+            "Processing query: {} {:?}",
+            query.name(),
+            query.query_type()
+        );
+
+        let outcome = handler.answer(query).await;
+        if outcome.response_code != ResponseCode::NoError {
+            response_code = outcome.response_code;
+        }
+        response.add_answers(outcome.answers);
+        response.add_authorities(outcome.authorities);
+    }
+
+    // A refusal means the name is not ours, so the answer is not authoritative.
+    if response_code == ResponseCode::Refused {
+        response.metadata.authoritative = false;
+    }
+
+    // Set response code
+    response.metadata.response_code = response_code;
+    response
+}
+
+/// Handles a single incoming UDP DNS request and sends the response
+///
+/// # Arguments
+///
+/// * `request_msg` - Parsed DNS request message
+/// * `remote_addr` - Address of the client
+/// * `handler` - LLM DNS handler for processing queries
+/// * `socket` - UDP socket for sending responses
+///
+/// # Returns
+///
+/// Ok(()) when response is sent successfully
+///
+/// # Errors
+///
+/// Returns error if:
+/// - DNS response serialization fails
+/// - UDP send fails
+async fn handle_dns_request(
+    request_msg: Message,
+    remote_addr: SocketAddr,
+    handler: Arc<LlmDnsHandler>,
+    rate_limiter: Arc<IpRateLimiter>,
+    socket: Arc<UdpSocket>,
+) -> Result<()> {
+    let response = build_response(&request_msg, remote_addr, &handler, &rate_limiter).await;
+
+    // Serialize DNS response to bytes
+    let mut response_bytes = response.to_vec()?;
+
+    // Cap the datagram at what the client is entitled to receive. UDP source
+    // addresses are trivially spoofed, so an unbounded response turns this
+    // server into an amplifier: a ~50 byte query would otherwise return up to
+    // the chunker's 4096 byte limit. Over the budget we drop the answers and
+    // set TC, which tells a legitimate client to retry over TCP (RFC 1035
+    // §4.2.1) while giving a spoofing attacker almost no amplification.
+    let max_response_size = client_udp_payload_size(&request_msg);
+    if response_bytes.len() > max_response_size {
+        debug!(
+            "Response {} bytes exceeds client budget {}, truncating",
+            response_bytes.len(),
+            max_response_size
+        );
+        response_bytes = response.truncate().to_vec()?;
+    }
+
+    debug!(
+        "Serialized response: {} bytes, code: {:?}",
+        response_bytes.len(),
+        response.metadata.response_code
+    );
+
+    // Send response back to client
+    socket
+        .send_to(&response_bytes, remote_addr)
+        .await
+        .context("Failed to send DNS response")?;
+
+    debug!("Successfully sent response to {}", remote_addr);
+    Ok(())
+}
+
+/// An LLM client for tests, asking the API at `url`
+#[cfg(test)]
+pub(crate) fn test_llm_client(url: String) -> LlmClient {
+    test_llm_client_with_system_prompt(url, "Test system prompt")
+}
+
+/// An LLM client for tests, asking the API at `url` under `system_prompt`
+#[cfg(test)]
+pub(crate) fn test_llm_client_with_system_prompt(url: String, system_prompt: &str) -> LlmClient {
+    LlmClient::new(
+        "key".to_string(),
+        vec!["model".to_string()],
+        system_prompt.to_string(),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .unwrap()
+    .with_base_url(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TxtFraming;
+    use hickory_server::proto::op::Edns;
+
+    #[test]
+    fn test_server_creation() -> Result<()> {
+        let config = Config {
+            openrouter_api_key: "test_key".to_string(),
+            openrouter_models: vec!["test_model".to_string()],
+            llm_base_url: "https://openrouter.ai/api/v1/chat/completions".to_string(),
+            system_prompt: "Test system prompt".to_string(),
+            dns_address: "127.0.0.1".to_string(),
+            dns_port: 15353,
+            temperature: None,
+            max_tokens: None,
+            top_p: None,
+            top_k: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            cache_ttl_seconds: 300,
+            rate_limit_rps: 5.0,
+            rate_limit_burst: 10.0,
+            rate_limit_ipv4_prefix: 32,
+            rate_limit_ipv6_prefix: 64,
+            rate_limit_max_clients: 100_000,
+            max_concurrent_llm_requests: 32,
+            cache_max_entries: 10000,
+            cache_max_bytes: 64 << 20,
+            cache_stale_seconds: 0,
+            cache_prefetch_hits: 0,
+            negative_ttl_bad_request_seconds: 60,
+            negative_ttl_filtered_seconds: 300,
+            negative_ttl_model_seconds: 30,
+            cache_snapshot_path: None,
+            cache_snapshot_interval_seconds: 60,
+            cache_snapshot_max_bytes: 16 << 20,
+            cache_redis_url: None,
+            cache_ttl_rules: None,
+            cache_similarity_threshold: None,
+            tcp_idle_timeout_seconds: 10,
+            tcp_max_connections: 256,
+            tls_cert_path: None,
+            tls_key_path: None,
+            dot_port: 853,
+            http_port: None,
+            admin_token: None,
+            dns_zone: None,
+            dns_zone_nameservers: Vec::new(),
+            dns_zone_hostmaster: None,
+            hyphens_as_spaces: false,
+            upload_max_sessions: 256,
+            upload_max_bytes: 16384,
+            upload_ttl_seconds: 120,
+            ticket_max: 0,
+            ticket_ttl_seconds: 300,
+            response_deadline_ms: 0,
+            deadline_reply: DeadlineReply::ServFail,
+            txt_framing: TxtFraming::Records,
+        };
+
+        let server = Server::new(config)?;
+        assert_eq!(server.bind_address(), "127.0.0.1:15353");
+        Ok(())
+    }
+
+    #[test]
+    fn test_handler_creation() {
+        let llm_client = Arc::new(
+            LlmClient::new(
+                "key".to_string(),
+                vec!["model".to_string()],
+                "Test system prompt".to_string(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let chunker = Arc::new(Chunker::new());
+        let dns_handler = Arc::new(DnsHandler::new());
+        let cache = Arc::new(DnsCache::new(Duration::from_secs(300)));
+
+        let handler = LlmDnsHandler::new(llm_client, chunker, dns_handler, cache);
+
+        // Handler should be created successfully
+        assert!(Arc::strong_count(&handler.llm_client) > 0);
+    }
+
+    #[test]
+    fn test_udp_payload_size_defaults_to_512_without_edns() {
+        // A plain-DNS client advertises nothing, so RFC 1035's 512 byte cap applies.
+        let msg = Message::new(1, MessageType::Query, OpCode::Query);
+        assert_eq!(client_udp_payload_size(&msg), DEFAULT_MAX_UDP_RESPONSE);
+    }
+
+    #[test]
+    fn test_udp_payload_size_honors_edns_advertisement() {
+        let mut msg = Message::new(1, MessageType::Query, OpCode::Query);
+        let mut edns = Edns::new();
+        edns.set_max_payload(1000);
+        msg.set_edns(edns);
+
+        assert_eq!(client_udp_payload_size(&msg), 1000);
+    }
+
+    #[test]
+    fn test_udp_payload_size_clamps_oversized_advertisement() {
+        // An attacker spoofing a source address would advertise the largest
+        // buffer possible to maximise amplification. Cap it.
+        let mut msg = Message::new(1, MessageType::Query, OpCode::Query);
+        let mut edns = Edns::new();
+        edns.set_max_payload(u16::MAX);
+        msg.set_edns(edns);
+
+        assert_eq!(client_udp_payload_size(&msg), MAX_UDP_RESPONSE);
+    }
+
+    #[test]
+    fn test_udp_payload_size_raises_undersized_advertisement() {
+        // Below 512 we still owe the client a usable response.
+        let mut msg = Message::new(1, MessageType::Query, OpCode::Query);
+        let mut edns = Edns::new();
+        edns.set_max_payload(0);
+        msg.set_edns(edns);
+
+        assert_eq!(client_udp_payload_size(&msg), DEFAULT_MAX_UDP_RESPONSE);
+    }
+
+    #[test]
+    fn test_oversized_response_truncates_and_sets_tc_bit() {
+        // Build a response far larger than any client budget, mirroring what a
+        // long LLM answer produces, and confirm the wire form we would send is
+        // bounded and carries TC so the client retries over TCP.
+        let name = Name::from_utf8("what.is.rust.").unwrap();
+        let mut response = Message::new(42, MessageType::Response, OpCode::Query);
+        response.metadata.authoritative = true;
+        for _ in 0..16 {
+            let txt = TXT::new(vec!["x".repeat(250)]);
+            response.add_answer(Record::from_rdata(name.clone(), 300, RData::TXT(txt)));
+        }
+
+        let full = response.to_vec().unwrap();
+        assert!(
+            full.len() > MAX_UDP_RESPONSE,
+            "fixture should exceed the cap, got {} bytes",
+            full.len()
+        );
+
+        let truncated = response.truncate();
+        let bytes = truncated.to_vec().unwrap();
+
+        assert!(truncated.metadata.truncation, "TC bit must be set");
+        assert!(truncated.answers.is_empty(), "answers must be dropped");
+        assert!(
+            bytes.len() <= DEFAULT_MAX_UDP_RESPONSE,
+            "truncated response should fit the smallest budget, got {} bytes",
+            bytes.len()
+        );
+        assert_eq!(truncated.metadata.id, 42, "query id must be preserved");
+    }
+
+    fn test_handler_with_limit(limit: usize) -> LlmDnsHandler {
+        let llm_client = Arc::new(
+            LlmClient::new(
+                "key".to_string(),
+                vec!["model".to_string()],
+                "Test system prompt".to_string(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        LlmDnsHandler::new(
+            llm_client,
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_max_concurrent_llm_requests(limit)
+    }
+
+    #[tokio::test]
+    async fn test_query_is_shed_when_llm_concurrency_exhausted() {
+        // With every permit held, a further query must be refused *before* the
+        // outbound call - so this asserts the shed without touching the network.
+        let handler = test_handler_with_limit(1);
+        let sem = handler.llm_permits.clone().expect("limit should be active");
+        let _held = sem.try_acquire_owned().expect("first permit available");
+
+        let name = Name::from_utf8("what.is.rust.").unwrap();
+        let err = handler
+            .process_query(&name)
+            .await
+            .expect_err("query should be shed while permits are exhausted");
+
+        assert!(
+            err.to_string().contains("concurrency limit"),
+            "unexpected error: {err}"
+        );
+        assert!(err.is::<LlmBusy>());
+
+        // The HTTP API draws from the same permits.
+        let err = handler
+            .ask("what is rust")
+            .await
+            .expect_err("ask should be shed while permits are exhausted");
+        assert!(err.is::<LlmBusy>());
+    }
+
+    #[tokio::test]
+    async fn test_permit_is_released_after_query() {
+        // A shed query must not leak its permit, or the server would wedge shut
+        // after the first burst.
+        let handler = test_handler_with_limit(1);
+        let sem = handler.llm_permits.clone().expect("limit should be active");
+
+        {
+            let _held = sem.clone().try_acquire_owned().unwrap();
+            let name = Name::from_utf8("first.query.").unwrap();
+            assert!(handler.process_query(&name).await.is_err());
+        }
+
+        assert_eq!(
+            sem.available_permits(),
+            1,
+            "permit was not returned after the shed query"
+        );
+    }
+
+    #[test]
+    fn test_zero_limit_disables_llm_concurrency_cap() {
+        assert!(test_handler_with_limit(0).llm_permits.is_none());
+        assert!(test_handler_with_limit(4).llm_permits.is_some());
+    }
+
+    async fn zone_response(name: &str, record_type: RecordType) -> Message {
+        let zone = Zone::new("llm.example.com").unwrap();
+        let handler = test_handler_with_limit(0).with_zone(zone);
+        let mut request = Message::new(7, MessageType::Query, OpCode::Query);
+        request.add_query(Query::query(Name::from_utf8(name).unwrap(), record_type));
+
+        let rate_limiter = IpRateLimiter::new(0.0, 0.0);
+        build_response(
+            &request,
+            "127.0.0.1:5353".parse().unwrap(),
+            &handler,
+            &rate_limiter,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_zone_apex_answers_soa_and_ns() {
+        let response = zone_response("llm.example.com.", RecordType::SOA).await;
+        assert_eq!(response.metadata.response_code, ResponseCode::NoError);
+        assert!(response.metadata.authoritative);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].record_type(), RecordType::SOA);
+
+        let response = zone_response("llm.example.com.", RecordType::NS).await;
+        assert_eq!(response.metadata.response_code, ResponseCode::NoError);
+        assert_eq!(response.answers[0].record_type(), RecordType::NS);
+    }
+
+    #[tokio::test]
+    async fn test_zone_negative_answers_carry_soa() {
+        // NODATA: the apex has no A record, and prompts only have TXT.
+        for name in ["llm.example.com.", "what.is.rust.llm.example.com."] {
+            let response = zone_response(name, RecordType::A).await;
+            assert_eq!(response.metadata.response_code, ResponseCode::NoError);
+            assert!(response.answers.is_empty());
+            assert_eq!(response.authorities.len(), 1);
+            assert_eq!(response.authorities[0].record_type(), RecordType::SOA);
+        }
+
+        // NXDOMAIN: service labels are never prompts.
+        let response = zone_response("_dmarc.llm.example.com.", RecordType::TXT).await;
+        assert_eq!(response.metadata.response_code, ResponseCode::NXDomain);
+        assert_eq!(response.authorities[0].record_type(), RecordType::SOA);
+    }
+
+    #[tokio::test]
+    async fn test_zone_refuses_names_outside_it() {
+        let response = zone_response("what.is.rust.", RecordType::TXT).await;
+        assert_eq!(response.metadata.response_code, ResponseCode::Refused);
+        assert!(!response.metadata.authoritative);
+        assert!(response.answers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_zone_origin_is_stripped_from_prompt() {
+        let mut llm = mockito::Server::new_async().await;
+        let _mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "messages": [{"role": "system"}, {"role": "user", "content": "what is rust"}]
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "A language"}}]}"#)
+            .create_async()
+            .await;
+
+        let llm_client = test_llm_client(llm.url());
+        let handler = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_zone(Zone::new("llm.example.com").unwrap());
+
+        let name = Name::from_utf8("what.is.rust.llm.example.com.").unwrap();
+        let outcome = handler.answer(&Query::query(name, RecordType::TXT)).await;
+
+        assert_eq!(outcome.response_code, ResponseCode::NoError);
+        assert_eq!(outcome.answers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_base64url_prompts_are_cached_by_case() {
+        let mut llm = mockito::Server::new_async().await;
+        let mut mocks = Vec::new();
+        for (prompt, reply) in [("Rust", "A language"), ("RuYt", "Not a word")] {
+            let mock = llm
+                .mock("POST", mockito::Matcher::Any)
+                .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                    "messages": [{"role": "system"}, {"role": "user", "content": prompt}]
+                })))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(
+                    serde_json::json!({"choices": [{"message": {"content": reply}}]}).to_string(),
+                )
+                .expect(1)
+                .create_async()
+                .await;
+            mocks.push(mock);
+        }
+
+        let llm_client = test_llm_client(llm.url());
+        let handler = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        );
+
+        // Same name up to case, different prompts
+        let rust = Name::from_ascii("b64.UnVzdA.").unwrap();
+        let ruyt = Name::from_ascii("b64.UnVZdA.").unwrap();
+
+        let first = handler.process_query(&rust).await.unwrap();
+        let second = handler.process_query(&ruyt).await.unwrap();
+        let cached = handler.process_query(&rust).await.unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(first, cached);
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spellings_share_an_answer_until_the_config_changes() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let cache = Arc::new(DnsCache::new(Duration::from_secs(300)));
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            cache.clone(),
+        );
+        for name in ["what.is.rust.", "What-Is-Rust.", "what_is_rust."] {
+            let name = Name::from_ascii(name).unwrap();
+            let records = handler.process_query(&name).await.unwrap();
+            assert!(records.iter().all(|r| r.name == name), "{}", name);
+        }
+
+        // Another system prompt must not be answered from the old entries
+        let llm_client = test_llm_client_with_system_prompt(llm.url(), "Answer in French");
+        let reconfigured = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            cache,
+        );
+        let name = Name::from_ascii("what.is.rust.").unwrap();
+        assert!(reconfigured.process_query(&name).await.is_ok());
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_encoded_prompts_are_keyed_as_spelled() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "It prints"}}]}"#)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let cache = Arc::new(DnsCache::new(Duration::from_secs(300)));
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            cache.clone(),
+        );
+        // print(X.y), print(x.y), print(X_y), then print(X.y) with trailing spaces
+        let names = [
+            "b64.cHJpbnQoWC55KQ.",
+            "b64.cHJpbnQoeC55KQ.",
+            "b64.cHJpbnQoWF95KQ.",
+            "b64.cHJpbnQoWC55KSAg.",
+        ];
+        for name in names {
+            let name = Name::from_ascii(name).unwrap();
+            assert!(handler.process_query(&name).await.is_ok(), "{}", name);
+        }
+        assert_eq!(cache.len().await, 3);
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_ttl_policy_shortens_lifetimes() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "It depends"}}]}"#)
+            .expect(4)
+            .create_async()
+            .await;
+
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_ttl_policy(TtlPolicy::parse("weather|today=0;^define =60").unwrap());
+        let ttl = |name: &'static str| {
+            let handler = handler.clone();
+            async move {
+                let name = Name::from_ascii(name).unwrap();
+                handler.process_query(&name).await.unwrap()[0].ttl
+            }
+        };
+
+        // Neither we nor resolvers keep time-sensitive answers
+        assert_eq!(ttl("weather.in.hanoi.today.").await, 0);
+        assert_eq!(ttl("weather.in.hanoi.today.").await, 0);
+
+        assert_eq!(ttl("define.ownership.").await, 60);
+        assert!(ttl("define.ownership.").await <= 60);
+        assert_eq!(ttl("what.is.rust.").await, 300);
+        assert!(ttl("what.is.rust.").await <= 300);
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_uncached_pages_are_held_for_the_page_walk() {
+        let answer = "0123456789".repeat(4);
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::with_sizes(10, 25)),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_ttl_policy(TtlPolicy::parse("weather=0").unwrap());
+
+        // Every page comes from the one LLM call, still with no TTL
+        for name in ["weather.today.", "p2.weather.today."] {
+            let name = Name::from_ascii(name).unwrap();
+            let records = handler.process_query(&name).await.unwrap();
+            assert!(records.iter().all(|r| r.ttl == 0));
+        }
+
+        // The first page is not held, so asking again asks the LLM
+        let name = Name::from_ascii("weather.today.").unwrap();
+        handler.process_query(&name).await.unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_similar_prompts_share_an_answer() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "A systems language"}}]}"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_similar_prompts(SimilarPrompts::new(0.6, 0));
+
+        let asked = Name::from_ascii("what.is.rust.lang.").unwrap();
+        handler.process_query(&asked).await.unwrap();
+
+        // A rewording is answered from the cache, under its own name
+        let reworded = Name::from_ascii("what.is.rust.language.").unwrap();
+        let records = handler.process_query(&reworded).await.unwrap();
+        assert_eq!(records[0].name, reworded);
+        assert!(records[0].to_string().contains("A systems language"));
+
+        // Another question is still asked
+        let other = Name::from_ascii("capital.of.france.").unwrap();
+        handler.process_query(&other).await.unwrap();
+        handler.process_query(&asked).await.unwrap();
+
+        assert_eq!(
+            handler.hit_stats(),
+            CacheHitStats {
+                exact: 1,
+                shared: 0,
+                similar: 1
+            }
+        );
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_similar_answer_pages_follow_the_first_page() {
+        let answer = "0123456789".repeat(4);
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::with_sizes(10, 25)),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_similar_prompts(SimilarPrompts::new(0.6, 0));
+        let ask = |name: &'static str| {
+            let handler = handler.clone();
+            async move {
+                handler
+                    .process_query(&Name::from_ascii(name).unwrap())
+                    .await
+            }
+        };
+
+        ask("what.is.rust.lang.").await.unwrap();
+
+        // Later pages are not matched by similarity on their own
+        let err = ask("p2.what.is.rust.language.").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PromptError>(),
+            Some(&PromptError::PageExpired(2))
+        );
+
+        // Once the first page matched, the rest of that answer follows it
+        let first = ask("what.is.rust.language.").await.unwrap();
+        let second = ask("p2.what.is.rust.language.").await.unwrap();
+        assert!(first.last().unwrap().to_string().contains("page 1/2"));
+        assert!(second.last().unwrap().to_string().contains("page 2/2"));
+        assert_eq!(
+            handler.hit_stats(),
+            CacheHitStats {
+                exact: 1,
+                shared: 0,
+                similar: 1
+            }
+        );
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_replicas_share_answers_through_the_shared_cache() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let store = crate::cache::resp::stand_in::StandIn::spawn(None).await;
+        let shared: Arc<dyn CacheBackend> = Arc::new(RespCache::new(&store.url()).unwrap());
+        let replica = || {
+            LlmDnsHandler::new(
+                Arc::new(test_llm_client(llm.url())),
+                Arc::new(Chunker::new()),
+                Arc::new(DnsHandler::new()),
+                Arc::new(DnsCache::new(Duration::from_secs(300))),
+            )
+            .with_shared_cache(shared.clone())
+        };
+        let (first, second) = (replica(), replica());
+
+        let name = Name::from_ascii("what.is.rust.").unwrap();
+        let answer = first.process_query(&name).await.unwrap();
+
+        // The write-through does not hold up the answer
+        let key = first.cache_key(&name).unwrap().page(1);
+        for _ in 0..100 {
+            if shared.get(&key).await.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let asked = Name::from_ascii("What-Is-Rust.").unwrap();
+        let records = second.process_query(&asked).await.unwrap();
+        assert_eq!(records.len(), answer.len());
+        assert!(records.iter().all(|r| r.name == asked));
+        assert!(second.cache.get(&key).await.is_some());
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_purges_reach_every_shared_page_and_the_similar_index() {
+        let answer = "0123456789".repeat(4);
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let store = crate::cache::resp::stand_in::StandIn::spawn(None).await;
+        let shared: Arc<dyn CacheBackend> = Arc::new(RespCache::new(&store.url()).unwrap());
+        let replica = || {
+            LlmDnsHandler::new(
+                Arc::new(test_llm_client(llm.url())),
+                Arc::new(Chunker::with_sizes(10, 25)),
+                Arc::new(DnsHandler::new()),
+                Arc::new(DnsCache::new(Duration::from_secs(300))),
+            )
+            .with_shared_cache(shared.clone())
+            .with_similar_prompts(SimilarPrompts::new(0.6, 0))
+        };
+        let (first, second) = (replica(), replica());
+
+        let mut pages = 0;
+        for name in ["tell.me.a.story.", "what.is.rust."] {
+            let records = first
+                .process_query(&Name::from_ascii(name).unwrap())
+                .await
+                .unwrap();
+            let marker = records.iter().rev().find_map(|r| match &r.data {
+                RData::TXT(txt) => {
+                    Chunker::parse_page_marker(std::str::from_utf8(&txt.txt_data[0]).unwrap())
+                }
+                _ => None,
+            });
+            pages += marker.unwrap().1;
+        }
+        assert!(pages > 2);
+
+        // The write-through does not hold up the answer
+        let stored = || store.entries.lock().unwrap().len();
+        for _ in 0..100 {
+            if stored() == pages {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(stored(), pages);
+
+        // A prefix purge finds the shared pages through this replica's memory
+        let purged = first.purge_prefix("Tell me").await;
+        assert!(purged.pages > 1);
+        assert_eq!(purged.shared_error, None);
+        assert!(store
+            .entries
+            .lock()
+            .unwrap()
+            .keys()
+            .all(|key| !String::from_utf8_lossy(key).contains("story")));
+        assert_eq!(first.similar.as_deref().unwrap().len(), 1);
+
+        // A replica that never held the answer still purges every shared
+        // page, even once the first has gone
+        let name = Name::from_ascii("what.is.rust.").unwrap();
+        let key = second.cache_key(&name).unwrap();
+        let first_page = format!("llm-over-dns:{}", key.page(1));
+        let removed = store.entries.lock().unwrap().remove(first_page.as_bytes());
+        assert!(removed.is_some());
+        assert!(stored() > 0);
+        assert_eq!(second.purge_name(&name).await.unwrap(), Purged::default());
+        assert_eq!(stored(), 0);
+
+        // A store that cannot be reached is reported
+        drop(store);
+        let purged = second.purge_name(&name).await.unwrap();
+        assert!(purged.shared_error.is_some());
+        let purged = first.purge_prefix("what").await;
+        assert!(purged.pages > 1);
+        assert!(purged.shared_error.unwrap().starts_with("1 of 1 answers"));
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_uploaded_prompt_is_asked_on_go() {
+        let prompt = "Explain this panic: thread 'main' panicked at src/main.rs:2:5";
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "messages": [{"role": "system"}, {"role": "user", "content": prompt}]
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "An unwrap on None"}}]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let llm_client = test_llm_client(llm.url());
+        let handler = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_zone(Zone::new("llm.example.com").unwrap())
+        .with_uploads(UploadSessions::new(4, 1024, Duration::from_secs(60)));
+
+        let txt = |name: String| Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT);
+
+        // Upload the second half first; order does not matter
+        let (first, second) = prompt.split_at(30);
+        for (seq, part) in [(1, second), (0, first)] {
+            let data = data_encoding::BASE32_NOPAD.encode(part.as_bytes());
+            let outcome = handler
+                .answer(&txt(format!("{}.{}.s1.up.llm.example.com.", data, seq)))
+                .await;
+            assert_eq!(outcome.response_code, ResponseCode::NoError);
+            assert_eq!(outcome.answers[0].ttl, 0);
+        }
+
+        let go = txt("go.s1.up.llm.example.com.".to_string());
+        let outcome = handler.answer(&go).await;
+        assert_eq!(outcome.response_code, ResponseCode::NoError);
+        assert_eq!(outcome.answers.len(), 1);
+
+        // A retried `go` asks the same prompt, which is answered from cache
+        let retried = handler.answer(&go).await;
+        assert_eq!(retried.answers, outcome.answers);
+        mock.assert_async().await;
+
+        let outcome = handler
+            .answer(&txt("go.nosuch.up.llm.example.com.".to_string()))
+            .await;
+        assert_eq!(outcome.response_code, ResponseCode::NXDomain);
+        assert_eq!(outcome.authorities.len(), 1);
+
+        let outcome = handler
+            .answer(&txt("not-base32.0.s2.up.llm.example.com.".to_string()))
+            .await;
+        assert_eq!(outcome.response_code, ResponseCode::FormErr);
+    }
+
+    #[tokio::test]
+    async fn test_reused_upload_session_asks_the_new_prompt() {
+        let mut llm = mockito::Server::new_async().await;
+        let mut mocks = Vec::new();
+        for (prompt, answer) in [("first upload", "one"), ("second upload", "two")] {
+            let mock = llm
+                .mock("POST", mockito::Matcher::Any)
+                .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                    "messages": [{"role": "system"}, {"role": "user", "content": prompt}]
+                })))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(
+                    serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
+                )
+                .expect(1)
+                .create_async()
+                .await;
+            mocks.push(mock);
+        }
+
+        let llm_client = test_llm_client(llm.url());
+        let handler = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_zone(Zone::new("llm.example.com").unwrap())
+        .with_uploads(UploadSessions::new(4, 1024, Duration::from_secs(60)));
+
+        let txt = |name: String| Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT);
+        let go = txt("go.s1.up.llm.example.com.".to_string());
+
+        let mut answers = Vec::new();
+        for prompt in ["first upload", "second upload"] {
+            let data = data_encoding::BASE32_NOPAD.encode(prompt.as_bytes());
+            let outcome = handler
+                .answer(&txt(format!("{}.0.s1.up.llm.example.com.", data)))
+                .await;
+            assert_eq!(outcome.response_code, ResponseCode::NoError);
+
+            let outcome = handler.answer(&go).await;
+            assert_eq!(outcome.response_code, ResponseCode::NoError);
+            answers.push(outcome.answers);
+        }
 
-    // Cap the datagram at what the client is entitled to receive. UDP source
-    // addresses are trivially spoofed, so an unbounded response turns this
-    // server into an amplifier: a ~50 byte query would otherwise return up to
-    // the chunker's 4096 byte limit. Over the budget we drop the answers and
-    // set TC, which tells a legitimate client to retry over TCP (RFC 1035
-    // §4.2.1) while giving a spoofing attacker almost no amplification.
-    let max_response_size = client_udp_payload_size(&request_msg);
-    if response_bytes.len() > max_response_size {
-        debug!(
-            "Response {} bytes exceeds client budget {}, truncating",
-            response_bytes.len(),
-            max_response_size
+        // The second upload is asked, not answered with the first one's answer
+        assert_ne!(answers[0], answers[1]);
+        assert_eq!(
+            answers[1][0].data,
+            RData::TXT(TXT::new(vec!["two".to_string()]))
         );
-        response_bytes = response.truncate().to_vec()?;
+        for mock in mocks {
+            mock.assert_async().await;
+        }
     }
 
-    debug!(
-        "Serialized response: {} bytes, code: {:?}",
-        response_bytes.len(),
-        response_code
-    );
+    #[tokio::test]
+    async fn test_ticket_is_answered_in_the_background() {
+        let answer = "0123456789".repeat(4);
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "messages": [{"role": "system"}, {"role": "user", "content": "count"}]
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
 
-    // Send response back to client
-    socket
-        .send_to(&response_bytes, remote_addr)
-        .await
-        .context("Failed to send DNS response")?;
+        let llm_client = test_llm_client(llm.url());
+        let cache = Arc::new(DnsCache::new(Duration::from_secs(300)));
+        let handler = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::with_sizes(10, 25)),
+            Arc::new(DnsHandler::new()),
+            cache.clone(),
+        )
+        .with_zone(Zone::new("llm.example.com").unwrap())
+        .with_tickets(TicketStore::new(4, Duration::from_secs(60)));
 
-    debug!("Successfully sent response to {}", remote_addr);
-    Ok(())
-}
+        let ask = |name: String| {
+            let query = Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT);
+            let handler = &handler;
+            async move { handler.answer(&query).await }
+        };
+        let txt = |outcome: &QueryOutcome| -> Vec<String> {
+            outcome
+                .answers
+                .iter()
+                .map(|record| match &record.data {
+                    RData::TXT(txt) => String::from_utf8(txt.txt_data[0].to_vec()).unwrap(),
+                    other => panic!("expected TXT, got {:?}", other),
+                })
+                .collect()
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use hickory_server::proto::op::Edns;
+        // The ticket comes back before the LLM has been called
+        let outcome = ask("ask.count.llm.example.com.".to_string()).await;
+        assert_eq!(outcome.response_code, ResponseCode::NoError);
+        assert_eq!(outcome.answers[0].ttl, 0);
+        let ticket = txt(&outcome).remove(0);
+
+        let result = format!("{}.result.llm.example.com.", ticket);
+        let outcome = ask(result.clone()).await;
+        assert_eq!(txt(&outcome), vec![TICKET_PENDING]);
+        assert_eq!(outcome.answers[0].ttl, 0);
+
+        let mut outcome = outcome;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            outcome = ask(result.clone()).await;
+            if txt(&outcome) != vec![TICKET_PENDING] {
+                break;
+            }
+        }
+        assert_eq!(outcome.response_code, ResponseCode::NoError);
+        assert_eq!(outcome.answers[0].name, Name::from_ascii(&result).unwrap());
+        assert_eq!(txt(&outcome).last().unwrap(), "page 1/2");
+
+        // Later pages hang off the result name too, all kept on the ticket
+        cache.clear().await;
+        let outcome = ask(format!("p2.{}", result)).await;
+        assert_eq!(txt(&outcome).last().unwrap(), "page 2/2");
+        let outcome = ask(result.clone()).await;
+        assert_eq!(txt(&outcome).last().unwrap(), "page 1/2");
+        mock.assert_async().await;
+
+        let outcome = ask(format!("p3.{}", result)).await;
+        assert_eq!(outcome.response_code, ResponseCode::NXDomain);
+
+        let outcome = ask("abcdefghij234.result.llm.example.com.".to_string()).await;
+        assert_eq!(outcome.response_code, ResponseCode::NXDomain);
+
+        let outcome = ask("ask.xn--99999999999999999999.llm.example.com.".to_string()).await;
+        assert_eq!(outcome.response_code, ResponseCode::FormErr);
+    }
 
-    #[test]
-    fn test_server_creation() -> Result<()> {
-        let config = Config {
-            openrouter_api_key: "test_key".to_string(),
-            openrouter_models: vec!["test_model".to_string()],
-            llm_base_url: "https://openrouter.ai/api/v1/chat/completions".to_string(),
-            system_prompt: "Test system prompt".to_string(),
-            dns_address: "127.0.0.1".to_string(),
-            dns_port: 15353,
-            temperature: None,
-            max_tokens: None,
-            top_p: None,
-            top_k: None,
-            frequency_penalty: None,
-            presence_penalty: None,
-            cache_ttl_seconds: 300,
-            rate_limit_rps: 5.0,
-            rate_limit_burst: 10.0,
-            max_concurrent_llm_requests: 32,
-            cache_max_entries: 10000,
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_answer_past_deadline_is_served_on_retry() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|w| {
+                // A model slower than the deadline
+                std::thread::sleep(Duration::from_millis(300));
+                w.write_all(br#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#)
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
+        let llm_client = test_llm_client(llm.url());
+        let handler = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        )
+        .with_response_deadline(Duration::from_millis(50), DeadlineReply::Thinking);
+
+        let query = Query::query(Name::from_ascii("what.is.rust.").unwrap(), RecordType::TXT);
+        let text = |outcome: &QueryOutcome| match &outcome.answers[0].data {
+            RData::TXT(txt) => String::from_utf8(txt.txt_data[0].to_vec()).unwrap(),
+            other => panic!("expected TXT, got {:?}", other),
         };
 
-        let server = Server::new(config)?;
-        assert_eq!(server.bind_address(), "127.0.0.1:15353");
-        Ok(())
+        let outcome = handler.answer(&query).await;
+        assert_eq!(outcome.response_code, ResponseCode::NoError);
+        assert_eq!(text(&outcome), THINKING);
+        assert_eq!(outcome.answers[0].ttl, 0);
+
+        // A retry while the call runs does not start another one
+        let outcome = handler.answer(&query).await;
+        assert_eq!(text(&outcome), THINKING);
+
+        let mut outcome = outcome;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            outcome = handler.answer(&query).await;
+            if text(&outcome) != THINKING {
+                break;
+            }
+        }
+        assert_eq!(text(&outcome), "Rust is fast");
+        assert_eq!(outcome.answers[0].ttl, 300);
+        mock.assert_async().await;
+
+        let servfail =
+            handler.with_response_deadline(Duration::from_millis(50), DeadlineReply::ServFail);
+        let outcome = servfail.late_reply(query.name());
+        assert_eq!(outcome.response_code, ResponseCode::ServFail);
+        assert!(outcome.answers.is_empty());
     }
 
-    #[test]
-    fn test_handler_creation() {
-        let llm_client = Arc::new(
-            LlmClient::new(
-                "key".to_string(),
-                vec!["model".to_string()],
-                "Test system prompt".to_string(),
-                None,
-                None,
-                None,
-                None,
-                None,
-                None,
-            )
-            .unwrap(),
-        );
-        let chunker = Arc::new(Chunker::new());
-        let dns_handler = Arc::new(DnsHandler::new());
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_uncached_late_answer_is_held_for_the_retry() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|w| {
+                std::thread::sleep(Duration::from_millis(200));
+                w.write_all(br#"{"choices": [{"message": {"content": "Sunny"}}]}"#)
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
         let cache = Arc::new(DnsCache::new(Duration::from_secs(300)));
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            cache.clone(),
+        )
+        .with_ttl_policy(TtlPolicy::parse("weather=0").unwrap())
+        .with_response_deadline(Duration::from_millis(50), DeadlineReply::Thinking);
 
-        let handler = LlmDnsHandler::new(llm_client, chunker, dns_handler, cache);
+        let query = Query::query(Name::from_ascii("weather.today.").unwrap(), RecordType::TXT);
+        let text = |outcome: &QueryOutcome| match &outcome.answers[0].data {
+            RData::TXT(txt) => String::from_utf8(txt.txt_data[0].to_vec()).unwrap(),
+            other => panic!("expected TXT, got {:?}", other),
+        };
 
-        // Handler should be created successfully
-        assert!(Arc::strong_count(&handler.llm_client) > 0);
-    }
+        assert_eq!(text(&handler.answer(&query).await), THINKING);
 
-    #[test]
-    fn test_udp_payload_size_defaults_to_512_without_edns() {
-        // A plain-DNS client advertises nothing, so RFC 1035's 512 byte cap applies.
-        let msg = Message::new(1, MessageType::Query, OpCode::Query);
-        assert_eq!(client_udp_payload_size(&msg), DEFAULT_MAX_UDP_RESPONSE);
+        // The retry comes after the call landed, which cached nothing
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(cache.is_empty().await);
+        let outcome = handler.answer(&query).await;
+        assert_eq!(text(&outcome), "Sunny");
+        assert_eq!(outcome.answers[0].ttl, 0);
+        mock.assert_async().await;
     }
 
-    #[test]
-    fn test_udp_payload_size_honors_edns_advertisement() {
-        let mut msg = Message::new(1, MessageType::Query, OpCode::Query);
-        let mut edns = Edns::new();
-        edns.set_max_payload(1000);
-        msg.set_edns(edns);
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_misses_share_one_llm_call() {
+        for (status, body) in [
+            (
+                200,
+                r#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#,
+            ),
+            (400, r#"{"error": "bad request"}"#),
+        ] {
+            let mut llm = mockito::Server::new_async().await;
+            let mock = llm
+                .mock("POST", mockito::Matcher::Any)
+                .with_status(status)
+                .with_header("content-type", "application/json")
+                .with_chunked_body(move |w| {
+                    std::thread::sleep(Duration::from_millis(200));
+                    w.write_all(body.as_bytes())
+                })
+                .expect(1)
+                .create_async()
+                .await;
+
+            let llm_client = test_llm_client(llm.url());
+            let handler = Arc::new(
+                LlmDnsHandler::new(
+                    Arc::new(llm_client),
+                    Arc::new(Chunker::new()),
+                    Arc::new(DnsHandler::new()),
+                    Arc::new(DnsCache::new(Duration::from_secs(300))),
+                )
+                // One permit: a second uncoalesced call would be shed
+                .with_max_concurrent_llm_requests(1),
+            );
 
-        assert_eq!(client_udp_payload_size(&msg), 1000);
-    }
+            let tasks: Vec<_> = ["what.is.rust.", "WHAT.is.Rust.", "what.is.rust."]
+                .into_iter()
+                .map(|name| {
+                    let handler = handler.clone();
+                    let query = Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT);
+                    tokio::spawn(async move { handler.answer(&query).await })
+                })
+                .collect();
+
+            let mut outcomes = Vec::new();
+            for task in tasks {
+                outcomes.push(task.await.unwrap());
+            }
 
-    #[test]
-    fn test_udp_payload_size_clamps_oversized_advertisement() {
-        // An attacker spoofing a source address would advertise the largest
-        // buffer possible to maximise amplification. Cap it.
-        let mut msg = Message::new(1, MessageType::Query, OpCode::Query);
-        let mut edns = Edns::new();
-        edns.set_max_payload(u16::MAX);
-        msg.set_edns(edns);
+            let expected = match status {
+                200 => ResponseCode::NoError,
+                _ => ResponseCode::ServFail,
+            };
+            for outcome in &outcomes {
+                assert_eq!(outcome.response_code, expected);
+            }
+            // Each query keeps its own spelling of the name
+            if status == 200 {
+                assert_eq!(
+                    outcomes[1].answers[0].name,
+                    Name::from_ascii("WHAT.is.Rust.").unwrap()
+                );
+            }
 
-        assert_eq!(client_udp_payload_size(&msg), MAX_UDP_RESPONSE);
+            mock.assert_async().await;
+            assert_eq!(
+                handler.flight_stats(),
+                FlightStats {
+                    calls: 1,
+                    coalesced: 2
+                }
+            );
+        }
     }
 
-    #[test]
-    fn test_udp_payload_size_raises_undersized_advertisement() {
-        // Below 512 we still owe the client a usable response.
-        let mut msg = Message::new(1, MessageType::Query, OpCode::Query);
-        let mut edns = Edns::new();
-        edns.set_max_payload(0);
-        msg.set_edns(edns);
+    #[tokio::test]
+    async fn test_expired_answer_is_served_stale_when_llm_fails() {
+        let mut llm = mockito::Server::new_async().await;
+        let ok = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#)
+            .create_async()
+            .await;
+
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(
+                DnsCache::new(Duration::from_millis(50)).with_serve_stale(Duration::from_secs(60)),
+            ),
+        );
+        let query = Query::query(Name::from_ascii("what.is.rust.").unwrap(), RecordType::TXT);
+
+        let fresh = handler.answer(&query).await;
+        assert_eq!(fresh.response_code, ResponseCode::NoError);
+        // Cached for 50ms, which resolvers must not hold on to
+        assert_eq!(fresh.answers[0].ttl, 0);
+
+        ok.remove_async().await;
+        let down = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(503)
+            .with_body(r#"{"error": "unavailable"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let stale = handler.answer(&query).await;
+        down.assert_async().await;
+        assert_eq!(stale.response_code, ResponseCode::NoError);
+        assert_eq!(stale.answers.len(), fresh.answers.len());
+        assert_eq!(stale.answers[0].data, fresh.answers[0].data);
+        assert_eq!(stale.answers[0].ttl, STALE_TTL);
+
+        // Without a stale copy the failure shows
+        let other = Query::query(Name::from_ascii("what.is.go.").unwrap(), RecordType::TXT);
+        assert_eq!(
+            handler.answer(&other).await.response_code,
+            ResponseCode::ServFail
+        );
+    }
 
-        assert_eq!(client_udp_payload_size(&msg), DEFAULT_MAX_UDP_RESPONSE);
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_expired_answer_is_served_stale_past_deadline() {
+        let mut llm = mockito::Server::new_async().await;
+        let ok = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#)
+            .create_async()
+            .await;
+
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(
+                DnsCache::new(Duration::from_millis(50)).with_serve_stale(Duration::from_secs(60)),
+            ),
+        )
+        .with_response_deadline(Duration::from_millis(100), DeadlineReply::Thinking);
+        let query = Query::query(Name::from_ascii("what.is.rust.").unwrap(), RecordType::TXT);
+        assert_eq!(
+            handler.answer(&query).await.response_code,
+            ResponseCode::NoError
+        );
+
+        ok.remove_async().await;
+        llm.mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|w| {
+                std::thread::sleep(Duration::from_millis(400));
+                w.write_all(br#"{"choices": [{"message": {"content": "Rust is new"}}]}"#)
+            })
+            .create_async()
+            .await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let stale = handler.answer(&query).await;
+        assert_eq!(stale.answers[0].ttl, STALE_TTL);
+        let RData::TXT(txt) = &stale.answers[0].data else {
+            panic!("Expected TXT record data");
+        };
+        assert_eq!(&*txt.txt_data[0], b"Rust is fast");
     }
 
-    #[test]
-    fn test_oversized_response_truncates_and_sets_tc_bit() {
-        // Build a response far larger than any client budget, mirroring what a
-        // long LLM answer produces, and confirm the wire form we would send is
-        // bounded and carries TC so the client retries over TCP.
-        let name = Name::from_utf8("what.is.rust.").unwrap();
-        let mut response = Message::new(42, MessageType::Response, OpCode::Query);
-        response.metadata.authoritative = true;
-        for _ in 0..16 {
-            let txt = TXT::new(vec!["x".repeat(250)]);
-            response.add_answer(Record::from_rdata(name.clone(), 300, RData::TXT(txt)));
+    #[tokio::test]
+    async fn test_hot_answer_is_prefetched_before_expiry() {
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let cache = Arc::new(DnsCache::new(Duration::from_millis(300)).with_prefetch(2));
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            cache.clone(),
+        );
+        let name = Name::from_ascii("what.is.rust.").unwrap();
+        let key = handler.cache_key(&name).unwrap().page(1);
+        let query = Query::query(name, RecordType::TXT);
+
+        // A miss, then two hits make the entry hot
+        for _ in 0..3 {
+            handler.answer(&query).await;
         }
+        assert_eq!(cache.hits(&key).await, 2);
 
-        let full = response.to_vec().unwrap();
+        // A hit in the last tenth of the TTL refreshes it in the background
+        tokio::time::sleep(Duration::from_millis(275)).await;
+        let outcome = handler.answer(&query).await;
+        assert_eq!(outcome.response_code, ResponseCode::NoError);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        mock.assert_async().await;
         assert!(
-            full.len() > MAX_UDP_RESPONSE,
-            "fixture should exceed the cap, got {} bytes",
-            full.len()
+            cache.get(&key).await.is_some(),
+            "refreshed entry expired with the old one"
         );
+    }
 
-        let truncated = response.truncate();
-        let bytes = truncated.to_vec().unwrap();
+    #[tokio::test]
+    async fn test_recurring_llm_failures_are_cached() {
+        for (status, expected, calls, later_page) in [
+            (400, ResponseCode::ServFail, 1, ResponseCode::ServFail),
+            (403, ResponseCode::Refused, 1, ResponseCode::Refused),
+            // A rate limit clears up on its own, so every first page asks
+            // again; a later page has no answer to come from
+            (429, ResponseCode::ServFail, 2, ResponseCode::NXDomain),
+        ] {
+            let mut llm = mockito::Server::new_async().await;
+            let mock = llm
+                .mock("POST", mockito::Matcher::Any)
+                .with_status(status)
+                .with_body(r#"{"error": "no"}"#)
+                .expect(calls)
+                .create_async()
+                .await;
+
+            let handler = LlmDnsHandler::new(
+                Arc::new(test_llm_client(llm.url())),
+                Arc::new(Chunker::new()),
+                Arc::new(DnsHandler::new()),
+                Arc::new(DnsCache::new(Duration::from_secs(300)).with_negative_ttls(
+                    NegativeTtls {
+                        bad_request: Duration::from_secs(60),
+                        content_filtered: Duration::from_secs(60),
+                        model_not_found: Duration::from_secs(60),
+                    },
+                )),
+            );
 
-        assert!(truncated.metadata.truncation, "TC bit must be set");
-        assert!(truncated.answers.is_empty(), "answers must be dropped");
-        assert!(
-            bytes.len() <= DEFAULT_MAX_UDP_RESPONSE,
-            "truncated response should fit the smallest budget, got {} bytes",
-            bytes.len()
-        );
-        assert_eq!(truncated.metadata.id, 42, "query id must be preserved");
+            for (name, expected) in [
+                ("what.is.rust.", expected),
+                ("WHAT.is.rust.", expected),
+                ("p2.what.is.rust.", later_page),
+            ] {
+                let query = Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT);
+                assert_eq!(
+                    handler.answer(&query).await.response_code,
+                    expected,
+                    "{status} {name}"
+                );
+            }
+            mock.assert_async().await;
+        }
     }
 
-    fn test_handler_with_limit(limit: usize) -> LlmDnsHandler {
-        let llm_client = Arc::new(
-            LlmClient::new(
-                "key".to_string(),
-                vec!["model".to_string()],
-                "Test system prompt".to_string(),
-                None,
-                None,
-                None,
-                None,
-                None,
-                None,
+    #[tokio::test]
+    async fn test_long_answer_is_served_in_pages() {
+        let answer = "0123456789".repeat(7);
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
             )
-            .unwrap(),
-        );
-        LlmDnsHandler::new(
-            llm_client,
-            Arc::new(Chunker::new()),
+            .expect(1)
+            .create_async()
+            .await;
+
+        let llm_client = test_llm_client(llm.url());
+        let handler = LlmDnsHandler::new(
+            Arc::new(llm_client),
+            Arc::new(Chunker::with_sizes(10, 25)),
             Arc::new(DnsHandler::new()),
             Arc::new(DnsCache::new(Duration::from_secs(300))),
         )
-        .with_max_concurrent_llm_requests(limit)
-    }
+        .with_zone(Zone::new("llm.example.com").unwrap());
 
-    #[tokio::test]
-    async fn test_query_is_shed_when_llm_concurrency_exhausted() {
-        // With every permit held, a further query must be refused *before* the
-        // outbound call - so this asserts the shed without touching the network.
-        let handler = test_handler_with_limit(1);
-        let sem = handler.llm_permits.clone().expect("limit should be active");
-        let _held = sem.try_acquire_owned().expect("first permit available");
+        let txt = |record: &Record| match &record.data {
+            RData::TXT(txt) => String::from_utf8(txt.txt_data[0].to_vec()).unwrap(),
+            other => panic!("expected TXT, got {:?}", other),
+        };
+        let ask = |name: &str| {
+            let query = Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT);
+            let handler = &handler;
+            async move { handler.answer(&query).await }
+        };
 
-        let name = Name::from_utf8("what.is.rust.").unwrap();
-        let err = handler
-            .process_query(&name)
-            .await
-            .expect_err("query should be shed while permits are exhausted");
+        let mut pages = Vec::new();
+        for name in [
+            "count.llm.example.com.",
+            "p2.count.llm.example.com.",
+            "p3.count.llm.example.com.",
+        ] {
+            let outcome = ask(name).await;
+            assert_eq!(outcome.response_code, ResponseCode::NoError);
+            assert_eq!(outcome.answers[0].name, Name::from_ascii(name).unwrap());
+            pages.push(outcome.answers.iter().map(txt).collect::<Vec<_>>());
+        }
 
-        assert!(
-            err.to_string().contains("concurrency limit"),
-            "unexpected error: {err}"
-        );
+        // Each page ends with the page count; the rest is the whole answer
+        assert_eq!(pages[0].last().unwrap(), "page 1/3");
+        assert_eq!(pages[2].last().unwrap(), "page 3/3");
+        let text: String = pages
+            .iter()
+            .flat_map(|page| &page[..page.len() - 1])
+            .map(String::as_str)
+            .collect();
+        assert_eq!(text, answer);
+
+        // Later pages came from the stored answer, not another LLM call
+        mock.assert_async().await;
+
+        let outcome = ask("p4.count.llm.example.com.").await;
+        assert_eq!(outcome.response_code, ResponseCode::NXDomain);
     }
 
     #[tokio::test]
-    async fn test_permit_is_released_after_query() {
-        // A shed query must not leak its permit, or the server would wedge shut
-        // after the first burst.
-        let handler = test_handler_with_limit(1);
-        let sem = handler.llm_permits.clone().expect("limit should be active");
+    async fn test_evicted_page_is_not_asked_again() {
+        let answer = "0123456789".repeat(4);
+        let mut llm = mockito::Server::new_async().await;
+        let mock = llm
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
 
-        {
-            let _held = sem.clone().try_acquire_owned().unwrap();
-            let name = Name::from_utf8("first.query.").unwrap();
-            assert!(handler.process_query(&name).await.is_err());
-        }
+        let cache = Arc::new(DnsCache::new(Duration::from_secs(300)));
+        let handler = LlmDnsHandler::new(
+            Arc::new(test_llm_client(llm.url())),
+            Arc::new(Chunker::with_sizes(10, 25)),
+            Arc::new(DnsHandler::new()),
+            cache.clone(),
+        );
+
+        let name = Name::from_ascii("count.").unwrap();
+        handler.process_query(&name).await.unwrap();
+        let key = handler.cache_key(&name).unwrap();
+        assert!(cache.remove(&key.page(2)).await);
 
+        // Page 2 of a new answer would not follow on from the page 1 served
+        let err = handler
+            .process_query(&Name::from_ascii("p2.count.").unwrap())
+            .await
+            .unwrap_err();
         assert_eq!(
-            sem.available_permits(),
-            1,
-            "permit was not returned after the shed query"
+            err.downcast_ref::<PromptError>(),
+            Some(&PromptError::PageExpired(2))
         );
+        mock.assert_async().await;
     }
 
-    #[test]
-    fn test_zero_limit_disables_llm_concurrency_cap() {
-        assert!(test_handler_with_limit(0).llm_permits.is_none());
-        assert!(test_handler_with_limit(4).llm_permits.is_some());
+    #[tokio::test]
+    async fn test_txt_framing_survives_shuffled_records() {
+        let answer =
+            "Rust is a language empowering everyone to build reliable software. ".repeat(8);
+
+        for framing in [TxtFraming::Sequenced, TxtFraming::Packed] {
+            let mut llm = mockito::Server::new_async().await;
+            let _mock = llm
+                .mock("POST", mockito::Matcher::Any)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(
+                    serde_json::json!({"choices": [{"message": {"content": answer}}]}).to_string(),
+                )
+                .create_async()
+                .await;
+
+            let llm_client = test_llm_client(llm.url());
+            let chunker = Chunker::new().with_framing(framing);
+            let handler = LlmDnsHandler::new(
+                Arc::new(llm_client),
+                Arc::new(chunker.clone()),
+                Arc::new(DnsHandler::new()),
+                Arc::new(DnsCache::new(Duration::from_secs(300))),
+            );
+
+            let name = Name::from_ascii("what.is.rust.").unwrap();
+            let mut records = handler.process_query(&name).await.unwrap();
+            match framing {
+                TxtFraming::Packed => assert_eq!(records.len(), 1),
+                _ => assert_eq!(records.len(), 3),
+            }
+
+            // A resolver is free to return the RRset in any order
+            records.reverse();
+            let strings: Vec<String> = records
+                .iter()
+                .flat_map(|record| match &record.data {
+                    RData::TXT(txt) => txt.txt_data.to_vec(),
+                    other => panic!("expected TXT, got {:?}", other),
+                })
+                .map(|bytes| String::from_utf8(bytes.to_vec()).unwrap())
+                .collect();
+
+            assert_eq!(
+                chunker.reassemble(&strings).unwrap(),
+                answer,
+                "{:?}",
+                framing
+            );
+        }
     }
 }
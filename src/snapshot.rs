@@ -0,0 +1,389 @@
+//! Snapshots of the response cache, for a warm start after a restart
+//!
+//! The cache lives in memory, so a deploy or container restart would empty it
+//! and every popular question would be paid for again. A [`CacheSnapshot`]
+//! writes the retained answers to a file, with their expiry on the wall clock
+//! and their hit counts, and loads them back on startup. Answers that expired
+//! while the server was down are dropped (or kept stale, within the stale
+//! window).
+//!
+//! The file is JSON Lines: a header line, then one answer per line with its
+//! records in DNS wire format. It is written to a temporary file and renamed
+//! into place, so a crash mid-write leaves the previous snapshot intact.
+//! Snapshots are capped at `max_bytes`; the most hit answers are written
+//! first, so the cap drops the coldest.
+//!
+//! [`export`] and [`import`] produce and read the same format without a file,
+//! for moving answers between servers.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use llm_over_dns::snapshot::CacheSnapshot;
+//! use llm_over_dns::DnsCache;
+//! use std::time::Duration;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let cache = DnsCache::new(Duration::from_secs(300));
+//! let snapshot = CacheSnapshot::new("/var/lib/llm-over-dns/cache.jsonl", 16 << 20);
+//!
+//! let loaded = snapshot.load(&cache).await?;
+//! // ... serve queries ...
+//! let saved = snapshot.save(&cache).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{bail, Context, Result};
+use data_encoding::BASE64;
+use hickory_server::proto::rr::Record;
+use hickory_server::proto::serialize::binary::{BinDecodable, BinEncodable};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::cache::ExportedEntry;
+use crate::DnsCache;
+
+/// Version written in the header line; files with another version are rejected
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// First line of a snapshot file
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+/// One cached answer, as stored on disk
+#[derive(Debug, Serialize, Deserialize)]
+struct Line {
+    key: String,
+    /// Expiry in milliseconds since the Unix epoch
+    expires_at: u64,
+    hits: u64,
+    /// Base64 of each record in wire format
+    records: Vec<String>,
+}
+
+impl Line {
+    fn encode(entry: &ExportedEntry) -> Result<Self> {
+        let records = entry
+            .records
+            .iter()
+            .map(|record| Ok(BASE64.encode(&record.to_bytes()?)))
+            .collect::<Result<_>>()?;
+        let expires_at = entry
+            .expires_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        Ok(Self {
+            key: entry.key.clone(),
+            expires_at,
+            hits: entry.hits,
+            records,
+        })
+    }
+
+    fn decode(self) -> Result<ExportedEntry> {
+        let records = self
+            .records
+            .iter()
+            .map(|encoded| {
+                let bytes = BASE64.decode(encoded.as_bytes())?;
+                Ok(Record::from_bytes(&bytes)?)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ExportedEntry {
+            key: self.key,
+            records,
+            expires_at: UNIX_EPOCH + Duration::from_millis(self.expires_at),
+            hits: self.hits,
+        })
+    }
+}
+
+/// A snapshot file for a [`DnsCache`]
+#[derive(Debug, Clone)]
+pub struct CacheSnapshot {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+impl CacheSnapshot {
+    /// Creates a snapshot stored at `path` and capped at `max_bytes`.
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes,
+        }
+    }
+
+    /// Where the snapshot is stored
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the cache's retained answers, most hit first, and returns how
+    /// many fit under the size cap.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the file cannot be written.
+    pub async fn save(&self, cache: &DnsCache) -> Result<usize> {
+        let (contents, saved) = export(cache, self.max_bytes).await?;
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".tmp");
+        tokio::fs::write(&partial, &contents)
+            .await
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        tokio::fs::rename(&partial, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+
+        Ok(saved)
+    }
+
+    /// Restores the answers in the snapshot into `cache` and returns how many
+    /// were still worth keeping.
+    ///
+    /// A missing file is an empty snapshot. Lines that do not parse are
+    /// skipped, so one bad line does not lose the rest.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the file cannot be read or is not a snapshot of this
+    /// version.
+    pub async fn load(&self, cache: &DnsCache) -> Result<usize> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()))
+            }
+        };
+
+        let restored = import(cache, &contents)
+            .await
+            .with_context(|| format!("Failed to load {}", self.path.display()))?;
+        debug!("Restored {} answers from {}", restored, self.path.display());
+
+        Ok(restored)
+    }
+}
+
+/// Writes the cache's retained answers in the snapshot format, most hit
+/// first, and returns them with how many fit under `max_bytes`.
+///
+/// # Errors
+///
+/// Returns error if a record cannot be encoded.
+pub async fn export(cache: &DnsCache, max_bytes: u64) -> Result<(Vec<u8>, usize)> {
+    let mut entries = cache.export().await;
+    entries.sort_by(|a, b| {
+        b.hits
+            .cmp(&a.hits)
+            .then_with(|| b.expires_at.cmp(&a.expires_at))
+    });
+
+    let mut contents = serde_json::to_vec(&Header {
+        version: SNAPSHOT_VERSION,
+    })?;
+    contents.push(b'\n');
+
+    let mut saved = 0;
+    for entry in &entries {
+        let mut line = serde_json::to_vec(&Line::encode(entry)?)?;
+        line.push(b'\n');
+        if (contents.len() + line.len()) as u64 > max_bytes {
+            debug!(
+                "Cache snapshot full at {} of {} answers",
+                saved,
+                entries.len()
+            );
+            break;
+        }
+        contents.extend_from_slice(&line);
+        saved += 1;
+    }
+
+    Ok((contents, saved))
+}
+
+/// Restores the answers in snapshot-format `contents` into `cache` and
+/// returns how many were still worth keeping.
+///
+/// Lines that do not parse are skipped, so one bad line does not lose the
+/// rest.
+///
+/// # Errors
+///
+/// Returns error if `contents` is not a snapshot of this version.
+pub async fn import(cache: &DnsCache, contents: &str) -> Result<usize> {
+    let mut lines = contents.lines();
+    let header: Header = lines
+        .next()
+        .map(serde_json::from_str)
+        .transpose()
+        .ok()
+        .flatten()
+        .context("Not a cache snapshot")?;
+    if header.version != SNAPSHOT_VERSION {
+        bail!(
+            "Version {} cache snapshot, expected {}",
+            header.version,
+            SNAPSHOT_VERSION
+        );
+    }
+
+    let mut restored = 0;
+    for line in lines {
+        let entry = match serde_json::from_str::<Line>(line)
+            .map_err(anyhow::Error::from)
+            .and_then(Line::decode)
+        {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skipping bad cache snapshot line: {:#}", e);
+                continue;
+            }
+        };
+        if cache.restore(entry).await {
+            restored += 1;
+        }
+    }
+
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_server::proto::rr::rdata::TXT;
+    use hickory_server::proto::rr::{Name, RData};
+
+    fn record(text: &str) -> Record {
+        Record::from_rdata(
+            Name::from_ascii("what.is.rust.").unwrap(),
+            300,
+            RData::TXT(TXT::new(vec![text.to_string()])),
+        )
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "llm-over-dns-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let path = temp_path("round-trip");
+        let snapshot = CacheSnapshot::new(&path, 1 << 20);
+
+        let cache = DnsCache::new(Duration::from_secs(300));
+        cache.insert("what.is.rust.", vec![record("fast")]).await;
+        cache.insert("p2 what.is.rust.", vec![record("safe")]).await;
+        cache.get("what.is.rust.").await;
+        assert_eq!(snapshot.save(&cache).await.unwrap(), 2);
+
+        let restarted = DnsCache::new(Duration::from_secs(300));
+        assert_eq!(snapshot.load(&restarted).await.unwrap(), 2);
+        assert_eq!(
+            restarted.get("what.is.rust.").await,
+            Some(vec![record("fast")])
+        );
+        assert_eq!(restarted.hits("what.is.rust.").await, 2);
+        assert!(restarted.get("p2 what.is.rust.").await.is_some());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_snapshot_is_empty() {
+        let snapshot = CacheSnapshot::new(temp_path("missing"), 1 << 20);
+        let cache = DnsCache::new(Duration::from_secs(300));
+        assert_eq!(snapshot.load(&cache).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_answers_are_not_restored() {
+        let path = temp_path("expired");
+        let snapshot = CacheSnapshot::new(&path, 1 << 20);
+
+        let cache = DnsCache::new(Duration::from_millis(20));
+        cache.insert("what.is.rust.", vec![record("fast")]).await;
+        snapshot.save(&cache).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        let restarted = DnsCache::new(Duration::from_secs(300));
+        assert_eq!(snapshot.load(&restarted).await.unwrap(), 0);
+
+        // Within the stale window it comes back, but only to be served stale
+        let stale =
+            DnsCache::new(Duration::from_secs(300)).with_serve_stale(Duration::from_secs(60));
+        assert_eq!(snapshot.load(&stale).await.unwrap(), 1);
+        assert!(stale.get("what.is.rust.").await.is_none());
+        assert!(stale.get_stale("what.is.rust.").await.is_some());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_keeps_hottest_answers_under_cap() {
+        let path = temp_path("capped");
+
+        let cache = DnsCache::new(Duration::from_secs(300));
+        for i in 0..20 {
+            cache
+                .insert(&format!("q{i}."), vec![record(&"x".repeat(100))])
+                .await;
+        }
+        for _ in 0..3 {
+            cache.get("q7.").await;
+        }
+
+        let snapshot = CacheSnapshot::new(&path, 1000);
+        let saved = snapshot.save(&cache).await.unwrap();
+        assert!(saved > 0 && saved < 20, "saved {saved}");
+        assert!(std::fs::metadata(&path).unwrap().len() <= 1000);
+
+        let restarted = DnsCache::new(Duration::from_secs(300));
+        snapshot.load(&restarted).await.unwrap();
+        assert!(restarted.get("q7.").await.is_some());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bad_lines_are_skipped_and_foreign_files_rejected() {
+        let path = temp_path("bad-lines");
+        let snapshot = CacheSnapshot::new(&path, 1 << 20);
+
+        let cache = DnsCache::new(Duration::from_secs(300));
+        cache.insert("what.is.rust.", vec![record("fast")]).await;
+        snapshot.save(&cache).await.unwrap();
+
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str("{\"key\": \"broken\"}\n");
+        std::fs::write(&path, contents).unwrap();
+        let restarted = DnsCache::new(Duration::from_secs(300));
+        assert_eq!(snapshot.load(&restarted).await.unwrap(), 1);
+
+        std::fs::write(&path, "not a snapshot\n").unwrap();
+        assert!(snapshot.load(&restarted).await.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
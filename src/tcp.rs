@@ -0,0 +1,413 @@
+//! DNS over TCP (RFC 7766)
+//!
+//! UDP answers that exceed the client's payload budget are truncated with the
+//! TC bit set, which tells the client to retry over TCP. This module provides
+//! that TCP path.
+//!
+//! Every message on a TCP connection is prefixed with its length as a two-byte
+//! big-endian integer (RFC 1035 §4.2.2). A client may pipeline several queries
+//! on one connection without waiting for answers, and responses are written as
+//! they become ready, so a slow LLM call does not hold up a cached answer
+//! queued behind it (RFC 7766 §6.2.1.1).
+//!
+//! The framing is independent of the byte stream underneath, so the same
+//! listener also serves DNS-over-TLS (RFC 7858) when given a TLS acceptor: the
+//! handshake runs first and the framed exchange continues inside the session.
+
+use anyhow::Result;
+use hickory_server::proto::op::Message;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+
+use crate::server::build_response;
+use crate::{IpRateLimiter, LlmDnsHandler};
+
+/// Queries a single connection may have outstanding at once.
+///
+/// Pipelining lets one connection start many LLM calls, so cap it; once the
+/// limit is reached we stop reading until an answer has been written, which
+/// pushes back on the client through TCP flow control.
+const MAX_PIPELINED_QUERIES: usize = 16;
+
+/// Accepts DNS-over-TCP (or DNS-over-TLS) connections until shutdown is signalled
+///
+/// # Arguments
+///
+/// * `listener` - Bound TCP listener
+/// * `tls` - TLS acceptor for DNS-over-TLS, `None` for plain TCP
+/// * `handler` - LLM DNS handler shared with the UDP listener
+/// * `rate_limiter` - Per-client rate limiter shared with the UDP listener
+/// * `idle_timeout` - How long a connection may wait for its next query
+/// * `max_connections` - Ceiling on open connections, 0 for no limit
+/// * `shutdown_rx` - Server shutdown signal
+pub(crate) async fn serve(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    handler: Arc<LlmDnsHandler>,
+    rate_limiter: Arc<IpRateLimiter>,
+    idle_timeout: Duration,
+    max_connections: usize,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    let connection_permits =
+        (max_connections > 0).then(|| Arc::new(Semaphore::new(max_connections)));
+
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.recv() => {
+                info!("Shutdown signal received, stopping TCP listener");
+                break;
+            }
+
+            result = listener.accept() => {
+                let (stream, remote_addr) = match result {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("TCP accept error: {}", e);
+                        // Small delay to prevent tight loop on persistent errors
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+
+                // Refuse rather than queue: a connection we cannot serve still
+                // holds a file descriptor, which is what the limit protects.
+                let permit = match connection_permits.as_ref() {
+                    Some(sem) => match sem.clone().try_acquire_owned() {
+                        Ok(permit) => Some(permit),
+                        Err(_) => {
+                            warn!("TCP connection limit reached, dropping {}", remote_addr);
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                debug!("Accepted TCP connection from {}", remote_addr);
+                let tls = tls.clone();
+                let handler = handler.clone();
+                let rate_limiter = rate_limiter.clone();
+
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let result = match tls {
+                        // The handshake counts against the idle timeout, so a
+                        // client that connects and stalls cannot pin the slot.
+                        Some(acceptor) => match timeout(idle_timeout, acceptor.accept(stream)).await {
+                            Ok(Ok(tls_stream)) => {
+                                serve_connection(tls_stream, remote_addr, handler, rate_limiter, idle_timeout).await
+                            }
+                            Ok(Err(e)) => Err(anyhow::anyhow!("TLS handshake failed: {}", e)),
+                            Err(_) => Err(anyhow::anyhow!("TLS handshake timed out")),
+                        },
+                        None => {
+                            serve_connection(stream, remote_addr, handler, rate_limiter, idle_timeout).await
+                        }
+                    };
+                    if let Err(e) = result {
+                        debug!("TCP connection from {} ended with error: {}", remote_addr, e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Serves length-prefixed DNS messages on one connection
+///
+/// Reads queries until the client closes the connection, sends something
+/// that does not parse, or stays silent for `idle_timeout`. Answers still
+/// being computed when reading stops are written before the connection is
+/// closed.
+///
+/// # Errors
+///
+/// Returns error if reading from or writing to the stream fails.
+pub(crate) async fn serve_connection<S>(
+    stream: S,
+    remote_addr: SocketAddr,
+    handler: Arc<LlmDnsHandler>,
+    rate_limiter: Arc<IpRateLimiter>,
+    idle_timeout: Duration,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let (response_tx, mut response_rx) = mpsc::channel::<Vec<u8>>(MAX_PIPELINED_QUERIES);
+
+    // A single writer owns the write half, so responses finishing at the same
+    // time cannot interleave their bytes on the wire.
+    let writer_task = tokio::spawn(async move {
+        while let Some(frame) = response_rx.recv().await {
+            writer.write_all(&frame).await?;
+            writer.flush().await?;
+        }
+        writer.shutdown().await
+    });
+
+    let in_flight = Arc::new(Semaphore::new(MAX_PIPELINED_QUERIES));
+
+    loop {
+        // The idle timer only covers the wait for the next query. A query that
+        // is still being answered keeps its response, even once we stop reading.
+        let length = match timeout(idle_timeout, reader.read_u16()).await {
+            Ok(Ok(length)) => length as usize,
+            Ok(Err(_)) => {
+                debug!("TCP client {} closed the connection", remote_addr);
+                break;
+            }
+            Err(_) => {
+                debug!("TCP connection from {} idle, closing", remote_addr);
+                break;
+            }
+        };
+
+        let mut buffer = vec![0u8; length];
+        match timeout(idle_timeout, reader.read_exact(&mut buffer)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                debug!("TCP client {} sent a short frame: {}", remote_addr, e);
+                break;
+            }
+            Err(_) => {
+                debug!("TCP client {} stalled mid-frame, closing", remote_addr);
+                break;
+            }
+        }
+
+        let request_msg = match Message::from_vec(&buffer) {
+            Ok(msg) => msg,
+            Err(e) => {
+                // Framing cannot be trusted after a bad message, so give up on
+                // the connection instead of guessing where the next one starts.
+                warn!("Failed to parse DNS message from {}: {}", remote_addr, e);
+                break;
+            }
+        };
+
+        let permit = in_flight
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("in-flight semaphore is never closed");
+        let handler = handler.clone();
+        let rate_limiter = rate_limiter.clone();
+        let response_tx = response_tx.clone();
+
+        tokio::spawn(async move {
+            let _permit = permit;
+            let response = build_response(&request_msg, remote_addr, &handler, &rate_limiter).await;
+
+            match encode_frame(&response) {
+                Ok(frame) => {
+                    // The writer is gone only if the connection already failed.
+                    let _ = response_tx.send(frame).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to serialize DNS response for {}: {}",
+                        remote_addr, e
+                    );
+                }
+            }
+        });
+    }
+
+    // Dropping our sender lets the writer finish once every outstanding
+    // query has delivered its answer.
+    drop(response_tx);
+    writer_task.await??;
+    Ok(())
+}
+
+/// Serializes a response with its two-byte length prefix
+///
+/// A response that does not fit the 16-bit length field is truncated and
+/// sent with TC set, the same signal a UDP client gets.
+fn encode_frame(response: &Message) -> Result<Vec<u8>> {
+    let mut bytes = response.to_vec()?;
+    if bytes.len() > u16::MAX as usize {
+        bytes = response.truncate().to_vec()?;
+    }
+
+    let mut frame = Vec::with_capacity(bytes.len() + 2);
+    frame.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&bytes);
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::test_llm_client;
+    use crate::{Chunker, DnsCache, DnsHandler};
+    use hickory_server::proto::op::{MessageType, OpCode, Query, ResponseCode};
+    use hickory_server::proto::rr::{Name, RecordType};
+    use tokio::io::{duplex, DuplexStream};
+
+    fn test_handler() -> Arc<LlmDnsHandler> {
+        Arc::new(LlmDnsHandler::new(
+            Arc::new(test_llm_client("http://127.0.0.1:9".to_string())),
+            Arc::new(Chunker::new()),
+            Arc::new(DnsHandler::new()),
+            Arc::new(DnsCache::new(Duration::from_secs(300))),
+        ))
+    }
+
+    fn query_frame(id: u16, record_type: RecordType) -> Vec<u8> {
+        let mut msg = Message::new(id, MessageType::Query, OpCode::Query);
+        msg.add_query(Query::query(
+            Name::from_utf8("what.is.rust.").unwrap(),
+            record_type,
+        ));
+        let bytes = msg.to_vec().unwrap();
+        let mut frame = (bytes.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(&bytes);
+        frame
+    }
+
+    async fn read_frame(client: &mut DuplexStream) -> Message {
+        let length = client.read_u16().await.unwrap() as usize;
+        let mut buffer = vec![0u8; length];
+        client.read_exact(&mut buffer).await.unwrap();
+        Message::from_vec(&buffer).unwrap()
+    }
+
+    fn spawn_connection(
+        idle_timeout: Duration,
+    ) -> (DuplexStream, tokio::task::JoinHandle<Result<()>>) {
+        let (client, server) = duplex(64 * 1024);
+        let task = tokio::spawn(serve_connection(
+            server,
+            "127.0.0.1:5353".parse().unwrap(),
+            test_handler(),
+            Arc::new(IpRateLimiter::new(0.0, 0.0)),
+            idle_timeout,
+        ));
+        (client, task)
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_queries_each_get_a_framed_answer() {
+        // Non-TXT queries are answered without touching the LLM, so this
+        // exercises framing and pipelining without any network.
+        let (mut client, _task) = spawn_connection(Duration::from_secs(5));
+
+        let mut pipelined = query_frame(1, RecordType::A);
+        pipelined.extend(query_frame(2, RecordType::AAAA));
+        client.write_all(&pipelined).await.unwrap();
+
+        let mut ids = vec![read_frame(&mut client).await, read_frame(&mut client).await]
+            .into_iter()
+            .map(|msg| {
+                assert_eq!(msg.metadata.response_code, ResponseCode::NotImp);
+                msg.metadata.id
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_is_closed() {
+        let (mut client, task) = spawn_connection(Duration::from_millis(50));
+
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(2), client.read(&mut buf))
+            .await
+            .expect("idle connection was never closed")
+            .unwrap();
+        assert_eq!(read, 0, "expected EOF after idle timeout");
+        assert!(task.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connection_closes_after_unparseable_frame() {
+        let (mut client, task) = spawn_connection(Duration::from_secs(5));
+
+        client.write_all(&[0, 3, 0xff, 0xff, 0xff]).await.unwrap();
+
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(2), client.read(&mut buf))
+            .await
+            .expect("connection stayed open after garbage")
+            .unwrap();
+        assert_eq!(read, 0);
+        assert!(task.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dns_over_tls_round_trip() {
+        use crate::tls::tests::TestCert;
+        use crate::tls::{ReloadingCertResolver, DOT_ALPN};
+        use tokio_rustls::rustls::crypto::aws_lc_rs;
+        use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+        use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+        use tokio_rustls::TlsConnector;
+
+        let cert = TestCert::generate();
+        let resolver =
+            Arc::new(ReloadingCertResolver::new(&cert.cert_path, &cert.key_path).unwrap());
+        let acceptor = resolver.acceptor(vec![DOT_ALPN.to_vec()]).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(serve(
+            listener,
+            Some(acceptor),
+            test_handler(),
+            Arc::new(IpRateLimiter::new(0.0, 0.0)),
+            Duration::from_secs(5),
+            4,
+            shutdown_rx,
+        ));
+
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from(cert.cert_der.clone()))
+            .unwrap();
+        let mut client_config =
+            ClientConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        client_config.alpn_protocols = vec![DOT_ALPN.to_vec()];
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut tls = TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .expect("TLS handshake with self-signed certificate");
+        assert_eq!(tls.get_ref().1.alpn_protocol(), Some(DOT_ALPN));
+
+        tls.write_all(&query_frame(9, RecordType::A)).await.unwrap();
+        let length = tls.read_u16().await.unwrap() as usize;
+        let mut buffer = vec![0u8; length];
+        tls.read_exact(&mut buffer).await.unwrap();
+        let response = Message::from_vec(&buffer).unwrap();
+
+        assert_eq!(response.metadata.id, 9);
+        assert_eq!(response.metadata.response_code, ResponseCode::NotImp);
+        shutdown_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_encode_frame_prefixes_length() {
+        let msg = Message::new(7, MessageType::Response, OpCode::Query);
+        let frame = encode_frame(&msg).unwrap();
+        let body = msg.to_vec().unwrap();
+
+        assert_eq!(&frame[..2], &(body.len() as u16).to_be_bytes());
+        assert_eq!(&frame[2..], &body[..]);
+    }
+}
@@ -0,0 +1,372 @@
+//! Asynchronous tickets for prompts slower than a resolver's patience
+//!
+//! Public recursive resolvers give up on an upstream after 2-5 seconds, while
+//! an LLM call takes 5-15, so a prompt asked through 8.8.8.8 usually times
+//! out before the answer exists. Tickets split the exchange in two:
+//!
+//! ```text
+//! ask.<prompt>.<zone>           TXT   "<ticket>" at once; the call starts
+//! <ticket>.result.<zone>        TXT   "pending", then the answer
+//! p<n>.<ticket>.result.<zone>   TXT   page n of the answer
+//! ```
+//!
+//! Every page of the answer is kept on the ticket when the call finishes, and
+//! the result queries are served from there, however long the response cache
+//! keeps it. A ticket whose call failed answers with the response code the
+//! prompt itself would have got.
+//!
+//! Tickets are bounded like upload sessions: at most `max_tickets` exist at
+//! once, and each expires `ttl` after it was issued or completed. Ticket ids
+//! are random, so one client cannot guess another's.
+//!
+//! # Examples
+//!
+//! ```
+//! use hickory_proto::op::ResponseCode;
+//! use hickory_proto::rr::rdata::TXT;
+//! use hickory_proto::rr::{Name, RData, Record};
+//! use llm_over_dns::ticket::{TicketState, TicketStore};
+//! use std::time::Duration;
+//!
+//! let tickets = TicketStore::new(16, Duration::from_secs(60));
+//! let prompt = Name::from_ascii("what.is.rust.").unwrap();
+//!
+//! let id = tickets.issue(prompt.clone()).unwrap();
+//! assert_eq!(tickets.get(&id).unwrap().state, TicketState::Pending);
+//!
+//! let txt = RData::TXT(TXT::new(vec!["A language".to_string()]));
+//! tickets.complete(&id, vec![vec![Record::from_rdata(prompt, 0, txt)]]);
+//! assert!(matches!(tickets.get(&id).unwrap().state, TicketState::Ready(_)));
+//!
+//! let failed = tickets.issue(Name::from_ascii("why.").unwrap()).unwrap();
+//! tickets.fail(&failed, ResponseCode::ServFail);
+//! assert_eq!(
+//!     tickets.get(&failed).unwrap().state,
+//!     TicketState::Failed(ResponseCode::ServFail)
+//! );
+//! ```
+
+use data_encoding::BASE32_NOPAD;
+use hickory_server::proto::op::ResponseCode;
+use hickory_server::proto::rr::{Name, Record};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::dns_handler::prompt_depth;
+
+/// First label of a query that asks for a ticket
+pub const ASK_LABEL: &[u8] = b"ask";
+
+/// Label that follows a ticket id to collect its answer
+pub const RESULT_LABEL: &[u8] = b"result";
+
+/// Length of a ticket id: 64 random bits in unpadded base32
+pub const TICKET_ID_LEN: usize = 13;
+
+/// Why a ticket query was rejected
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TicketError {
+    /// Every slot holds a live ticket
+    #[error("Too many outstanding tickets")]
+    TooManyTickets,
+    /// No live ticket has this id
+    #[error("Unknown or expired ticket '{0}'")]
+    UnknownTicket(String),
+    /// The ticket's prompt was answered with an error
+    #[error("Ticket failed with {0}")]
+    Failed(ResponseCode),
+}
+
+/// A query name addressed to the ticket protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TicketQuery {
+    /// `ask.<prompt>`: issue a ticket for the prompt
+    Ask {
+        /// The query name without the `ask` label
+        prompt: Name,
+    },
+    /// `<ticket>.result`: collect the answer
+    Result {
+        /// Ticket id, lowercased
+        ticket: String,
+    },
+}
+
+impl TicketQuery {
+    /// Parses the labels below `origin` as a ticket query
+    ///
+    /// Returns `None` for names of any other shape, which are ordinary
+    /// prompts.
+    pub fn parse(name: &Name, origin: Option<&Name>) -> Option<Self> {
+        let depth = prompt_depth(name, origin)?;
+        let labels: Vec<&[u8]> = name.iter().take(depth).collect();
+
+        match labels.as_slice() {
+            [ticket, result] if result.eq_ignore_ascii_case(RESULT_LABEL) => {
+                parse_ticket(ticket).map(|ticket| Self::Result { ticket })
+            }
+            [ask, _, ..] if ask.eq_ignore_ascii_case(ASK_LABEL) => Some(Self::Ask {
+                prompt: name.base_name(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Ticket ids are unpadded base32, compared case-insensitively.
+fn parse_ticket(label: &[u8]) -> Option<String> {
+    let valid = label.len() == TICKET_ID_LEN
+        && label
+            .iter()
+            .all(|b| b.is_ascii_alphabetic() || (b'2'..=b'7').contains(b));
+    valid.then(|| String::from_utf8_lossy(label).to_ascii_lowercase())
+}
+
+/// Progress of a ticket's LLM call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TicketState {
+    /// The call is still running
+    Pending,
+    /// The records of each page of the answer, in order
+    Ready(Arc<Vec<Vec<Record>>>),
+    /// The prompt was answered with this error code
+    Failed(ResponseCode),
+}
+
+/// A ticket as stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticket {
+    /// The query name the ticket asks, without the `ask` label
+    pub prompt: Name,
+    /// Progress of the call
+    pub state: TicketState,
+    expires_at: Instant,
+}
+
+/// Thread-safe store of issued tickets.
+#[derive(Debug)]
+pub struct TicketStore {
+    tickets: Mutex<HashMap<String, Ticket>>,
+    max_tickets: usize,
+    ttl: Duration,
+}
+
+impl TicketStore {
+    /// Creates a store with the given limits.
+    ///
+    /// * `max_tickets` - Tickets alive at once
+    /// * `ttl` - How long a ticket lives after it is issued or completed
+    pub fn new(max_tickets: usize, ttl: Duration) -> Self {
+        Self {
+            tickets: Mutex::new(HashMap::new()),
+            max_tickets,
+            ttl,
+        }
+    }
+
+    /// Issues a pending ticket for `prompt` and returns its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TicketError::TooManyTickets`] if every slot holds a live
+    /// ticket.
+    pub fn issue(&self, prompt: Name) -> Result<String, TicketError> {
+        let now = Instant::now();
+        let mut tickets = self.tickets.lock().unwrap();
+
+        if tickets.len() >= self.max_tickets {
+            tickets.retain(|_, t| now < t.expires_at);
+            if tickets.len() >= self.max_tickets {
+                return Err(TicketError::TooManyTickets);
+            }
+        }
+
+        let id = loop {
+            let id = new_ticket_id();
+            if !tickets.contains_key(&id) {
+                break id;
+            }
+        };
+        tickets.insert(
+            id.clone(),
+            Ticket {
+                prompt,
+                state: TicketState::Pending,
+                expires_at: now + self.ttl,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Keeps every page of the answer to the ticket's prompt, restarting its
+    /// lifetime.
+    ///
+    /// A ticket that expired in the meantime stays gone.
+    pub fn complete(&self, id: &str, pages: Vec<Vec<Record>>) {
+        self.finish(id, TicketState::Ready(Arc::new(pages)));
+    }
+
+    /// Records the error code the ticket's prompt was answered with,
+    /// restarting its lifetime.
+    ///
+    /// A ticket that expired in the meantime stays gone.
+    pub fn fail(&self, id: &str, response_code: ResponseCode) {
+        self.finish(id, TicketState::Failed(response_code));
+    }
+
+    fn finish(&self, id: &str, state: TicketState) {
+        let now = Instant::now();
+        let mut tickets = self.tickets.lock().unwrap();
+        if let Some(ticket) = tickets.get_mut(id).filter(|t| now < t.expires_at) {
+            ticket.state = state;
+            ticket.expires_at = now + self.ttl;
+        }
+    }
+
+    /// Returns a live ticket.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TicketError::UnknownTicket`] if no live ticket has this id.
+    pub fn get(&self, id: &str) -> Result<Ticket, TicketError> {
+        let tickets = self.tickets.lock().unwrap();
+        tickets
+            .get(id)
+            .filter(|t| Instant::now() < t.expires_at)
+            .cloned()
+            .ok_or_else(|| TicketError::UnknownTicket(id.to_string()))
+    }
+
+    /// Returns the number of stored tickets, including expired ones not yet
+    /// cleaned up.
+    pub fn len(&self) -> usize {
+        self.tickets.lock().unwrap().len()
+    }
+
+    /// Returns true if no tickets are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops expired tickets.
+    pub fn cleanup(&self) {
+        let now = Instant::now();
+        self.tickets
+            .lock()
+            .unwrap()
+            .retain(|_, t| now < t.expires_at);
+    }
+}
+
+/// 64 unpredictable bits, from a freshly keyed SipHash of a counter and the
+/// clock, as a lowercase base32 label
+///
+/// Also names upload sessions, whose answers only their id guards.
+pub(crate) fn new_ticket_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(nanos);
+
+    BASE32_NOPAD
+        .encode(&hasher.finish().to_be_bytes())
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(s: &str) -> Name {
+        Name::from_ascii(s).unwrap()
+    }
+
+    fn parse(s: &str) -> Option<TicketQuery> {
+        TicketQuery::parse(&name(s), Some(&name("llm.example.com.")))
+    }
+
+    #[test]
+    fn test_parse_ask_and_result() {
+        assert_eq!(
+            parse("ask.what.is.rust.llm.example.com."),
+            Some(TicketQuery::Ask {
+                prompt: name("what.is.rust.llm.example.com.")
+            })
+        );
+        assert_eq!(
+            parse("ABCDEFGHIJ234.Result.llm.example.com."),
+            Some(TicketQuery::Result {
+                ticket: "abcdefghij234".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_ignores_ordinary_prompts() {
+        assert_eq!(parse("ask.llm.example.com."), None);
+        assert_eq!(parse("final.result.llm.example.com."), None);
+        assert_eq!(parse("abcdefghij234.result.now.llm.example.com."), None);
+        assert_eq!(parse("why.ask.llm.example.com."), None);
+        assert_eq!(parse("ask.rust.example.org."), None);
+    }
+
+    #[test]
+    fn test_ticket_ids_parse_back() {
+        let id = new_ticket_id();
+        assert_eq!(id.len(), TICKET_ID_LEN);
+        assert_eq!(parse_ticket(id.as_bytes()), Some(id.clone()));
+        assert_ne!(new_ticket_id(), id);
+    }
+
+    #[test]
+    fn test_ticket_lifecycle() {
+        let tickets = TicketStore::new(4, Duration::from_secs(60));
+        let id = tickets.issue(name("what.is.rust.")).unwrap();
+
+        let ticket = tickets.get(&id).unwrap();
+        assert_eq!(ticket.state, TicketState::Pending);
+        assert_eq!(ticket.prompt, name("what.is.rust."));
+
+        tickets.fail(&id, ResponseCode::ServFail);
+        assert_eq!(
+            tickets.get(&id).unwrap().state,
+            TicketState::Failed(ResponseCode::ServFail)
+        );
+
+        assert_eq!(
+            tickets.get("abcdefghij234"),
+            Err(TicketError::UnknownTicket("abcdefghij234".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_store_is_bounded() {
+        let tickets = TicketStore::new(2, Duration::from_secs(60));
+        tickets.issue(name("a.")).unwrap();
+        tickets.issue(name("b.")).unwrap();
+
+        assert_eq!(tickets.issue(name("c.")), Err(TicketError::TooManyTickets));
+        assert_eq!(tickets.len(), 2);
+    }
+
+    #[test]
+    fn test_expired_tickets_are_unknown_and_make_room() {
+        let tickets = TicketStore::new(1, Duration::ZERO);
+        let id = tickets.issue(name("a.")).unwrap();
+
+        assert!(tickets.get(&id).is_err());
+        assert!(tickets.issue(name("b.")).is_ok());
+
+        tickets.cleanup();
+        assert!(tickets.is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+//! TLS certificate handling for encrypted listeners
+//!
+//! DNS-over-TLS (RFC 7858) is ordinary DNS-over-TCP framing inside a TLS
+//! session, so the listener itself lives in the `tcp` module, and
+//! DNS-over-HTTPS lives in `http`. This module loads the PEM certificate
+//! chain and private key, and keeps them current: the files are polled and a
+//! changed pair is swapped in without a restart, so a certificate renewal
+//! (e.g. by certbot) takes effect on the next handshake.
+//!
+//! A pair that fails to load is logged and ignored, and the previous
+//! certificate keeps serving. A half-written renewal never takes the listener
+//! down.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_rustls::rustls::crypto::{aws_lc_rs, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+/// How often certificate files are checked for changes.
+pub const CERT_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// ALPN protocol identifier for DNS-over-TLS.
+pub const DOT_ALPN: &[u8] = b"dot";
+
+/// ALPN protocol identifiers for DNS-over-HTTPS, most preferred first.
+pub const DOH_ALPN: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+/// Certificate resolver that serves the current PEM pair and reloads it on change
+///
+/// Every TLS handshake asks the resolver for a certificate, so swapping the
+/// pair here is all a reload needs; established sessions are unaffected.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<LoadedPair>,
+}
+
+#[derive(Debug)]
+struct LoadedPair {
+    /// Raw file contents, compared against disk to detect changes.
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+    certified_key: Arc<CertifiedKey>,
+}
+
+impl ReloadingCertResolver {
+    /// Loads the certificate chain and key from the given PEM files
+    ///
+    /// # Errors
+    ///
+    /// Returns error if either file cannot be read, contains no usable PEM
+    /// section, or the key does not match the certificate.
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Result<Self> {
+        let cert_path = cert_path.into();
+        let key_path = key_path.into();
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let current = load_pair(&cert_path, &key_path, &provider)?;
+
+        Ok(Self {
+            cert_path,
+            key_path,
+            provider,
+            current: RwLock::new(current),
+        })
+    }
+
+    /// Returns the certificate currently being served.
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().certified_key.clone()
+    }
+
+    /// Reloads the pair if either file changed on disk
+    ///
+    /// Returns `Ok(true)` when a new pair was swapped in and `Ok(false)` when
+    /// nothing changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the files changed but the new pair is unusable. The
+    /// previous certificate stays in service.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let cert_pem = read_file(&self.cert_path)?;
+        let key_pem = read_file(&self.key_path)?;
+
+        {
+            let current = self.current.read().unwrap();
+            if current.cert_pem == cert_pem && current.key_pem == key_pem {
+                return Ok(false);
+            }
+        }
+
+        let pair = parse_pair(cert_pem, key_pem, &self.provider)?;
+        *self.current.write().unwrap() = pair;
+        Ok(true)
+    }
+
+    /// Builds a TLS acceptor serving this resolver's certificate
+    ///
+    /// # Arguments
+    ///
+    /// * `alpn_protocols` - Protocols to advertise, most preferred first
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the crypto provider supports no safe protocol version.
+    pub fn acceptor(self: &Arc<Self>, alpn_protocols: Vec<Vec<u8>>) -> Result<TlsAcceptor> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .context("Failed to select TLS protocol versions")?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = alpn_protocols;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// Polls the certificate files until shutdown, reloading on change
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - Time between checks
+    /// * `shutdown_rx` - Server shutdown signal
+    pub async fn watch(
+        self: Arc<Self>,
+        interval: Duration,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) {
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.recv() => break,
+                _ = tokio::time::sleep(interval) => {
+                    match self.reload_if_changed() {
+                        Ok(true) => info!("Reloaded TLS certificate from {}", self.cert_path.display()),
+                        Ok(false) => debug!("TLS certificate unchanged"),
+                        Err(e) => warn!("Keeping previous TLS certificate, reload failed: {:#}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn load_pair(cert_path: &Path, key_path: &Path, provider: &CryptoProvider) -> Result<LoadedPair> {
+    parse_pair(read_file(cert_path)?, read_file(key_path)?, provider)
+}
+
+fn parse_pair(
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+    provider: &CryptoProvider,
+) -> Result<LoadedPair> {
+    let chain = CertificateDer::pem_slice_iter(&cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Invalid PEM certificate: {:?}", e))?;
+    if chain.is_empty() {
+        return Err(anyhow!("No certificate found in PEM file"));
+    }
+
+    let key = PrivateKeyDer::from_pem_slice(&key_pem)
+        .map_err(|e| anyhow!("Invalid PEM private key: {:?}", e))?;
+
+    let certified_key = CertifiedKey::from_der(chain, key, provider)
+        .context("Private key does not match certificate")?;
+
+    Ok(LoadedPair {
+        cert_pem,
+        key_pem,
+        certified_key: Arc::new(certified_key),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A self-signed certificate for `localhost` written to a scratch directory.
+    pub(crate) struct TestCert {
+        pub dir: PathBuf,
+        pub cert_path: PathBuf,
+        pub key_path: PathBuf,
+        pub cert_der: Vec<u8>,
+    }
+
+    impl TestCert {
+        pub fn generate() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "llm-dns-tls-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let cert = Self {
+                cert_path: dir.join("cert.pem"),
+                key_path: dir.join("key.pem"),
+                dir,
+                cert_der: Vec::new(),
+            };
+            cert.regenerate()
+        }
+
+        /// Writes a fresh pair over the same paths.
+        pub fn regenerate(mut self) -> Self {
+            let generated =
+                rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            std::fs::write(&self.cert_path, generated.cert.pem()).unwrap();
+            std::fs::write(&self.key_path, generated.signing_key.serialize_pem()).unwrap();
+            self.cert_der = generated.cert.der().to_vec();
+            self
+        }
+    }
+
+    impl Drop for TestCert {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn test_loads_self_signed_pair() {
+        let cert = TestCert::generate();
+        let resolver = ReloadingCertResolver::new(&cert.cert_path, &cert.key_path).unwrap();
+
+        assert_eq!(resolver.current().cert[0].as_ref(), &cert.cert_der[..]);
+    }
+
+    #[test]
+    fn test_missing_file_is_an_error() {
+        let cert = TestCert::generate();
+        let err = ReloadingCertResolver::new(cert.dir.join("absent.pem"), &cert.key_path)
+            .expect_err("missing certificate should fail");
+        assert!(
+            err.to_string().contains("absent.pem"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn test_garbage_certificate_is_an_error() {
+        let cert = TestCert::generate();
+        std::fs::write(&cert.cert_path, "not a certificate").unwrap();
+
+        assert!(ReloadingCertResolver::new(&cert.cert_path, &cert.key_path).is_err());
+    }
+
+    #[test]
+    fn test_reload_picks_up_renewed_certificate() {
+        let cert = TestCert::generate();
+        let resolver = ReloadingCertResolver::new(&cert.cert_path, &cert.key_path).unwrap();
+        assert!(
+            !resolver.reload_if_changed().unwrap(),
+            "nothing changed yet"
+        );
+
+        let renewed = cert.regenerate();
+        assert!(resolver.reload_if_changed().unwrap());
+        assert_eq!(resolver.current().cert[0].as_ref(), &renewed.cert_der[..]);
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_previous_certificate() {
+        // A renewal caught half-written must not take the listener down.
+        let cert = TestCert::generate();
+        let resolver = ReloadingCertResolver::new(&cert.cert_path, &cert.key_path).unwrap();
+
+        std::fs::write(&cert.key_path, "truncated").unwrap();
+
+        assert!(resolver.reload_if_changed().is_err());
+        assert_eq!(resolver.current().cert[0].as_ref(), &cert.cert_der[..]);
+    }
+}
@@ -0,0 +1,540 @@
+//! Multi-query upload sessions for prompts longer than a DNS name
+//!
+//! A query name holds at most 253 bytes, which rules out a stack trace or a
+//! paragraph. Longer prompts are uploaded in numbered fragments under a
+//! session id chosen by the client, then asked with a final query:
+//!
+//! ```text
+//! <base32 data>.<seq>.<session>.up.<zone>   TXT   stores fragment <seq>
+//! go.<session>.up.<zone>                    TXT   asks the assembled prompt
+//! ```
+//!
+//! Fragment data is the UTF-8 prompt bytes in base32 (case-insensitive, so it
+//! survives resolvers), split across as many labels as fit. Fragments are
+//! numbered from 0, may arrive in any order, and may be resent. The `go`
+//! answer is keyed on the assembled prompt, like any other prompt, and the
+//! session keeps that prompt until it expires, so a resolver retrying `go`
+//! still gets the answer. A fragment sent to an asked session starts a new
+//! upload under its id.
+//!
+//! Sessions are bounded so uploads cannot exhaust memory: at most
+//! `max_sessions` are open at once, each holds at most `max_bytes` of decoded
+//! prompt in at most one fragment per [`MIN_FRAGMENT_BYTES`] of it, and each
+//! expires `ttl` after its last fragment. Every fragment costs an allocation
+//! on top of its data, so the fragment cap keeps a flood of 1-byte fragments
+//! within a small multiple of `max_sessions × max_bytes`.
+//!
+//! # Examples
+//!
+//! ```
+//! use llm_over_dns::upload::UploadSessions;
+//! use std::time::Duration;
+//!
+//! let uploads = UploadSessions::new(16, 4096, Duration::from_secs(60));
+//!
+//! uploads.add_fragment("s1", 1, b"world".to_vec()).unwrap();
+//! uploads.add_fragment("s1", 0, b"hello ".to_vec()).unwrap();
+//!
+//! assert_eq!(uploads.ask("s1").unwrap(), "hello world");
+//! assert_eq!(uploads.ask("s1").unwrap(), "hello world");
+//! assert!(uploads.ask("s2").is_err());
+//! ```
+
+use hickory_server::proto::rr::Name;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::dns_handler::{prompt_depth, PromptEncoding, MAX_LABEL_LEN};
+
+/// Label that marks a query name as part of an upload
+pub const UPLOAD_LABEL: &[u8] = b"up";
+
+/// Label that asks the assembled prompt of a session
+pub const COMMIT_LABEL: &[u8] = b"go";
+
+/// Longest accepted session id
+const MAX_SESSION_ID_LEN: usize = 32;
+
+/// Decoded bytes per fragment a session is allowed, on average. Clients fill
+/// each name, so real fragments carry several times this.
+pub const MIN_FRAGMENT_BYTES: usize = 16;
+
+/// Why an upload query was rejected
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum UploadError {
+    /// Fragment data is not base32
+    #[error("Upload fragment is not valid base32")]
+    InvalidFragment,
+    /// The session would grow past the per-session limit
+    #[error("Upload session exceeds {limit} bytes")]
+    TooLarge {
+        /// Per-session limit in bytes
+        limit: usize,
+    },
+    /// The session would hold more fragments than its size allows
+    #[error("Upload session exceeds {limit} fragments")]
+    TooManyFragments {
+        /// Per-session limit in fragments
+        limit: usize,
+    },
+    /// Every session slot is taken by a live session
+    #[error("Too many open upload sessions")]
+    TooManySessions,
+    /// No live session has this id, because it never existed or expired
+    #[error("Unknown or expired upload session '{0}'")]
+    UnknownSession(String),
+    /// The fragments do not run from 0 without gaps
+    #[error("Upload session is missing fragment {0}")]
+    MissingFragment(u16),
+    /// The assembled bytes are not UTF-8 text
+    #[error("Uploaded prompt is not valid UTF-8 text")]
+    InvalidPrompt,
+}
+
+/// A query name addressed to the upload protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadQuery {
+    /// `<data>.<seq>.<session>.up`: store one fragment
+    Fragment {
+        /// Session id, lowercased
+        session: String,
+        /// Position of the fragment in the prompt
+        seq: u16,
+        /// Decoded fragment bytes
+        data: Vec<u8>,
+    },
+    /// `go.<session>.up`: ask the assembled prompt
+    Commit {
+        /// Session id, lowercased
+        session: String,
+    },
+}
+
+impl UploadQuery {
+    /// Parses the labels below `origin` as an upload query
+    ///
+    /// Returns `None` when the name does not have the shape of an upload
+    /// (an ordinary prompt that happens to end in "up"), and an error when it
+    /// does but the fragment data does not decode.
+    pub fn parse(name: &Name, origin: Option<&Name>) -> Option<Result<Self, UploadError>> {
+        let depth = prompt_depth(name, origin)?;
+        let labels: Vec<&[u8]> = name.iter().take(depth).collect();
+
+        let (last, rest) = labels.split_last()?;
+        if !last.eq_ignore_ascii_case(UPLOAD_LABEL) {
+            return None;
+        }
+        let (session, rest) = rest.split_last()?;
+        let session = parse_session(session)?;
+
+        match rest {
+            [commit] if commit.eq_ignore_ascii_case(COMMIT_LABEL) => {
+                Some(Ok(Self::Commit { session }))
+            }
+            [data @ .., seq] if !data.is_empty() => {
+                let seq = parse_seq(seq)?;
+                let data: Vec<u8> = data.concat();
+                Some(
+                    PromptEncoding::Base32
+                        .decode(&data)
+                        .filter(|data| !data.is_empty())
+                        .map(|data| Self::Fragment { session, seq, data })
+                        .ok_or(UploadError::InvalidFragment),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Builds the query name for this upload query, the inverse of
+    /// [`UploadQuery::parse`]
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the name would exceed 255 bytes.
+    pub fn to_name(&self, origin: Option<&Name>) -> anyhow::Result<Name> {
+        let mut labels: Vec<Vec<u8>> = match self {
+            Self::Fragment { session, seq, data } => {
+                let data = PromptEncoding::Base32.encode(data);
+                let mut labels: Vec<Vec<u8>> = data
+                    .as_bytes()
+                    .chunks(MAX_LABEL_LEN)
+                    .map(<[u8]>::to_vec)
+                    .collect();
+                labels.push(seq.to_string().into_bytes());
+                labels.push(session.as_bytes().to_vec());
+                labels
+            }
+            Self::Commit { session } => vec![COMMIT_LABEL.to_vec(), session.as_bytes().to_vec()],
+        };
+        labels.push(UPLOAD_LABEL.to_vec());
+
+        let mut name = Name::from_labels(labels.iter().map(Vec::as_slice))?;
+        match origin {
+            Some(origin) => Ok(name.append_domain(origin)?),
+            None => {
+                name.set_fqdn(true);
+                Ok(name)
+            }
+        }
+    }
+}
+
+/// Session ids are short alphanumeric labels, compared case-insensitively.
+fn parse_session(label: &[u8]) -> Option<String> {
+    let valid = !label.is_empty()
+        && label.len() <= MAX_SESSION_ID_LEN
+        && label.iter().all(u8::is_ascii_alphanumeric);
+    valid.then(|| String::from_utf8_lossy(label).to_ascii_lowercase())
+}
+
+/// Sequence numbers are plain decimal.
+fn parse_seq(label: &[u8]) -> Option<u16> {
+    if label.is_empty() || !label.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(label).ok()?.parse().ok()
+}
+
+/// Thread-safe store of in-progress uploads.
+#[derive(Debug)]
+pub struct UploadSessions {
+    sessions: Mutex<HashMap<String, Session>>,
+    max_sessions: usize,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+#[derive(Debug)]
+struct Session {
+    fragments: BTreeMap<u16, Vec<u8>>,
+    bytes: usize,
+    expires_at: Instant,
+    /// The assembled prompt, once `go` has asked it
+    asked: Option<String>,
+}
+
+impl UploadSessions {
+    /// Creates a store with the given limits.
+    ///
+    /// * `max_sessions` - Sessions open at once
+    /// * `max_bytes` - Decoded prompt bytes per session
+    /// * `ttl` - How long a session lives after its last fragment
+    pub fn new(max_sessions: usize, max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            max_sessions,
+            max_bytes,
+            ttl,
+        }
+    }
+
+    /// Stores a fragment, opening the session if needed.
+    ///
+    /// Resending a sequence number replaces the earlier fragment. Returns the
+    /// session's total size in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UploadError::TooLarge`] if the session would exceed its size
+    /// limit, [`UploadError::TooManyFragments`] if it would hold more than one
+    /// fragment per [`MIN_FRAGMENT_BYTES`] of that limit, or
+    /// [`UploadError::TooManySessions`] if a new session is needed and every
+    /// slot holds a live one.
+    pub fn add_fragment(
+        &self,
+        session: &str,
+        seq: u16,
+        data: Vec<u8>,
+    ) -> Result<usize, UploadError> {
+        if data.len() > self.max_bytes {
+            return Err(UploadError::TooLarge {
+                limit: self.max_bytes,
+            });
+        }
+
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+
+        if !sessions.contains_key(session) && sessions.len() >= self.max_sessions {
+            sessions.retain(|_, s| now < s.expires_at);
+            if sessions.len() >= self.max_sessions {
+                return Err(UploadError::TooManySessions);
+            }
+        }
+
+        let entry = sessions
+            .entry(session.to_string())
+            .or_insert_with(|| Session {
+                fragments: BTreeMap::new(),
+                bytes: 0,
+                expires_at: now,
+                asked: None,
+            });
+        if now >= entry.expires_at || entry.asked.is_some() {
+            entry.fragments.clear();
+            entry.bytes = 0;
+            entry.asked = None;
+        }
+
+        let replaced = entry.fragments.get(&seq).map_or(0, Vec::len);
+        let bytes = entry.bytes - replaced + data.len();
+        if bytes > self.max_bytes {
+            return Err(UploadError::TooLarge {
+                limit: self.max_bytes,
+            });
+        }
+
+        let max_fragments = self.max_fragments();
+        if replaced == 0 && entry.fragments.len() >= max_fragments {
+            return Err(UploadError::TooManyFragments {
+                limit: max_fragments,
+            });
+        }
+
+        entry.fragments.insert(seq, data);
+        entry.bytes = bytes;
+        entry.expires_at = now + self.ttl;
+        Ok(bytes)
+    }
+
+    /// Fragments one session may hold
+    fn max_fragments(&self) -> usize {
+        (self.max_bytes / MIN_FRAGMENT_BYTES).max(1)
+    }
+
+    /// Returns a session's assembled prompt, marking the session asked.
+    ///
+    /// Asking again returns the same prompt until the session expires or a
+    /// new fragment starts another upload under its id. A session with a gap
+    /// in its fragments is kept, so the client can send the missing fragment
+    /// and ask again.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UploadError::UnknownSession`] if no live session has this id,
+    /// [`UploadError::MissingFragment`] for a gap, or
+    /// [`UploadError::InvalidPrompt`] if the bytes are not UTF-8 text, which
+    /// also drops the session.
+    pub fn ask(&self, session: &str) -> Result<String, UploadError> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let live = sessions
+            .get_mut(session)
+            .filter(|s| now < s.expires_at)
+            .ok_or_else(|| UploadError::UnknownSession(session.to_string()))?;
+        if let Some(prompt) = &live.asked {
+            return Ok(prompt.clone());
+        }
+        for (expected, &seq) in (0..).zip(live.fragments.keys()) {
+            if seq != expected {
+                return Err(UploadError::MissingFragment(expected));
+            }
+        }
+
+        let bytes = std::mem::take(&mut live.fragments)
+            .into_values()
+            .flatten()
+            .collect();
+        let prompt = String::from_utf8(bytes)
+            .ok()
+            .map(|prompt| prompt.trim().to_string())
+            .filter(|prompt| !prompt.is_empty());
+        let Some(prompt) = prompt else {
+            sessions.remove(session);
+            return Err(UploadError::InvalidPrompt);
+        };
+
+        live.asked = Some(prompt.clone());
+        live.expires_at = now + self.ttl;
+        Ok(prompt)
+    }
+
+    /// Number of sessions currently held, including any not yet cleaned up.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// Returns true when no sessions are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes expired sessions.
+    pub fn cleanup(&self) {
+        let now = Instant::now();
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, s| now < s.expires_at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str) -> Option<Result<UploadQuery, UploadError>> {
+        let zone = Name::from_ascii("llm.example.com.").unwrap();
+        UploadQuery::parse(&Name::from_ascii(name).unwrap(), Some(&zone))
+    }
+
+    #[test]
+    fn test_parse_fragment_and_commit() {
+        // "hello world" in base32, split across two labels
+        assert_eq!(
+            parse("NBSWY3DP.EB3W64TMMQ.0.Sess1.up.llm.example.com."),
+            Some(Ok(UploadQuery::Fragment {
+                session: "sess1".to_string(),
+                seq: 0,
+                data: b"hello world".to_vec(),
+            }))
+        );
+        assert_eq!(
+            parse("GO.sess1.UP.llm.example.com."),
+            Some(Ok(UploadQuery::Commit {
+                session: "sess1".to_string()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_to_name_round_trips() {
+        let zone = Name::from_ascii("llm.example.com.").unwrap();
+        let fragment = UploadQuery::Fragment {
+            session: "abc123".to_string(),
+            seq: 7,
+            data: vec![b'x'; 100],
+        };
+        let commit = UploadQuery::Commit {
+            session: "abc123".to_string(),
+        };
+
+        for query in [fragment, commit] {
+            let name = query.to_name(Some(&zone)).unwrap();
+            assert_eq!(UploadQuery::parse(&name, Some(&zone)), Some(Ok(query)));
+        }
+    }
+
+    #[test]
+    fn test_parse_ignores_ordinary_prompts() {
+        assert_eq!(parse("what.is.up.llm.example.com."), None);
+        assert_eq!(parse("up.llm.example.com."), None);
+        assert_eq!(parse("what.is.rust.llm.example.com."), None);
+        assert_eq!(parse("nbswy3dp.0.bad-id.up.llm.example.com."), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_fragment_data() {
+        assert_eq!(
+            parse("not-base32.0.s1.up.llm.example.com."),
+            Some(Err(UploadError::InvalidFragment))
+        );
+    }
+
+    #[test]
+    fn test_fragments_assemble_in_order() {
+        let uploads = UploadSessions::new(4, 1024, Duration::from_secs(60));
+
+        uploads.add_fragment("s", 2, b"!".to_vec()).unwrap();
+        uploads.add_fragment("s", 0, b"hi".to_vec()).unwrap();
+        // A resent fragment replaces the first copy
+        uploads.add_fragment("s", 1, b" wrold".to_vec()).unwrap();
+        assert_eq!(uploads.add_fragment("s", 1, b" world".to_vec()), Ok(9));
+
+        assert_eq!(uploads.ask("s"), Ok("hi world!".to_string()));
+        // A retried `go` gets the same prompt
+        assert_eq!(uploads.ask("s"), Ok("hi world!".to_string()));
+
+        // A new fragment starts a new upload under the same id
+        assert_eq!(uploads.add_fragment("s", 0, b"bye".to_vec()), Ok(3));
+        assert_eq!(uploads.ask("s"), Ok("bye".to_string()));
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(
+            uploads.ask("t"),
+            Err(UploadError::UnknownSession("t".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_missing_fragment_keeps_session() {
+        let uploads = UploadSessions::new(4, 1024, Duration::from_secs(60));
+
+        uploads.add_fragment("s", 0, b"a".to_vec()).unwrap();
+        uploads.add_fragment("s", 2, b"c".to_vec()).unwrap();
+        assert_eq!(uploads.ask("s"), Err(UploadError::MissingFragment(1)));
+
+        uploads.add_fragment("s", 1, b"b".to_vec()).unwrap();
+        assert_eq!(uploads.ask("s"), Ok("abc".to_string()));
+    }
+
+    #[test]
+    fn test_session_size_limit() {
+        let uploads = UploadSessions::new(4, 8, Duration::from_secs(60));
+
+        uploads.add_fragment("s", 0, b"12345".to_vec()).unwrap();
+        assert_eq!(
+            uploads.add_fragment("s", 1, b"6789".to_vec()),
+            Err(UploadError::TooLarge { limit: 8 })
+        );
+        assert_eq!(
+            uploads.add_fragment("t", 0, b"123456789".to_vec()),
+            Err(UploadError::TooLarge { limit: 8 })
+        );
+        // The rejected fragments left nothing behind
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads.ask("s"), Ok("12345".to_string()));
+    }
+
+    #[test]
+    fn test_flood_of_tiny_fragments_is_capped() {
+        let uploads = UploadSessions::new(4, 1024, Duration::from_secs(60));
+        let limit = 1024 / MIN_FRAGMENT_BYTES;
+
+        for seq in 0..limit as u16 {
+            uploads.add_fragment("s", seq, b"x".to_vec()).unwrap();
+        }
+        assert_eq!(
+            uploads.add_fragment("s", limit as u16, b"x".to_vec()),
+            Err(UploadError::TooManyFragments { limit })
+        );
+        // Resending a held fragment is not a new one
+        assert!(uploads.add_fragment("s", 0, b"y".to_vec()).is_ok());
+        assert_eq!(uploads.ask("s").unwrap().len(), limit);
+    }
+
+    #[test]
+    fn test_session_count_limit() {
+        let uploads = UploadSessions::new(2, 64, Duration::from_secs(60));
+
+        uploads.add_fragment("a", 0, b"x".to_vec()).unwrap();
+        uploads.add_fragment("b", 0, b"x".to_vec()).unwrap();
+        assert_eq!(
+            uploads.add_fragment("c", 0, b"x".to_vec()),
+            Err(UploadError::TooManySessions)
+        );
+
+        // Existing sessions can still grow
+        assert!(uploads.add_fragment("a", 1, b"y".to_vec()).is_ok());
+    }
+
+    #[test]
+    fn test_expired_sessions_free_their_slots() {
+        let uploads = UploadSessions::new(1, 64, Duration::from_millis(20));
+
+        uploads.add_fragment("a", 0, b"x".to_vec()).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert_eq!(
+            uploads.ask("a"),
+            Err(UploadError::UnknownSession("a".to_string()))
+        );
+        assert!(uploads.add_fragment("b", 0, b"x".to_vec()).is_ok());
+
+        std::thread::sleep(Duration::from_millis(40));
+        uploads.cleanup();
+        assert!(uploads.is_empty());
+    }
+}
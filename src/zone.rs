@@ -0,0 +1,278 @@
+//! Authoritative zone for delegated deployments
+//!
+//! To be reachable as `llm.example.com` from the public DNS, the parent zone
+//! delegates the name with NS records, and recursive resolvers then expect
+//! this server to behave like any other authoritative nameserver:
+//!
+//! - SOA and NS are answered at the zone apex
+//! - negative answers (NXDOMAIN, or NODATA for a type the name does not have)
+//!   carry the SOA in the authority section so resolvers can cache them
+//!   (RFC 2308)
+//! - names outside the zone are REFUSED rather than answered
+//!
+//! Every name below the apex is a prompt: `what.is.rust.llm.example.com` asks
+//! "what is rust". Labels starting with an underscore (`_dmarc`,
+//! `_acme-challenge`, ...) are service names that mail servers and certificate
+//! authorities probe on their own; they are answered NXDOMAIN instead of
+//! being sent to the LLM.
+//!
+//! # Examples
+//!
+//! ```
+//! use hickory_proto::rr::Name;
+//! use llm_over_dns::zone::{Zone, ZoneMatch};
+//!
+//! let zone = Zone::new("llm.example.com").unwrap();
+//!
+//! let name = Name::from_utf8("what.is.rust.llm.example.com.").unwrap();
+//! assert_eq!(zone.classify(&name), ZoneMatch::Subdomain);
+//!
+//! let name = Name::from_utf8("example.org.").unwrap();
+//! assert_eq!(zone.classify(&name), ZoneMatch::Outside);
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use hickory_server::proto::rr::rdata::{NS, SOA};
+use hickory_server::proto::rr::{Name, RData, Record};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// TTL of the apex SOA and NS records.
+pub const ZONE_TTL: u32 = 3600;
+
+/// How long resolvers may cache a negative answer (the SOA MINIMUM field).
+pub const NEGATIVE_TTL: u32 = 60;
+
+/// SOA timers for secondaries. Nothing transfers this zone, but resolvers
+/// and zone checkers expect sensible values.
+const SOA_REFRESH: i32 = 3600;
+const SOA_RETRY: i32 = 600;
+const SOA_EXPIRE: i32 = 604_800;
+
+/// Where a query name sits relative to the zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneMatch {
+    /// Not in this zone; the server is not authoritative for it
+    Outside,
+    /// The zone origin itself
+    Apex,
+    /// A service name (a label starting with `_`), which never exists
+    Reserved,
+    /// A name below the apex, carrying a prompt
+    Subdomain,
+}
+
+/// The zone this server is authoritative for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    origin: Name,
+    nameservers: Vec<Name>,
+    hostmaster: Name,
+    serial: u32,
+}
+
+impl Zone {
+    /// Creates a zone for the given origin
+    ///
+    /// The nameserver defaults to `ns1.<origin>` and the hostmaster mailbox to
+    /// `hostmaster.<origin>`; override them to match the parent delegation.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if `origin` is not a valid domain name or is the root.
+    pub fn new(origin: &str) -> Result<Self> {
+        let origin = parse_name(origin).context("Invalid DNS zone origin")?;
+        if origin.is_root() {
+            return Err(anyhow!("DNS zone origin cannot be the root"));
+        }
+
+        let nameservers = vec![Name::from_utf8("ns1")?.append_domain(&origin)?];
+        let hostmaster = Name::from_utf8("hostmaster")?.append_domain(&origin)?;
+
+        // The serial only has to change when the apex records might have, and
+        // those come from configuration, so the start time is enough.
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as u32)
+            .unwrap_or(1);
+
+        Ok(Self {
+            origin,
+            nameservers,
+            hostmaster,
+            serial,
+        })
+    }
+
+    /// Sets the nameservers published in the apex NS records
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the list is empty or a name is invalid.
+    pub fn with_nameservers(mut self, nameservers: &[String]) -> Result<Self> {
+        if nameservers.is_empty() {
+            return Err(anyhow!("DNS zone needs at least one nameserver"));
+        }
+
+        self.nameservers = nameservers
+            .iter()
+            .map(|ns| parse_name(ns).with_context(|| format!("Invalid nameserver '{}'", ns)))
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+
+    /// Sets the responsible mailbox in the SOA record
+    ///
+    /// Accepts either DNS form (`hostmaster.example.com`) or an email address
+    /// (`hostmaster@example.com`).
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the mailbox is not a valid domain name.
+    pub fn with_hostmaster(mut self, hostmaster: &str) -> Result<Self> {
+        let hostmaster = hostmaster.replacen('@', ".", 1);
+        self.hostmaster = parse_name(&hostmaster)
+            .with_context(|| format!("Invalid hostmaster '{}'", hostmaster))?;
+        Ok(self)
+    }
+
+    /// Returns the zone origin as a fully qualified name.
+    pub fn origin(&self) -> &Name {
+        &self.origin
+    }
+
+    /// Classifies a query name against the zone
+    pub fn classify(&self, name: &Name) -> ZoneMatch {
+        if !self.origin.zone_of(name) {
+            return ZoneMatch::Outside;
+        }
+
+        // Count labels directly; `num_labels` discounts a leading `*`.
+        let depth = name.iter().count() - self.origin.iter().count();
+        if depth == 0 {
+            return ZoneMatch::Apex;
+        }
+
+        if name.iter().take(depth).any(|label| label.starts_with(b"_")) {
+            ZoneMatch::Reserved
+        } else {
+            ZoneMatch::Subdomain
+        }
+    }
+
+    /// The apex SOA record, as returned for an SOA query
+    pub fn soa_record(&self) -> Record {
+        Record::from_rdata(self.origin.clone(), ZONE_TTL, RData::SOA(self.soa()))
+    }
+
+    /// The SOA record for the authority section of a negative answer
+    ///
+    /// RFC 2308 §3 caps the negative caching time at the lesser of the SOA
+    /// TTL and its MINIMUM field, so the record carries [`NEGATIVE_TTL`].
+    pub fn negative_soa_record(&self) -> Record {
+        Record::from_rdata(self.origin.clone(), NEGATIVE_TTL, RData::SOA(self.soa()))
+    }
+
+    /// The apex NS records
+    pub fn ns_records(&self) -> Vec<Record> {
+        self.nameservers
+            .iter()
+            .map(|ns| Record::from_rdata(self.origin.clone(), ZONE_TTL, RData::NS(NS(ns.clone()))))
+            .collect()
+    }
+
+    fn soa(&self) -> SOA {
+        SOA::new(
+            self.nameservers[0].clone(),
+            self.hostmaster.clone(),
+            self.serial,
+            SOA_REFRESH,
+            SOA_RETRY,
+            SOA_EXPIRE,
+            NEGATIVE_TTL,
+        )
+    }
+}
+
+/// Parses a name as fully qualified, so `example.com` and `example.com.` match.
+fn parse_name(name: &str) -> Result<Name> {
+    let mut name = Name::from_utf8(name.trim())?;
+    name.set_fqdn(true);
+    Ok(name.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_server::proto::rr::RecordType;
+
+    fn name(s: &str) -> Name {
+        Name::from_utf8(s).unwrap()
+    }
+
+    #[test]
+    fn test_classify_names() {
+        let zone = Zone::new("llm.example.com").unwrap();
+
+        assert_eq!(zone.classify(&name("llm.example.com.")), ZoneMatch::Apex);
+        assert_eq!(zone.classify(&name("LLM.Example.COM.")), ZoneMatch::Apex);
+        assert_eq!(
+            zone.classify(&name("what.is.rust.llm.example.com.")),
+            ZoneMatch::Subdomain
+        );
+        assert_eq!(
+            zone.classify(&name("_dmarc.llm.example.com.")),
+            ZoneMatch::Reserved
+        );
+        assert_eq!(
+            zone.classify(&name("x._tcp.llm.example.com.")),
+            ZoneMatch::Reserved
+        );
+        assert_eq!(zone.classify(&name("example.com.")), ZoneMatch::Outside);
+        assert_eq!(zone.classify(&name("what.is.rust.")), ZoneMatch::Outside);
+        assert_eq!(
+            zone.classify(&name("notllm.example.com.")),
+            ZoneMatch::Outside
+        );
+    }
+
+    #[test]
+    fn test_apex_records() {
+        let zone = Zone::new("llm.example.com.")
+            .unwrap()
+            .with_nameservers(&["ns1.example.net".to_string(), "ns2.example.net".to_string()])
+            .unwrap()
+            .with_hostmaster("admin@example.com")
+            .unwrap();
+
+        let soa = zone.soa_record();
+        assert_eq!(soa.record_type(), RecordType::SOA);
+        assert_eq!(soa.name, name("llm.example.com."));
+        assert_eq!(soa.ttl, ZONE_TTL);
+        match &soa.data {
+            RData::SOA(soa) => {
+                assert_eq!(&soa.mname, &name("ns1.example.net."));
+                assert_eq!(&soa.rname, &name("admin.example.com."));
+                assert_eq!(soa.minimum, NEGATIVE_TTL);
+            }
+            other => panic!("expected SOA, got {:?}", other),
+        }
+
+        assert_eq!(zone.negative_soa_record().ttl, NEGATIVE_TTL);
+
+        let ns = zone.ns_records();
+        assert_eq!(ns.len(), 2);
+        assert!(ns.iter().all(|r| r.record_type() == RecordType::NS));
+    }
+
+    #[test]
+    fn test_invalid_origin_is_an_error() {
+        assert!(Zone::new(".").is_err());
+        assert!(Zone::new("bad..name").is_err());
+    }
+
+    #[test]
+    fn test_empty_nameserver_list_is_an_error() {
+        let zone = Zone::new("llm.example.com").unwrap();
+        assert!(zone.with_nameservers(&[]).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use llm_over_dns::{Config, DeadlineReply, TxtFraming};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
 use tokio::time::timeout;
@@ -52,6 +53,64 @@ pub async fn wait_for_dns_server(port: u16, max_attempts: u32) -> Result<()> {
     )
 }
 
+/// Build a server configuration listening on localhost and pointed at a mock LLM
+#[allow(dead_code)]
+pub fn test_config(port: u16, llm_base_url: String) -> Config {
+    Config {
+        openrouter_api_key: "test_key".to_string(),
+        openrouter_models: vec!["test_model".to_string()],
+        llm_base_url,
+        system_prompt: "Test system prompt".to_string(),
+        dns_address: "127.0.0.1".to_string(),
+        dns_port: port,
+        temperature: None,
+        max_tokens: None,
+        top_p: None,
+        top_k: None,
+        frequency_penalty: None,
+        presence_penalty: None,
+        cache_ttl_seconds: 300,
+        rate_limit_rps: 0.0,
+        rate_limit_burst: 0.0,
+        rate_limit_ipv4_prefix: 32,
+        rate_limit_ipv6_prefix: 64,
+        rate_limit_max_clients: 100_000,
+        max_concurrent_llm_requests: 32,
+        cache_max_entries: 10000,
+        cache_max_bytes: 64 << 20,
+        cache_stale_seconds: 0,
+        cache_prefetch_hits: 0,
+        negative_ttl_bad_request_seconds: 60,
+        negative_ttl_filtered_seconds: 300,
+        negative_ttl_model_seconds: 30,
+        cache_snapshot_path: None,
+        cache_snapshot_interval_seconds: 60,
+        cache_snapshot_max_bytes: 16 << 20,
+        cache_redis_url: None,
+        cache_ttl_rules: None,
+        cache_similarity_threshold: None,
+        tcp_idle_timeout_seconds: 10,
+        tcp_max_connections: 256,
+        tls_cert_path: None,
+        tls_key_path: None,
+        dot_port: 853,
+        http_port: None,
+        admin_token: None,
+        dns_zone: None,
+        dns_zone_nameservers: Vec::new(),
+        dns_zone_hostmaster: None,
+        hyphens_as_spaces: false,
+        upload_max_sessions: 256,
+        upload_max_bytes: 16384,
+        upload_ttl_seconds: 120,
+        ticket_max: 0,
+        ticket_ttl_seconds: 300,
+        response_deadline_ms: 0,
+        deadline_reply: DeadlineReply::ServFail,
+        txt_framing: TxtFraming::Records,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
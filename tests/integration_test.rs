@@ -937,3 +937,233 @@ async fn test_e2e_maximum_dns_response_size() -> Result<()> {
 
     Ok(())
 }
+
+/// Build a wire-format TXT query for the given name
+fn txt_query(id: u16, name: &str) -> Vec<u8> {
+    use hickory_proto::op::{Message, MessageType, OpCode, Query};
+    use hickory_proto::rr::{Name, RecordType};
+
+    let mut msg = Message::new(id, MessageType::Query, OpCode::Query);
+    msg.add_query(Query::query(
+        Name::from_utf8(name).unwrap(),
+        RecordType::TXT,
+    ));
+    msg.to_vec().unwrap()
+}
+
+/// Send a query over DNS-over-TCP framing and read the single response
+async fn tcp_exchange(addr: &str, query: &[u8]) -> Result<hickory_proto::op::Message> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+    let mut frame = (query.len() as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(query);
+    stream.write_all(&frame).await?;
+
+    let length = stream.read_u16().await? as usize;
+    let mut buffer = vec![0u8; length];
+    stream.read_exact(&mut buffer).await?;
+    Ok(hickory_proto::op::Message::from_vec(&buffer)?)
+}
+
+/// Test a truncated UDP answer can be retrieved in full over TCP
+#[tokio::test]
+async fn test_e2e_truncated_udp_answer_retried_over_tcp() -> Result<()> {
+    use llm_over_dns::Server;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let mut llm = mockito::Server::new_async().await;
+    let long_content = "a".repeat(2000);
+    let _mock = llm
+        .mock("POST", mockito::Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(format!(
+            r#"{{"choices": [{{"message": {{"content": "{}"}}}}]}}"#,
+            long_content
+        ))
+        .create_async()
+        .await;
+
+    let port = 25301;
+    let server = Arc::new(Server::new(common::test_config(port, llm.url()))?);
+    let running = server.clone();
+    tokio::spawn(async move { running.start().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let addr = format!("127.0.0.1:{}", port);
+
+    // Over UDP the answer cannot fit a plain-DNS budget, so only TC comes back.
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    socket
+        .send_to(&txt_query(1, "tell.me.a.story."), &addr)
+        .await?;
+    let mut buffer = vec![0u8; 4096];
+    let (n, _) =
+        tokio::time::timeout(Duration::from_secs(5), socket.recv_from(&mut buffer)).await??;
+    let udp_response = hickory_proto::op::Message::from_vec(&buffer[..n])?;
+    assert!(udp_response.metadata.truncation, "UDP answer should set TC");
+    assert!(udp_response.answers.is_empty());
+
+    // The TCP retry carries every chunk.
+    let tcp_response = tcp_exchange(&addr, &txt_query(2, "tell.me.a.story.")).await?;
+    assert!(!tcp_response.metadata.truncation);
+    assert_eq!(tcp_response.metadata.id, 2);
+    assert_eq!(tcp_response.answers.len(), 8);
+
+    server.shutdown()?;
+    Ok(())
+}
+
+/// Test DNS-over-HTTPS POST and GET return the full answer with cache headers
+#[tokio::test]
+async fn test_e2e_dns_over_https_returns_untruncated_answer() -> Result<()> {
+    use data_encoding::BASE64URL_NOPAD;
+    use llm_over_dns::Server;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let mut llm = mockito::Server::new_async().await;
+    let long_content = "a".repeat(2000);
+    let _mock = llm
+        .mock("POST", mockito::Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(format!(
+            r#"{{"choices": [{{"message": {{"content": "{}"}}}}]}}"#,
+            long_content
+        ))
+        .create_async()
+        .await;
+
+    let port = 25302;
+    let http_port = 25303;
+    let mut config = common::test_config(port, llm.url());
+    config.http_port = Some(http_port);
+    let server = Arc::new(Server::new(config)?);
+    let running = server.clone();
+    tokio::spawn(async move { running.start().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let url = format!("http://127.0.0.1:{}/dns-query", http_port);
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(&url)
+        .header("content-type", "application/dns-message")
+        .body(txt_query(0, "tell.me.a.story."))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["cache-control"], "max-age=300");
+    let message = hickory_proto::op::Message::from_vec(&response.bytes().await?)?;
+    assert!(!message.metadata.truncation);
+    assert_eq!(message.answers.len(), 8);
+
+    // The GET form carries the same query base64url-encoded.
+    let encoded = BASE64URL_NOPAD.encode(&txt_query(0, "tell.me.a.story."));
+    let response = client
+        .get(format!("{}?dns={}", url, encoded))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/dns-message"
+    );
+    let message = hickory_proto::op::Message::from_vec(&response.bytes().await?)?;
+    assert_eq!(message.answers.len(), 8);
+
+    server.shutdown()?;
+    Ok(())
+}
+
+/// Test the client reassembles a paged answer fetched over TCP, for both a
+/// plain prompt and one too long for a single query name
+#[tokio::test]
+async fn test_e2e_client_asks_plain_and_uploaded_prompts() -> Result<()> {
+    use llm_over_dns::{DnsClient, Server};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let mut llm = mockito::Server::new_async().await;
+    let long_content: String = (0..6000)
+        .map(|i| char::from(b'a' + (i % 26) as u8))
+        .collect();
+    let _mock = llm
+        .mock("POST", mockito::Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(format!(
+            r#"{{"choices": [{{"message": {{"content": "{}"}}}}]}}"#,
+            long_content
+        ))
+        .create_async()
+        .await;
+
+    let port = 25304;
+    let server = Arc::new(Server::new(common::test_config(port, llm.url()))?);
+    let running = server.clone();
+    tokio::spawn(async move { running.start().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let client =
+        DnsClient::new(format!("127.0.0.1:{}", port).parse()?).with_timeout(Duration::from_secs(5));
+
+    assert_eq!(client.ask("tell me a story").await?, long_content);
+    assert_eq!(
+        client.ask(&"once upon a time ".repeat(40)).await?,
+        long_content
+    );
+
+    server.shutdown()?;
+    Ok(())
+}
+
+/// Test a restarted server answers from the cache snapshot its predecessor
+/// saved on shutdown, without asking the LLM again
+#[tokio::test]
+async fn test_e2e_cache_snapshot_survives_restart() -> Result<()> {
+    use llm_over_dns::Server;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let mut llm = mockito::Server::new_async().await;
+    let mock = llm
+        .mock("POST", mockito::Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"choices": [{"message": {"content": "Rust is fast"}}]}"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let path = std::env::temp_dir().join(format!(
+        "llm-over-dns-e2e-snapshot-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    for port in [25305, 25306] {
+        let mut config = common::test_config(port, llm.url());
+        config.cache_snapshot_path = Some(path.to_string_lossy().into_owned());
+        let server = Arc::new(Server::new(config)?);
+        let running = server.clone();
+        let task = tokio::spawn(async move { running.start().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let addr = format!("127.0.0.1:{}", port);
+        let response = tcp_exchange(&addr, &txt_query(1, "what.is.rust.")).await?;
+        assert_eq!(response.answers.len(), 1, "no answer on port {}", port);
+
+        // The final snapshot is written before start() returns
+        server.shutdown()?;
+        task.await??;
+        assert!(path.exists());
+    }
+
+    mock.assert_async().await;
+    std::fs::remove_file(&path)?;
+    Ok(())
+}